@@ -0,0 +1,65 @@
+//! The AHB and APB prescalers in RCC_CFGR, and the timer clocks that hang off of the APBs.
+
+use crate::Hz;
+
+/// How much faster the timers on an APB run than its PCLK.
+///
+/// The timers run at PCLKx when its APB prescaler is 1, and at twice PCLKx otherwise.  With TIMPRE
+/// set, they run at HCLK for APB prescalers up to 4, and at four times PCLKx beyond that.
+pub const fn timer_multiplier(apb_prescaler: u64, timpre: bool) -> u64 {
+    match (timpre, apb_prescaler) {
+        (false, 1) => 1,
+        (false, _) => 2,
+        (true, 1..=4) => apb_prescaler,
+        (true, _) => 4,
+    }
+}
+
+pub const fn pclk(hclk: Hz, apb_prescaler: u64) -> Hz {
+    hclk.div(apb_prescaler)
+}
+
+pub const fn timer_clock(hclk: Hz, apb_prescaler: u64, timpre: bool) -> Hz {
+    pclk(hclk, apb_prescaler).mul(timer_multiplier(apb_prescaler, timpre))
+}
+
+/// The update event rate of a timer counting up to `arr` with prescaler `psc`.
+pub const fn update_rate(timer_clk: Hz, psc: u64, arr: u64) -> Hz {
+    timer_clk.div(psc + 1).div(arr + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HCLK: Hz = Hz::new(168_000_000);
+
+    #[test]
+    fn f407_at_168mhz() {
+        // RM0090: APB1 at HCLK / 4 and APB2 at HCLK / 2, with their timers at twice that
+        assert!(pclk(HCLK, 4).equals(42_000_000));
+        assert!(timer_clock(HCLK, 4, false).equals(84_000_000));
+        assert!(pclk(HCLK, 2).equals(84_000_000));
+        assert!(timer_clock(HCLK, 2, false).equals(168_000_000));
+        // with no APB prescaler, the timers run at PCLK itself
+        assert!(timer_clock(Hz::new(42_000_000), 1, false).equals(42_000_000));
+    }
+
+    #[test]
+    fn timpre() {
+        // RM0090's TIMPRE table: HCLK for APB prescalers up to 4, then 4 * PCLK
+        assert_eq!(timer_multiplier(1, true), 1);
+        assert_eq!(timer_multiplier(2, true), 2);
+        assert_eq!(timer_multiplier(4, true), 4);
+        assert_eq!(timer_multiplier(8, true), 4);
+        assert!(timer_clock(HCLK, 4, true).equals(168_000_000));
+        assert!(timer_clock(HCLK, 8, true).equals(84_000_000));
+    }
+
+    #[test]
+    fn update_rate_divides_by_one_more() {
+        // the DAC's sample clock: TIM4 at 84MHz with PSC = 0, ARR = 7
+        assert!(update_rate(Hz::new(84_000_000), 0, 7).equals(10_500_000));
+        assert!(update_rate(Hz::new(84_000_000), 83, 999).equals(1_000));
+    }
+}
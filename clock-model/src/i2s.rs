@@ -0,0 +1,72 @@
+//! The I2S prescaler in SPI_I2SPR, which divides the I2S kernel clock down to the sample rate.
+
+use crate::Hz;
+
+/// The ratio between the I2S kernel clock and the sample rate, with an I2SDIV/ODD divider of 1.
+///
+/// With MCKOE set, the master clock is always 256 * Fs, regardless of the channel length.
+pub const fn clocks_per_sample(mckoe: bool, channel_bits: u64) -> u64 {
+    if mckoe {
+        256
+    } else {
+        2 * channel_bits
+    }
+}
+
+/// The sample rate produced by `i2s_clk` with the given SPI_I2SPR and CHLEN settings.
+pub const fn sample_rate(
+    i2s_clk: Hz,
+    i2sdiv: u64,
+    odd: bool,
+    mckoe: bool,
+    channel_bits: u64,
+) -> Hz {
+    i2s_clk
+        .div(2 * i2sdiv + odd as u64)
+        .div(clocks_per_sample(mckoe, channel_bits))
+}
+
+/// The MCK output, which only runs when MCKOE is set.
+pub const fn master_clock(i2s_clk: Hz, i2sdiv: u64, odd: bool) -> Hz {
+    i2s_clk.div(2 * i2sdiv + odd as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rows of RM0090's audio frequency precision table, from a 1MHz PLLI2S VCO input
+    #[test]
+    fn mclk_enabled_48khz() {
+        // PLLI2SN = 258, PLLI2SR = 3, I2SDIV = 3, ODD = 1: 47.991kHz
+        let i2s_clk = Hz::new(86_000_000);
+        let fs = sample_rate(i2s_clk, 3, true, true, 16);
+        assert_eq!(fs.whole(), 47_991);
+        assert!(fs.within_ppm(48_000, 187));
+        // MCK is 256 * Fs
+        assert_eq!(master_clock(i2s_clk, 3, true).div(256), fs);
+    }
+
+    #[test]
+    fn mclk_enabled_44_1khz() {
+        // PLLI2SN = 271, PLLI2SR = 2, I2SDIV = 6, ODD = 0: 44.108kHz
+        let fs = sample_rate(Hz::new(135_500_000), 6, false, true, 16);
+        assert_eq!(fs.whole(), 44_108);
+    }
+
+    #[test]
+    fn mclk_disabled_48khz_is_exact() {
+        // PLLI2SN = 192, PLLI2SR = 5, I2SDIV = 12, ODD = 1 for a 16-bit channel, and
+        // PLLI2SN = 384 for a 32-bit one
+        assert!(sample_rate(Hz::new(38_400_000), 12, true, false, 16).equals(48_000));
+        assert!(sample_rate(Hz::new(76_800_000), 12, true, false, 32).equals(48_000));
+    }
+
+    #[test]
+    fn clocks_per_sample_by_frame() {
+        assert_eq!(clocks_per_sample(true, 16), 256);
+        assert_eq!(clocks_per_sample(true, 32), 256);
+        assert_eq!(clocks_per_sample(false, 16), 32);
+        assert_eq!(clocks_per_sample(false, 32), 64);
+    }
+}
@@ -0,0 +1,110 @@
+//! The STM32F4 clock tree in integer `const fn`s, so that firmware can check its clock settings at
+//! compile time and the host tools can share the same datasheet limits.
+
+#![no_std]
+
+pub mod bus;
+pub mod i2s;
+pub mod limits;
+pub mod pll;
+
+pub use limits::{Limits, Range};
+pub use pll::{Pll, PllI2s};
+
+/// A frequency, kept as an exact fraction of Hz since dividers rarely come out even.
+///
+/// Comparisons cross-multiply in 128 bits, so any chain of PLL and peripheral dividers fits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Hz {
+    pub numer: u64,
+    pub denom: u64,
+}
+
+impl Hz {
+    pub const fn new(hz: u64) -> Self {
+        Hz {
+            numer: hz,
+            denom: 1,
+        }
+    }
+
+    pub const fn mul(self, factor: u64) -> Self {
+        Hz {
+            numer: self.numer * factor,
+            denom: self.denom,
+        }
+    }
+
+    pub const fn div(self, divider: u64) -> Self {
+        Hz {
+            numer: self.numer,
+            denom: self.denom * divider,
+        }
+    }
+
+    /// The frequency rounded down to a whole number of Hz.
+    pub const fn whole(self) -> u64 {
+        self.numer / self.denom
+    }
+
+    pub const fn is_whole(self) -> bool {
+        self.numer % self.denom == 0
+    }
+
+    /// Whether this is exactly `hz`.
+    pub const fn equals(self, hz: u64) -> bool {
+        self.numer as u128 == hz as u128 * self.denom as u128
+    }
+
+    pub const fn at_least(self, hz: u64) -> bool {
+        self.numer as u128 >= hz as u128 * self.denom as u128
+    }
+
+    pub const fn at_most(self, hz: u64) -> bool {
+        self.numer as u128 <= hz as u128 * self.denom as u128
+    }
+
+    /// Whether this is within `ppm` parts-per-million of `hz`.  From 1,000,000ppm up, the lower
+    /// bound stays at 0Hz.
+    pub const fn within_ppm(self, hz: u64, ppm: u64) -> bool {
+        let actual = self.numer as u128 * 1_000_000;
+        let low = hz as u128 * (1_000_000u128.saturating_sub(ppm as u128)) * self.denom as u128;
+        let high = hz as u128 * (1_000_000 + ppm as u128) * self.denom as u128;
+        actual >= low && actual <= high
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hz;
+
+    #[test]
+    fn fractions_compare_exactly() {
+        // 8MHz / 3 * 3 is 8MHz again, not a rounded-down 7999999Hz
+        let third = Hz::new(8_000_000).div(3);
+        assert!(!third.is_whole());
+        assert_eq!(third.whole(), 2_666_666);
+        assert!(third.mul(3).equals(8_000_000));
+        assert!(third.mul(3).is_whole());
+        assert!(third.at_least(2_666_666) && !third.at_least(2_666_667));
+        assert!(third.at_most(2_666_667) && !third.at_most(2_666_666));
+    }
+
+    #[test]
+    fn ppm_bounds_are_inclusive() {
+        // 48,012kHz is 250ppm fast
+        let usb = Hz::new(48_012_000);
+        assert!(usb.within_ppm(48_000_000, 250));
+        assert!(!usb.within_ppm(48_000_000, 249));
+        assert!(Hz::new(47_988_000).within_ppm(48_000_000, 250));
+    }
+
+    #[test]
+    fn huge_tolerances_do_not_underflow() {
+        assert!(Hz::new(1).within_ppm(48_000_000, 1_000_000));
+        assert!(Hz::new(0).within_ppm(48_000_000, 2_000_000));
+        assert!(Hz::new(144_000_000).within_ppm(48_000_000, 2_000_000));
+        assert!(!Hz::new(144_000_001).within_ppm(48_000_000, 2_000_000));
+        assert!(Hz::new(1).within_ppm(1, u64::MAX));
+    }
+}
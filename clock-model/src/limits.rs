@@ -0,0 +1,95 @@
+//! Datasheet limits on the PLL and bus clocks of each supported part.
+
+use crate::Hz;
+
+/// An inclusive range of frequencies, in Hz.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Range {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Range {
+    pub const fn contains(self, clock: Hz) -> bool {
+        clock.at_least(self.min) && clock.at_most(self.max)
+    }
+}
+
+const fn mhz(min: u64, max: u64) -> Range {
+    Range {
+        min: min * 1_000_000,
+        max: max * 1_000_000,
+    }
+}
+
+pub struct Limits {
+    pub vco_input: Range,
+    pub vco_output: Range,
+    /// The range of each PLL's P and R outputs, whose upper end is the fastest allowed SYSCLK
+    pub pll_out: Range,
+    pub pll48_out: Range,
+    /// The range of the PLLI2S outputs that feed the I2S peripherals
+    pub plli2s_out: Range,
+    pub pclk1_max: u64,
+    pub pclk2_max: u64,
+}
+
+pub const F401: Limits = Limits {
+    vco_input: mhz(1, 2),
+    // RM0368 raises the bottom of the VCO range compared to the F407
+    vco_output: mhz(192, 432),
+    pll_out: mhz(24, 84),
+    pll48_out: mhz(48, 75),
+    plli2s_out: mhz(0, 192),
+    pclk1_max: 42_000_000,
+    pclk2_max: 84_000_000,
+};
+
+pub const F411: Limits = Limits {
+    vco_input: mhz(1, 2),
+    vco_output: mhz(100, 432),
+    pll_out: mhz(24, 100),
+    pll48_out: mhz(48, 75),
+    plli2s_out: mhz(0, 192),
+    pclk1_max: 50_000_000,
+    pclk2_max: 100_000_000,
+};
+
+pub const F407: Limits = Limits {
+    // VCO input must be between 1MHz and 2MHz, per documentation for the PLLM bits of RCC_PLLCFGR
+    vco_input: mhz(1, 2),
+    // VCO output must be between 100MHz and 432MHz, per documentation for the PLLN bits of
+    // RCC_PLLCFGR
+    vco_output: mhz(100, 432),
+    // PLL_OUT must be between 24MHz and 168MHz, per "PLL Characteristics" in the STM32F407
+    // datasheet
+    pll_out: mhz(24, 168),
+    // PLL48_OUT must be between 48MHz and 75MHz, per the same table
+    pll48_out: mhz(48, 75),
+    // PLLI2S_OUT has no minimum and a 192MHz maximum, per "PLLI2S characteristics"
+    plli2s_out: mhz(0, 192),
+    // APB1 and APB2 may run at up to 42MHz and 84MHz, per "Clocks" in RM0090
+    pclk1_max: 42_000_000,
+    pclk2_max: 84_000_000,
+};
+
+pub const F427: Limits = Limits {
+    vco_input: mhz(1, 2),
+    vco_output: mhz(100, 432),
+    // 180MHz needs over-drive mode; without it the limit is the same as the F407
+    pll_out: mhz(24, 180),
+    pll48_out: mhz(48, 75),
+    plli2s_out: mhz(0, 192),
+    pclk1_max: 45_000_000,
+    pclk2_max: 90_000_000,
+};
+
+pub const F446: Limits = Limits {
+    vco_input: mhz(1, 2),
+    vco_output: mhz(100, 432),
+    pll_out: mhz(24, 180),
+    pll48_out: mhz(48, 75),
+    plli2s_out: mhz(0, 192),
+    pclk1_max: 45_000_000,
+    pclk2_max: 90_000_000,
+};
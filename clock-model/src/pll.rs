@@ -0,0 +1,129 @@
+//! The main PLL and PLLI2S, as programmed in RCC_PLLCFGR and RCC_PLLI2SCFGR.
+
+use crate::{Hz, Limits};
+
+/// The main PLL's dividers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Pll {
+    pub m: u64,
+    pub n: u64,
+    pub p: u64,
+    pub q: u64,
+}
+
+impl Pll {
+    pub const fn vco_input(&self, source: Hz) -> Hz {
+        source.div(self.m)
+    }
+
+    pub const fn vco_output(&self, source: Hz) -> Hz {
+        self.vco_input(source).mul(self.n)
+    }
+
+    pub const fn sysclk(&self, source: Hz) -> Hz {
+        self.vco_output(source).div(self.p)
+    }
+
+    pub const fn pll48_clk(&self, source: Hz) -> Hz {
+        self.vco_output(source).div(self.q)
+    }
+
+    /// Whether every PLL clock is within `limits`.
+    pub const fn is_valid(&self, source: Hz, limits: &Limits) -> bool {
+        limits.vco_input.contains(self.vco_input(source))
+            && limits.vco_output.contains(self.vco_output(source))
+            && limits.pll_out.contains(self.sysclk(source))
+            && limits.pll48_out.contains(self.pll48_clk(source))
+    }
+}
+
+/// The dividers of PLLI2S.  On parts without PLLI2SM, `m` has to be the main PLL's PLLM.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PllI2s {
+    pub m: u64,
+    pub n: u64,
+    pub r: u64,
+}
+
+impl PllI2s {
+    pub const fn vco_input(&self, source: Hz) -> Hz {
+        source.div(self.m)
+    }
+
+    pub const fn vco_output(&self, source: Hz) -> Hz {
+        self.vco_input(source).mul(self.n)
+    }
+
+    pub const fn i2s_clk(&self, source: Hz) -> Hz {
+        self.vco_output(source).div(self.r)
+    }
+
+    /// Whether every PLLI2S clock is within `limits`.
+    pub const fn is_valid(&self, source: Hz, limits: &Limits) -> bool {
+        limits.vco_input.contains(self.vco_input(source))
+            && limits.vco_output.contains(self.vco_output(source))
+            && limits.plli2s_out.contains(self.i2s_clk(source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::{F401, F407};
+
+    const HSE: Hz = Hz::new(8_000_000);
+
+    #[test]
+    fn main_pll_168mhz_from_8mhz() {
+        // the STM32F4DISCOVERY setting: M = 8, N = 336, P = 2, Q = 7
+        let pll = Pll {
+            m: 8,
+            n: 336,
+            p: 2,
+            q: 7,
+        };
+        assert!(pll.vco_input(HSE).equals(1_000_000));
+        assert!(pll.vco_output(HSE).equals(336_000_000));
+        assert!(pll.sysclk(HSE).equals(168_000_000));
+        assert!(pll.pll48_clk(HSE).equals(48_000_000));
+        assert!(pll.is_valid(HSE, &F407));
+        // the F401 tops out at 84MHz
+        assert!(!pll.is_valid(HSE, &F401));
+    }
+
+    #[test]
+    fn main_pll_limits() {
+        // a 4MHz VCO input is past the 2MHz limit
+        let fast_input = Pll {
+            m: 2,
+            n: 84,
+            p: 2,
+            q: 7,
+        };
+        assert!(fast_input.sysclk(HSE).equals(168_000_000));
+        assert!(!fast_input.is_valid(HSE, &F407));
+
+        // PLL48CLK below 48MHz
+        let slow_usb = Pll {
+            m: 8,
+            n: 336,
+            p: 2,
+            q: 8,
+        };
+        assert!(slow_usb.pll48_clk(HSE).equals(42_000_000));
+        assert!(!slow_usb.is_valid(HSE, &F407));
+    }
+
+    #[test]
+    fn plli2s_from_rm0090_audio_table() {
+        // RM0090's audio frequency precision table, with a 1MHz VCO input
+        let pll = PllI2s { m: 8, n: 258, r: 3 };
+        assert!(pll.vco_output(HSE).equals(258_000_000));
+        assert!(pll.i2s_clk(HSE).equals(86_000_000));
+        assert!(pll.is_valid(HSE, &F407));
+
+        // 432MHz is the top of the VCO range; 433MHz is past it
+        assert!(PllI2s { m: 8, n: 432, r: 3 }.is_valid(HSE, &F407));
+        assert!(!PllI2s { m: 8, n: 433, r: 3 }.is_valid(HSE, &F407));
+    }
+}
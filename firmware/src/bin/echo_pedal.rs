@@ -9,12 +9,48 @@ use stm32f4xx_hal::spi::{self, NoMiso};
 
 use stm32f4xx_hal::stm32;
 
-use stm32::spi1::i2scfgr;
-
-use wm8731::WM8731;
-
-// approximate!  This will actually run a fraction of a percent slow, due to I2S clocking
-// constraints.
+use stm32f4_experimentation::codec::Control;
+use stm32f4_experimentation::i2s::{
+    self, ChannelLength, ClockPolarity, DataLength, Prescaler, Standard,
+};
+use stm32f4_experimentation::HSE;
+
+// the HAL divides the HSE clock down to 2MHz for a 168MHz SYSCLK, and PLLI2S shares that divider
+const PLL: clock_model::Pll = clock_model::Pll {
+    m: 4,
+    n: 168,
+    p: 2,
+    q: 7,
+};
+static_assertions::const_assert!(PLL.is_valid(HSE, &clock_model::limits::F407));
+static_assertions::const_assert!(PLL.sysclk(HSE).equals(168_000_000));
+// SPI3, which sets up the codec, runs from APB1, which the HAL divides by 4 to stay under 42MHz
+const PCLK1: clock_model::Hz = clock_model::bus::pclk(PLL.sysclk(HSE), 4);
+static_assertions::const_assert!(PCLK1.equals(42_000_000));
+
+// RM0090's 48ksps row with MCK on, N = 258 and R = 3 from a 1MHz VCO input, halved for the 2MHz
+const PLLI2S: clock_model::PllI2s = clock_model::PllI2s {
+    m: PLL.m,
+    n: 258 / 2,
+    r: 3,
+};
+static_assertions::const_assert!(PLLI2S.is_valid(HSE, &clock_model::limits::F407));
+// from that same row, I2SDIV = 3, ODD = 1
+const I2SDIV: u64 = 3;
+const ODD: bool = true;
+// 48ksps isn't reachable exactly from 2MHz with MCK on; this is 47991.07sps, 186ppm slow
+static_assertions::const_assert!(clock_model::i2s::sample_rate(
+    PLLI2S.i2s_clk(HSE),
+    I2SDIV,
+    ODD,
+    true,
+    32
+)
+.within_ppm(48_000, 187));
+static_assertions::const_assert!(
+    clock_model::i2s::sample_rate(PLLI2S.i2s_clk(HSE), I2SDIV, ODD, true, 32).whole() == 47_991
+);
+// the buffers are sized as if it were exact
 const SAMPLE_RATE: usize = 48_000;
 
 #[entry]
@@ -23,12 +59,7 @@ fn main() -> ! {
     let mut core_peripherals = cortex_m::Peripherals::take().unwrap();
 
     let rcc = peripherals.RCC.constrain();
-    let clocks = rcc
-        .cfgr
-        .use_hse(8.mhz())
-        .sysclk(168.mhz())
-        .i2s_clk(61_440.khz())
-        .freeze();
+    let clocks = rcc.cfgr.use_hse(8.mhz()).sysclk(168.mhz()).freeze();
 
     let _itm = &mut core_peripherals.ITM.stim[0];
 
@@ -45,15 +76,14 @@ fn main() -> ! {
     let control_sck = portc.pc10.into_alternate_af6();
     let control_mosi = portc.pc12.into_alternate_af6();
     let _control_nss = porta.pa4.into_alternate_af6();
-    let mut control_csb = portc.pc11.into_push_pull_output();
-
-    control_csb.set_high().unwrap();
+    let control_csb = portc.pc11.into_push_pull_output();
 
-    // enable the DAC peripheral
+    i2s::enable_spi2();
+    i2s::start_plli2s(&PLLI2S);
+    // enable the DMA controllers and the ADC
     unsafe {
         let rcc = &*stm32::RCC::ptr();
 
-        rcc.apb1enr.modify(|_r, w| w.spi2en().set_bit());
         rcc.ahb1enr.modify(|_r, w| {
             w.dma1en().set_bit();
             w.dma2en().set_bit()
@@ -62,34 +92,33 @@ fn main() -> ! {
     }
 
     let audio_rx = peripherals.I2S2EXT;
-    audio_rx.i2scfgr.write(|w| {
-        w.i2smod().set_bit();
-        w.i2scfg().slave_rx();
-        w.i2sstd().msb();
-        w.ckpol().clear_bit();
-        w.datlen().twenty_four_bit();
-        w.chlen().thirty_two_bit()
-    });
-    audio_rx.i2scfgr.modify(|_r, w| w.i2se().set_bit());
+    i2s::Config {
+        mode: i2s::Mode::SLAVERX,
+        standard: Standard::MSB,
+        polarity: ClockPolarity::IDLELOW,
+        data_length: DataLength::TWENTYFOURBIT,
+        channel_length: ChannelLength::THIRTYTWOBIT,
+        prescaler: None,
+    }
+    .apply(&audio_rx);
 
     let audio_tx = peripherals.SPI2;
-    audio_tx.i2scfgr.write(|w| {
-        w.i2smod().set_bit();
-        w.i2scfg().variant(i2scfgr::I2SCFG_A::MASTERTX);
-        w.i2sstd().variant(i2scfgr::I2SSTD_A::MSB);
-        w.ckpol().clear_bit();
-        w.datlen().variant(i2scfgr::DATLEN_A::TWENTYFOURBIT);
-        w.chlen().variant(i2scfgr::CHLEN_A::THIRTYTWOBIT)
-    });
-    // 61.440MHz / (2 * 2 + 1) = 12.288MHz MCK
-    // 12.288MHz / 4 [fixed in hardware] = 3.072MHz bit clock
-    // 3.072MHz / (2 channels * 32 bits per sample) = 48k samples per sec
-    audio_tx.i2spr.write(|w| {
-        w.mckoe().set_bit();
-        unsafe { w.i2sdiv().bits(2) };
-        w.odd().set_bit()
-    });
-    audio_tx.i2scfgr.modify(|_r, w| w.i2se().set_bit());
+    i2s::Config {
+        mode: i2s::Mode::MASTERTX,
+        standard: Standard::MSB,
+        polarity: ClockPolarity::IDLELOW,
+        data_length: DataLength::TWENTYFOURBIT,
+        channel_length: ChannelLength::THIRTYTWOBIT,
+        // 86MHz / (2 * 3 + 1) = 12.286MHz MCK
+        // 12.286MHz / 4 [fixed in hardware] = 3.071MHz bit clock
+        // 3.071MHz / (2 channels * 32 bits per sample) = 47.991k samples per sec
+        prescaler: Some(Prescaler {
+            i2sdiv: I2SDIV as u8,
+            odd: ODD,
+            mckoe: true,
+        }),
+    }
+    .apply(&audio_tx);
 
     let control_spi = stm32f4xx_hal::spi::Spi::spi3(
         peripherals.SPI3,
@@ -101,75 +130,12 @@ fn main() -> ! {
         200.khz().into(),
         clocks,
     );
-    let mut control = Control {
-        spi: control_spi,
-        not_cs: control_csb,
-        delay: stm32f4xx_hal::delay::Delay::new(core_peripherals.SYST, clocks),
-    };
-
-    fn final_power_settings(w: &mut wm8731::power_down::PowerDown) {
-        w.power_off().power_on();
-        w.clock_output().power_off();
-        w.oscillator().power_off();
-        w.output().power_on();
-        w.dac().power_on();
-        w.adc().power_on();
-        w.mic().power_off();
-        w.line_input().power_on();
-    }
-
-    control.set_register(WM8731::reset());
-    control.set_register(WM8731::power_down(|w| {
-        final_power_settings(w);
-        w.output().power_off();
-    }));
-
-    // disable input mute, set to 0dB gain
-    control.set_register(WM8731::left_line_in(|w| {
-        w.both().disable();
-        w.mute().disable();
-        w.volume().nearest_dB(0);
-    }));
-
-    // sidetone off; DAC selected; bypass off; line input selected; mic muted; mic boost off
-    control.set_register(WM8731::analog_audio_path(|w| {
-        w.sidetone().disable();
-        w.dac_select().select();
-        w.bypass().disable();
-        w.input_select().line_input();
-        w.mute_mic().enable();
-        w.mic_boost().disable();
-    }));
-
-    // disable DAC mute, deemphasis for 48k
-    control.set_register(WM8731::digital_audio_path(|w| {
-        w.dac_mut();
-        w.deemphasis().frequency_48();
-    }));
-
-    // nothing inverted, slave, 24-bits, MSB format
-    control.set_register(WM8731::digital_audio_interface_format(|w| {
-        w.bit_clock_invert().no_invert();
-        w.master_slave().slave();
-        w.left_right_dac_clock_swap().right_channel_dac_data_right();
-        w.left_right_phase().data_when_daclrc_low();
-        w.bit_length().bits_24();
-        w.format().left_justified();
-    }));
-
-    // no clock division, normal mode, 48k
-    control.set_register(WM8731::sampling(|w| {
-        w.core_clock_divider_select().normal();
-        w.base_oversampling_rate().normal_256();
-        w.sample_rate().adc_48();
-        w.usb_normal().normal();
-    }));
-
-    // set active
-    control.set_register(WM8731::active().active());
-
-    // enable output
-    control.set_register(WM8731::power_down(final_power_settings));
+    let mut control = Control::new(
+        control_spi,
+        control_csb,
+        stm32f4xx_hal::delay::Delay::new(core_peripherals.SYST, clocks),
+    );
+    control.start_line_in_out();
 
     // buffer that can hold a half second of data
     let mut top_buffer = [0u16; SAMPLE_RATE / 2];
@@ -250,36 +216,3 @@ fn main() -> ! {
         }
     }
 }
-
-struct Control<SPI, GPIO, DELAY> {
-    spi: SPI,
-    not_cs: GPIO,
-    delay: DELAY,
-}
-
-impl<SPI, GPIO, DELAY> Control<SPI, GPIO, DELAY>
-where
-    SPI: embedded_hal::blocking::spi::Write<u8>,
-    SPI::Error: core::fmt::Debug,
-    GPIO: embedded_hal::digital::v2::OutputPin,
-    GPIO::Error: core::fmt::Debug,
-    DELAY: embedded_hal::blocking::delay::DelayUs<u8>,
-{
-    fn set_register(&mut self, register: wm8731::Register) {
-        self.not_cs.set_low().unwrap();
-
-        embedded_hal::blocking::spi::Write::write(
-            &mut self.spi,
-            &[
-                (register.address << 1) | ((register.value & 0x100) >> 8) as u8,
-                (register.value & 0xff) as u8,
-            ],
-        )
-        .expect("SPI write failed");
-
-        self.not_cs.set_high().unwrap();
-
-        // t_CSH is minimum 20ns per the datasheet, so 1µs should be fine
-        self.delay.delay_us(1);
-    }
-}
@@ -4,15 +4,15 @@
 
 use panic_itm as _;
 
-use cortex_m::iprintln;
 use stm32f407g_disc::entry;
 
+use stm32f4_experimentation::itm;
+
 #[entry]
 fn main() -> ! {
-    let peripherals = cortex_m::Peripherals::take().unwrap();
-    let mut itm = peripherals.ITM;
+    let mut peripherals = cortex_m::Peripherals::take().unwrap();
 
-    iprintln!(&mut itm.stim[0], "Hello, world!");
+    itm::println(&mut peripherals.ITM, format_args!("Hello, world!"));
 
     loop {}
 }
@@ -5,11 +5,37 @@ use panic_itm as _;
 
 use stm32f407g_disc::entry;
 use stm32f4xx_hal::prelude::*;
-use stm32f4xx_hal::stm32::spi1::i2scfgr::{CHLEN_A, CKPOL_A, DATLEN_A, I2SCFG_A};
-use usb_device::prelude::*;
+
+use stm32f4_experimentation::i2s::{
+    self, ChannelLength, ClockPolarity, DataLength, Prescaler, Standard,
+};
+use stm32f4_experimentation::{itm, usb, HSE};
 
 static mut USB_BUF: [u32; 128] = [0; 128];
 
+// from the table in the SPI peripheral documentation, for 48ksps, 16bits/sample, N = 192MHz, R = 5.
+// The HAL divides the HSE clock down to 2MHz, and 48ksps * 32bits is more than I can shovel off of
+// USB.  Drop that down to 24 bits per sample, or 3/4 * 192 = 144MHz.
+const PLLI2S: clock_model::PllI2s = clock_model::PllI2s {
+    m: 4,
+    n: 144 / 2,
+    r: 5,
+};
+// That's still within the PLL range, yay!
+static_assertions::const_assert!(PLLI2S.is_valid(HSE, &clock_model::limits::F407));
+// from that same table, I2SDIV = 12, ODD = 1
+const I2SDIV: u64 = 12;
+const ODD: bool = true;
+// ... which makes for 3/4 of the 48ksps in 16-bit frames
+static_assertions::const_assert!(clock_model::i2s::sample_rate(
+    PLLI2S.i2s_clk(HSE),
+    I2SDIV,
+    ODD,
+    false,
+    16
+)
+.equals(36_000));
+
 #[entry]
 fn main() -> ! {
     let peripherals = stm32f407g_disc::Peripherals::take().unwrap();
@@ -23,64 +49,48 @@ fn main() -> ! {
         .require_pll48clk()
         .freeze();
 
-    cortex_m::iprintln!(
-        &mut core_peripherals.ITM.stim[0],
-        "pclk1 is {}",
-        clocks.pclk1().0
+    itm::println(
+        &mut core_peripherals.ITM,
+        format_args!("pclk1 is {}", clocks.pclk1().0),
     );
 
     let porta = peripherals.GPIOA.split();
 
-    let usb = stm32f4xx_hal::otg_fs::USB {
-        hclk: clocks.hclk(),
-        usb_global: peripherals.OTG_FS_GLOBAL,
-        usb_device: peripherals.OTG_FS_DEVICE,
-        usb_pwrclk: peripherals.OTG_FS_PWRCLK,
-        pin_dp: porta.pa12.into_alternate_af10(),
-        pin_dm: porta.pa11.into_alternate_af10(),
-    };
-
-    let bus = stm32f4xx_hal::otg_fs::UsbBus::new(usb, unsafe { &mut USB_BUF });
+    let bus = usb::bus(
+        &clocks,
+        usb::Peripherals {
+            global: peripherals.OTG_FS_GLOBAL,
+            device: peripherals.OTG_FS_DEVICE,
+            pwrclk: peripherals.OTG_FS_PWRCLK,
+        },
+        porta.pa11,
+        porta.pa12,
+        unsafe { &mut USB_BUF },
+    );
     let mut serial = usbd_serial::SerialPort::new(&bus);
-    let mut device = UsbDeviceBuilder::new(&bus, UsbVidPid(0x1337, 0xd00d))
-        .manufacturer("Matt Mullins")
-        .product("STM32F4 experiment")
-        .build();
+    let mut device = usb::device(&bus);
 
     // set-up SPI
     let portb = peripherals.GPIOB.split();
     let portc = peripherals.GPIOC.split();
     portb.pb10.into_alternate_af5();
     portc.pc3.into_alternate_af5();
-    unsafe {
-        let rcc = &*stm32f4xx_hal::stm32::RCC::ptr();
-        rcc.apb1enr.modify(|_r, w| w.spi2en().set_bit());
-        // from the table in the SPI peripheral documentation, for 48ksps, 16bits/sample,
-        // N = 192MHz, R = 5
-        rcc.plli2scfgr.modify(|_r, w| {
-            // the HSE clock is divided to 2MHz.
-            // and 48ksps * 32bits is more than I can shovel off of USB.  Drop that down to 24 bits
-            // per sample, or 3/4 * 192 = 144MHz.  That's still within the PLL range, yay!
-            w.plli2sn().bits(144 / 2);
-            w.plli2sr().bits(5)
-        });
-        rcc.cr.modify(|_r, w| w.plli2son().set_bit());
-        while !rcc.cr.read().plli2srdy().bit() {}
-    }
+    i2s::enable_spi2();
+    i2s::start_plli2s(&PLLI2S);
     let spi = peripherals.SPI2;
-    spi.i2scfgr.write(|w| {
-        w.i2smod().set_bit();
-        w.i2scfg().variant(I2SCFG_A::MASTERRX);
-        w.ckpol().variant(CKPOL_A::IDLEHIGH);
-        w.datlen().variant(DATLEN_A::SIXTEENBIT);
-        w.chlen().variant(CHLEN_A::SIXTEENBIT)
-    });
-    // from that same table, I2SDIV = 12, ODD = 1
-    spi.i2spr.write(|w| {
-        unsafe { w.i2sdiv().bits(12) };
-        w.odd().set_bit()
-    });
-    spi.i2scfgr.modify(|_r, w| w.i2se().set_bit());
+    i2s::Config {
+        mode: i2s::Mode::MASTERRX,
+        standard: Standard::PHILIPS,
+        polarity: ClockPolarity::IDLEHIGH,
+        data_length: DataLength::SIXTEENBIT,
+        channel_length: ChannelLength::SIXTEENBIT,
+        prescaler: Some(Prescaler {
+            i2sdiv: I2SDIV as u8,
+            odd: ODD,
+            mckoe: false,
+        }),
+    }
+    .apply(&spi);
 
     let mut send = false;
 
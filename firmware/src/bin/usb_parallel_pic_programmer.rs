@@ -9,7 +9,8 @@ use stm32f4xx_hal::prelude::*;
 use usb_device::bus::{UsbBus, UsbBusAllocator};
 use usb_device::class::UsbClass;
 use usb_device::class_prelude::*;
-use usb_device::prelude::*;
+
+use stm32f4_experimentation::usb;
 
 static mut USB_BUF: [u32; 128] = [0; 128];
 
@@ -28,21 +29,19 @@ fn main() -> ! {
 
     let porta = peripherals.GPIOA.split();
 
-    let usb = stm32f4xx_hal::otg_fs::USB {
-        hclk: clocks.hclk(),
-        usb_global: peripherals.OTG_FS_GLOBAL,
-        usb_device: peripherals.OTG_FS_DEVICE,
-        usb_pwrclk: peripherals.OTG_FS_PWRCLK,
-        pin_dp: porta.pa12.into_alternate_af10(),
-        pin_dm: porta.pa11.into_alternate_af10(),
-    };
-
-    let bus = stm32f4xx_hal::otg_fs::UsbBus::new(usb, unsafe { &mut USB_BUF });
+    let bus = usb::bus(
+        &clocks,
+        usb::Peripherals {
+            global: peripherals.OTG_FS_GLOBAL,
+            device: peripherals.OTG_FS_DEVICE,
+            pwrclk: peripherals.OTG_FS_PWRCLK,
+        },
+        porta.pa11,
+        porta.pa12,
+        unsafe { &mut USB_BUF },
+    );
     let mut parallel = ParallelPort::new(&bus);
-    let mut device = UsbDeviceBuilder::new(&bus, UsbVidPid(0x1337, 0xd00d))
-        .manufacturer("Matt Mullins")
-        .product("STM32F4 experiment")
-        .build();
+    let mut device = usb::device(&bus);
 
     loop {
         device.poll(&mut [&mut parallel]);
@@ -0,0 +1,111 @@
+//! The WM8731 audio codec's control interface, over 3-wire SPI.
+
+use wm8731::WM8731;
+
+pub struct Control<SPI, GPIO, DELAY> {
+    spi: SPI,
+    not_cs: GPIO,
+    delay: DELAY,
+}
+
+impl<SPI, GPIO, DELAY> Control<SPI, GPIO, DELAY>
+where
+    SPI: embedded_hal::blocking::spi::Write<u8>,
+    SPI::Error: core::fmt::Debug,
+    GPIO: embedded_hal::digital::v2::OutputPin,
+    GPIO::Error: core::fmt::Debug,
+    DELAY: embedded_hal::blocking::delay::DelayUs<u8>,
+{
+    /// `spi` has to idle high and capture on the second transition, and `not_cs` is the codec's
+    /// CSB pin.
+    pub fn new(spi: SPI, mut not_cs: GPIO, delay: DELAY) -> Self {
+        not_cs.set_high().unwrap();
+        Self { spi, not_cs, delay }
+    }
+
+    pub fn set_register(&mut self, register: wm8731::Register) {
+        self.not_cs.set_low().unwrap();
+
+        embedded_hal::blocking::spi::Write::write(
+            &mut self.spi,
+            &[
+                (register.address << 1) | ((register.value & 0x100) >> 8) as u8,
+                (register.value & 0xff) as u8,
+            ],
+        )
+        .expect("SPI write failed");
+
+        self.not_cs.set_high().unwrap();
+
+        // t_CSH is minimum 20ns per the datasheet, so 1µs should be fine
+        self.delay.delay_us(1);
+    }
+
+    /// Resets the codec and starts it as a 48kHz I2S slave, with 24-bit left-justified data, the
+    /// line input going to the ADC and the DAC going to the line output.
+    pub fn start_line_in_out(&mut self) {
+        fn final_power_settings(w: &mut wm8731::power_down::PowerDown) {
+            w.power_off().power_on();
+            w.clock_output().power_off();
+            w.oscillator().power_off();
+            w.output().power_on();
+            w.dac().power_on();
+            w.adc().power_on();
+            w.mic().power_off();
+            w.line_input().power_on();
+        }
+
+        self.set_register(WM8731::reset());
+        self.set_register(WM8731::power_down(|w| {
+            final_power_settings(w);
+            w.output().power_off();
+        }));
+
+        // disable input mute, set to 0dB gain
+        self.set_register(WM8731::left_line_in(|w| {
+            w.both().disable();
+            w.mute().disable();
+            w.volume().nearest_dB(0);
+        }));
+
+        // sidetone off; DAC selected; bypass off; line input selected; mic muted; mic boost off
+        self.set_register(WM8731::analog_audio_path(|w| {
+            w.sidetone().disable();
+            w.dac_select().select();
+            w.bypass().disable();
+            w.input_select().line_input();
+            w.mute_mic().enable();
+            w.mic_boost().disable();
+        }));
+
+        // disable DAC mute, deemphasis for 48k
+        self.set_register(WM8731::digital_audio_path(|w| {
+            w.dac_mut();
+            w.deemphasis().frequency_48();
+        }));
+
+        // nothing inverted, slave, 24-bits, MSB format
+        self.set_register(WM8731::digital_audio_interface_format(|w| {
+            w.bit_clock_invert().no_invert();
+            w.master_slave().slave();
+            w.left_right_dac_clock_swap().right_channel_dac_data_right();
+            w.left_right_phase().data_when_daclrc_low();
+            w.bit_length().bits_24();
+            w.format().left_justified();
+        }));
+
+        // no clock division, normal mode, 48k
+        self.set_register(WM8731::sampling(|w| {
+            w.core_clock_divider_select().normal();
+            w.base_oversampling_rate().normal_256();
+            w.sample_rate().adc_48();
+            w.usb_normal().normal();
+        }));
+
+        // set active
+        self.set_register(WM8731::active().active());
+
+        // enable output
+        self.set_register(WM8731::power_down(final_power_settings));
+    }
+}
@@ -0,0 +1,409 @@
+//! Single-letter commands over a USB serial port, each followed by a decimal number and a newline:
+//!
+//! - `f440` sets the signal generator's frequency in Hz, which both channels share
+//! - `h440125` sets the frequency in millihertz, for the DDS mode
+//! - `g1` picks the generator mode: 0 looping one period from a buffer, 1 DDS, 2 the last upload
+//! - `v2700` sets the selected channel's amplitude in millivolts peak-to-peak
+//! - `l1500` sets the DC level in millivolts that the selected channel is centered on, 1500 by
+//!   default
+//! - `w1` picks the selected channel's waveform: 0 sine, 1 square, 2 triangle, 3 sawtooth, 4 pulse,
+//!   5 noise
+//! - `d25` sets the selected channel's square wave duty cycle in percent, or its pulse width in
+//!   microseconds
+//! - `c2` selects the channel that `v`, `l`, `w` and `d` change, 1 (the default) or 2
+//! - `m1` sets what channel 2 outputs: 0 nothing, 1 the same as channel 1, 2 its own settings
+//! - `p90` sets how many degrees channel 2 leads channel 1
+//! - `b20` and `e20000` set the frequencies in Hz that a sweep begins and ends at
+//! - `t5000` sets how long a sweep takes in milliseconds
+//! - `r1` makes sweeps repeat from the beginning, or `r0` stop at the end frequency
+//! - `s1` starts a linear sweep, `s2` a logarithmic one, and `s0` stops it
+//! - `a1` modulates channel 1 in the DDS mode: 0 not at all, 1 AM, 2 FM, 3 PM
+//! - `n0` picks the modulating waveform, numbered as for `w`
+//! - `o5` sets the modulating frequency in Hz
+//! - `y50` sets the modulation depth: the AM index in percent, the FM deviation in Hz, or the PM
+//!   deviation in degrees
+//! - `k48000` sets the sample rate in Hz that the next upload plays at
+//! - `u1000` starts an upload of that many samples, sent in binary right after the newline; see
+//!   `upload` for the format.  A line of text sent before it is complete aborts it, and is
+//!   otherwise ignored.
+//!
+//! A command that cannot be carried out changes nothing, and gets a line starting with `error: `
+//! back saying why.  So does an upload that is aborted or whose checksum does not match.
+
+use core::cmp::min;
+
+use usb_device::prelude::*;
+
+use crate::dac::{
+    self, Channel, Channel2, Mode, SignalGenerator, ARBITRARY_CAPACITY, MIN_ARBITRARY_RATE,
+    SAMPLE_RATE,
+};
+use crate::modulation::{Kind, Modulation};
+use crate::regs::{Dac, DmaStream, Timer};
+use crate::sweep::{Shape, Sweep};
+use crate::upload::{self, Upload};
+use crate::waveform::Waveform;
+
+/// Why a command was not carried out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CommandError {
+    MissingValue,
+    NotANumber,
+    TooLarge,
+    UnknownCommand,
+    /// A value the command has no meaning for, like a waveform that does not exist
+    OutOfRange,
+    UploadLength,
+    UploadRate,
+    UploadSample,
+    Checksum,
+    Generator(dac::Error),
+}
+
+impl CommandError {
+    pub fn message(self) -> &'static str {
+        match self {
+            CommandError::MissingValue => "the command needs a number after it",
+            CommandError::NotANumber => "the value must be a whole number of digits",
+            CommandError::TooLarge => "the value is too large",
+            CommandError::UnknownCommand => "unknown command",
+            CommandError::OutOfRange => "the value is not one of the options",
+            CommandError::UploadLength => "an upload must have 1 to 42000 samples",
+            CommandError::UploadRate => "an upload's sample rate must be 1282Hz to 10.5MHz",
+            CommandError::UploadSample => "an upload's samples must be 0 to 4095; it was aborted",
+            CommandError::Checksum => "the upload's checksum did not match; it was discarded",
+            CommandError::Generator(error) => error.message(),
+        }
+    }
+}
+
+impl From<dac::Error> for CommandError {
+    fn from(error: dac::Error) -> Self {
+        CommandError::Generator(error)
+    }
+}
+
+impl From<upload::Error> for CommandError {
+    fn from(error: upload::Error) -> Self {
+        match error {
+            upload::Error::SampleTooLarge => CommandError::UploadSample,
+            upload::Error::Checksum => CommandError::Checksum,
+        }
+    }
+}
+
+pub struct UsbCommand<'a, T: usb_device::bus::UsbBus, D, S, P> {
+    buffer: &'a mut [u8],
+    current_length: usize,
+    usb_device: UsbDevice<'a, T>,
+    serial_class: usbd_serial::SerialPort<'a, T>,
+    pub signal_generator: SignalGenerator<D, S, P>,
+    /// The channel that amplitude and waveform commands apply to
+    channel: Channel,
+    /// The sweep that the next `s` command starts, other than its shape
+    sweep: Sweep,
+    /// The modulation that the next `a` command applies, other than its kind
+    modulation: Modulation,
+    /// The sample rate that the next upload plays at
+    upload_rate: usize,
+    upload: Option<Upload>,
+    /// Set when an upload is aborted part way through a line, which is dropped rather than run
+    discarding_line: bool,
+}
+
+impl<'a, T: usb_device::bus::UsbBus, D: Dac, S: DmaStream, P: Timer> UsbCommand<'a, T, D, S, P> {
+    pub fn new(
+        usb_device: UsbDevice<'a, T>,
+        serial_class: usbd_serial::SerialPort<'a, T>,
+        buffer: &'a mut [u8],
+        signal_generator: SignalGenerator<D, S, P>,
+    ) -> Self {
+        Self {
+            buffer,
+            current_length: 0,
+            usb_device,
+            serial_class,
+            signal_generator,
+            channel: Channel::One,
+            sweep: Sweep {
+                start_millihertz: 20_000,
+                stop_millihertz: 20_000_000,
+                duration_ms: 10_000,
+                shape: Shape::Logarithmic,
+                repeat: false,
+            },
+            modulation: Modulation {
+                kind: Kind::Am,
+                waveform: Waveform::Sine,
+                millihertz: 5_000,
+                depth: 50,
+            },
+            upload_rate: 48_000,
+            upload: None,
+            discarding_line: false,
+        }
+    }
+
+    pub fn poll(&mut self) {
+        // make sure there's at least one byte available, by potentially sacrificing the last
+        // character in the buffer
+        self.current_length = min(self.current_length, self.buffer.len() - 1);
+
+        if let Some(count) = self.check_for_serial_bytes() {
+            if count == 0 {
+                // why is read() returning me Ok(0), it should be WouldBlock in this case...
+                return;
+            }
+            self.current_length = min(self.buffer.len(), self.current_length + count);
+
+            // one read can hold the end of a command and the start of the next, or of an upload's
+            // samples, so keep going until everything received has been used
+            loop {
+                if self.upload.is_some() {
+                    self.receive_upload();
+                    if self.upload.is_some() {
+                        // still waiting for more of it
+                        return;
+                    }
+                }
+
+                let filled_buf = &self.buffer[..self.current_length];
+                match filled_buf.iter().position(|&c| c == b'\n') {
+                    Some(newline) => {
+                        if !self.discarding_line {
+                            self.execute(newline);
+                        }
+                        self.discarding_line = false;
+                        self.consume(newline + 1);
+                    }
+                    None => {
+                        if self.discarding_line {
+                            self.consume(self.current_length);
+                        }
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the command in the buffer that ends at `newline`, replying if it fails.
+    fn execute(&mut self, newline: usize) {
+        if let Err(error) = self.run(newline) {
+            self.reply_error(error);
+        }
+    }
+
+    fn run(&mut self, newline: usize) -> Result<(), CommandError> {
+        let mut line = &self.buffer[..newline];
+        // terminals send "\r\n"
+        if let Some((&b'\r', rest)) = line.split_last() {
+            line = rest;
+        }
+        let (&command, value_bytes) = match line.split_first() {
+            Some(split) => split,
+            // a blank line is not worth complaining about
+            None => return Ok(()),
+        };
+        if value_bytes.is_empty() {
+            return Err(CommandError::MissingValue);
+        }
+
+        let mut value: usize = 0;
+        for &c in value_bytes.iter() {
+            if !c.is_ascii_digit() {
+                return Err(CommandError::NotANumber);
+            }
+            value = value
+                .checked_mul(10)
+                .and_then(|value| value.checked_add((c - b'0') as usize))
+                .ok_or(CommandError::TooLarge)?;
+        }
+        let millihertz = (value as u64).saturating_mul(1000);
+
+        // execute the command that we just parsed; the first character tells us what we should
+        // change
+        match command {
+            b'f' => self.signal_generator.set_frequency(value)?,
+            b'h' => self
+                .signal_generator
+                .set_frequency_millihertz(value as u64)?,
+            b'g' => {
+                let mode = match value {
+                    0 => Mode::Buffer,
+                    1 => Mode::Dds,
+                    2 => Mode::Arbitrary,
+                    _ => return Err(CommandError::OutOfRange),
+                };
+                self.signal_generator.set_mode(mode)?;
+            }
+            b'a' => {
+                let kind = match value {
+                    0 => None,
+                    1 => Some(Kind::Am),
+                    2 => Some(Kind::Fm),
+                    3 => Some(Kind::Pm),
+                    _ => return Err(CommandError::OutOfRange),
+                };
+                match kind {
+                    Some(kind) => {
+                        let modulation = Modulation {
+                            kind,
+                            ..self.modulation
+                        };
+                        self.signal_generator.set_modulation(Some(modulation))?;
+                        self.modulation = modulation;
+                    }
+                    None => self.signal_generator.set_modulation(None)?,
+                }
+            }
+            b'n' => {
+                let waveform = Waveform::from_index(value).ok_or(CommandError::OutOfRange)?;
+                self.remodulate(Modulation {
+                    waveform,
+                    ..self.modulation
+                })?;
+            }
+            b'o' => self.remodulate(Modulation {
+                millihertz,
+                ..self.modulation
+            })?,
+            b'y' => {
+                let depth = min(value, u32::MAX as usize) as u32;
+                self.remodulate(Modulation {
+                    depth,
+                    ..self.modulation
+                })?;
+            }
+            b'k' => {
+                if !(MIN_ARBITRARY_RATE..=SAMPLE_RATE).contains(&value) {
+                    return Err(CommandError::UploadRate);
+                }
+                self.upload_rate = value;
+            }
+            b'u' => {
+                if value == 0 || value > ARBITRARY_CAPACITY {
+                    return Err(CommandError::UploadLength);
+                }
+                // stop the output now, since the samples are about to be overwritten
+                self.signal_generator.begin_upload();
+                self.upload = Some(Upload::new(value));
+            }
+            b'v' => self.signal_generator.set_mvpp(self.channel, value)?,
+            b'l' => self.signal_generator.set_offset_mv(self.channel, value)?,
+            b'w' => {
+                let waveform = Waveform::from_index(value).ok_or(CommandError::OutOfRange)?;
+                self.signal_generator.set_waveform(self.channel, waveform)?;
+            }
+            b'd' => {
+                let waveform = self.signal_generator.waveform(self.channel);
+                let parameter = min(value, u32::MAX as usize) as u32;
+                self.signal_generator
+                    .set_waveform(self.channel, waveform.with_parameter(parameter))?;
+            }
+            b'c' => {
+                self.channel = match value {
+                    1 => Channel::One,
+                    2 => Channel::Two,
+                    _ => return Err(CommandError::OutOfRange),
+                }
+            }
+            b'm' => {
+                let channel2 = match value {
+                    0 => Channel2::Off,
+                    1 => Channel2::Linked,
+                    2 => Channel2::Independent,
+                    _ => return Err(CommandError::OutOfRange),
+                };
+                self.signal_generator.set_channel2(channel2)?;
+            }
+            b'p' => self.signal_generator.set_phase(value),
+            b'b' => self.sweep.start_millihertz = millihertz,
+            b'e' => self.sweep.stop_millihertz = millihertz,
+            b't' => self.sweep.duration_ms = min(value, u32::MAX as usize) as u32,
+            b'r' => self.sweep.repeat = value != 0,
+            b's' => {
+                let shape = match value {
+                    0 => None,
+                    1 => Some(Shape::Linear),
+                    2 => Some(Shape::Logarithmic),
+                    _ => return Err(CommandError::OutOfRange),
+                };
+                match shape {
+                    Some(shape) => self.signal_generator.start_sweep(Sweep {
+                        shape,
+                        ..self.sweep
+                    })?,
+                    None => self.signal_generator.stop_sweep(),
+                }
+            }
+            _ => return Err(CommandError::UnknownCommand),
+        };
+        Ok(())
+    }
+
+    /// Sends `error` back to the host.  The host may not be reading, so whatever does not fit in
+    /// the serial port's buffer is dropped rather than waited for.
+    fn reply_error(&mut self, error: CommandError) {
+        let parts: [&[u8]; 3] = [b"error: ", error.message().as_bytes(), b"\n"];
+        for part in parts.iter() {
+            self.serial_class.write(part).ok();
+        }
+    }
+
+    /// Makes `modulation` the settings for the next `a` command, applying it straight away if
+    /// the carrier is being modulated.
+    fn remodulate(&mut self, modulation: Modulation) -> Result<(), CommandError> {
+        if self.signal_generator.modulation().is_some() {
+            self.signal_generator.set_modulation(Some(modulation))?;
+        }
+        self.modulation = modulation;
+        Ok(())
+    }
+
+    /// Moves the bytes received for an upload into the signal generator, and plays the upload if
+    /// it is complete and intact.
+    fn receive_upload(&mut self) {
+        let (used, result, len, line_ended) = match self.upload.as_mut() {
+            Some(upload) => {
+                let samples = self.signal_generator.arbitrary_samples();
+                let (used, result) = upload.receive(&self.buffer[..self.current_length], samples);
+                (used, result, upload.len(), upload.line_ended())
+            }
+            None => return,
+        };
+        self.consume(used);
+
+        match result {
+            Some(Ok(())) => {
+                self.upload = None;
+                self.signal_generator.play_arbitrary(len, self.upload_rate);
+            }
+            Some(Err(error)) => {
+                self.upload = None;
+                self.signal_generator.discard_arbitrary();
+                // whatever interrupted the upload was most likely a command, now missing its
+                // first bytes, whether they were taken as a sample or as the checksum
+                self.discarding_line = !line_ended;
+                self.reply_error(error.into());
+            }
+            None => (),
+        }
+    }
+
+    /// Drops the first `count` bytes of the buffer, keeping whatever came after them.
+    fn consume(&mut self, count: usize) {
+        self.buffer.copy_within(count..self.current_length, 0);
+        self.current_length -= count;
+    }
+
+    fn check_for_serial_bytes(&mut self) -> Option<usize> {
+        let unused_buf = &mut self.buffer[self.current_length..];
+
+        if self.usb_device.poll(&mut [&mut self.serial_class]) {
+            return self.serial_class.read(unused_buf).ok();
+        }
+
+        None
+    }
+}
@@ -0,0 +1,1076 @@
+//! A function generator on DAC channel 1 (PA4), and optionally channel 2 (PA5), fed by DMA1
+//! stream 5 and paced by TIM4.  With both channels on, each DMA transfer writes a pair of samples
+//! to the dual holding register, so the channels always convert together and their phase
+//! relationship is exact.
+//!
+//! The buffer mode loops one period from the buffer, so the frequency is the sample rate divided
+//! by a whole number of samples.  The DDS mode instead steps a phase accumulator through a table
+//! of one period at a lower sample rate, refilling each half of a small buffer while DMA plays the
+//! other, for sub-hertz resolution at any frequency; it only drives channel 1.  Sweeps run in the
+//! DDS mode, changing the accumulator's step every `SWEEP_CHUNK` samples, and so does modulation.
+//! The arbitrary mode loops samples uploaded by the host instead, at whatever rate they were
+//! captured at.
+//!
+//! Settings that the current mode cannot play, or that would clip at either end of the DAC's
+//! range, are refused with an `Error`, leaving the output as it was.
+
+use core::cmp::min;
+
+use stm32f4xx_hal::stm32;
+
+use crate::dds::{self, Accumulator, TABLE_LEN};
+use crate::modulation::{self, Modulation, Modulator};
+use crate::regs::{Dac, DacTrigger, DmaStream, Half, StreamConfig, Target, Timer, TransferSize};
+use crate::sweep::Sweep;
+use crate::waveform::{self, Waveform};
+
+const DAC_MILLIVOLTS: usize = 3_000;
+const DAC_VOLTAGE: f32 = DAC_MILLIVOLTS as f32 / 1000.0;
+
+const HCLK: clock_model::Hz = clock_model::Hz::new(168_000_000);
+// the HAL divides the 168MHz HCLK by 4 to keep APB1 under 42MHz
+const APB1_PRESCALER: u64 = 4;
+// TIM4 is on APB1, and since that prescaler is not 1, the timer clock is doubled.
+// `pll_configurations timers --timer tim4` lists this and the PSC/ARR values for other rates.
+const TIMER_CLOCK: clock_model::Hz = clock_model::bus::timer_clock(HCLK, APB1_PRESCALER, false);
+static_assertions::const_assert!(TIMER_CLOCK.equals(84_000_000));
+const TIMER_CLOCK_RATE: usize = TIMER_CLOCK.whole() as usize;
+pub const SAMPLE_RATE: usize = 10_500_000;
+// the timer won't behave correctly if the sample rate is not an exact integer number of ticks
+static_assertions::const_assert_eq!(TIMER_CLOCK_RATE % SAMPLE_RATE, 0);
+// nor if it takes more than 16 bits to represent the delay
+static_assertions::const_assert!(TIMER_CLOCK_RATE / SAMPLE_RATE <= 65536);
+static_assertions::const_assert!(clock_model::bus::update_rate(
+    TIMER_CLOCK,
+    0,
+    (TIMER_CLOCK_RATE / SAMPLE_RATE - 1) as u64
+)
+.equals(SAMPLE_RATE as u64));
+/// The DDS mode computes every sample, which the CPU cannot keep up with at `SAMPLE_RATE`
+pub const DDS_SAMPLE_RATE: usize = 500_000;
+static_assertions::const_assert_eq!(TIMER_CLOCK_RATE % DDS_SAMPLE_RATE, 0);
+static_assertions::const_assert!(TIMER_CLOCK_RATE / DDS_SAMPLE_RATE <= 65536);
+
+/// The sample buffer, in 32-bit words.  Channel 1 alone uses it as twice as many 16-bit samples.
+const BUFFER_WORDS: usize = 21_000;
+/// The buffer mode double-buffers between the two halves of the buffer, called banks so as not
+/// to be confused with the DDS mode's halves.
+const BANK_WORDS: usize = BUFFER_WORDS / 2;
+/// The most samples an arbitrary waveform can have.
+pub const ARBITRARY_CAPACITY: usize = 2 * BUFFER_WORDS;
+/// The slowest rate the timer can pace samples at without a prescaler, about 1.3kHz.
+pub const MIN_ARBITRARY_RATE: usize = TIMER_CLOCK_RATE / 65536 + 1;
+/// Samples in each half of the DDS mode's buffer, about 2ms at `DDS_SAMPLE_RATE`, which is how long
+/// `refill()` can be kept waiting.
+const DDS_HALF: usize = 1024;
+/// Samples between frequency steps of a sweep, 128us at `DDS_SAMPLE_RATE`.
+const SWEEP_CHUNK: usize = 64;
+static_assertions::const_assert_eq!(DDS_HALF % SWEEP_CHUNK, 0);
+/// The shortest period, in samples, that the buffer mode swaps banks under.  Writing the memory
+/// register the stream is reading from is a transfer error, which stops it, so the period must
+/// outlast reading CT and writing the other register.  64 samples is about 1000 CPU cycles.
+const MIN_SWAP_SAMPLES: usize = 64;
+
+/// DAC channel 1 requests DMA1 stream 5 on channel 7.
+const STREAM: StreamConfig = StreamConfig {
+    channel: 7,
+    memory_size: TransferSize::Bits16,
+    peripheral_size: TransferSize::Bits16,
+    memory_increment: true, // increment the memory address we're reading from each cycle
+    circular: true,         // circular mode means I don't have to keep refilling the buffer
+    double_buffer: false,   // no need for double-buffering
+    transfer_interrupts: false,
+};
+
+/// The same stream moving a sample for each channel at once.
+const DUAL_STREAM: StreamConfig = StreamConfig {
+    memory_size: TransferSize::Bits32,
+    peripheral_size: TransferSize::Bits32,
+    ..STREAM
+};
+
+/// The buffer mode's stream, alternating between M0AR and M1AR at the end of each period, so that
+/// either can be pointed at new samples while the other plays.
+const BANK_STREAM: StreamConfig = StreamConfig {
+    double_buffer: true,
+    ..STREAM
+};
+const DUAL_BANK_STREAM: StreamConfig = StreamConfig {
+    double_buffer: true,
+    ..DUAL_STREAM
+};
+
+/// The stream looping over the DDS buffer, interrupting as each half is played.
+const DDS_STREAM: StreamConfig = StreamConfig {
+    transfer_interrupts: true,
+    ..STREAM
+};
+
+/// Turns on the clocks of the DAC, and the timer and DMA controller that drive it.
+pub fn enable_clocks() {
+    unsafe {
+        let rcc = &*stm32::RCC::ptr();
+        rcc.apb1enr.modify(|_r, w| {
+            w.dacen().set_bit();
+            w.tim4en().set_bit()
+        });
+        rcc.ahb1enr.modify(|_r, w| w.dma1en().set_bit());
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Channel {
+    One,
+    Two,
+}
+
+/// What DAC channel 2 outputs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Channel2 {
+    /// Nothing; channel 1 runs alone, with twice the buffer for long periods
+    Off,
+    /// Channel 1's waveform and amplitude
+    Linked,
+    /// Its own waveform and amplitude
+    Independent,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// One period looped from the buffer at `SAMPLE_RATE`
+    Buffer,
+    /// A phase accumulator at `DDS_SAMPLE_RATE`, refilled by `refill()`
+    Dds,
+    /// The samples from the last upload, at their own rate, on channel 1 only
+    Arbitrary,
+}
+
+/// Why the signal generator refused a setting.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    ZeroFrequency,
+    /// Above half the sample rate of the mode
+    FrequencyTooHigh,
+    /// A period too long to fit in the buffer mode's buffer
+    FrequencyTooLow,
+    /// An amplitude and offset that would take the output past either end of the DAC's range
+    Clipping,
+    /// AM deeper than 100%
+    DepthTooLarge,
+    /// FM that would take the carrier above half the DDS mode's sample rate
+    DeviationTooLarge,
+    /// The arbitrary mode with no upload to play, or one that another mode has since overwritten
+    NoUpload,
+}
+
+impl Error {
+    pub fn message(self) -> &'static str {
+        match self {
+            Error::ZeroFrequency => "the frequency must be above 0Hz",
+            Error::FrequencyTooHigh => "the frequency is above half the sample rate",
+            Error::FrequencyTooLow => "the frequency is too low for the buffer mode; use DDS",
+            Error::Clipping => "the amplitude and offset would clip outside 0-3V",
+            Error::DepthTooLarge => "AM depth cannot be over 100%",
+            Error::DeviationTooLarge => "FM would take the frequency above half the sample rate",
+            Error::NoUpload => "no waveform has been uploaded since the last mode change",
+        }
+    }
+}
+
+/// The settings of one channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Output {
+    waveform: Waveform,
+    mvpp: usize,
+    /// The DC level the waveform is centered on, in millivolts
+    offset_mv: usize,
+}
+
+/// Everything that decides what the buffer and DDS modes play, which is checked as a whole
+/// before any of it changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Settings {
+    mode: Mode,
+    outputs: [Output; 2],
+    channel2: Channel2,
+    /// How far channel 2 leads channel 1, in degrees
+    phase: usize,
+    millihertz: u64,
+}
+
+impl Settings {
+    /// Whether the settings can be played as they are.
+    fn check(&self) -> Result<(), Error> {
+        let outputs = self.buffer_outputs();
+        let outputs = match self.channel2 {
+            Channel2::Off => &outputs[..1],
+            _ => &outputs[..],
+        };
+        for output in outputs {
+            // the distance to whichever end of the range is nearer
+            let headroom = min(
+                output.offset_mv,
+                DAC_MILLIVOLTS.saturating_sub(output.offset_mv),
+            );
+            if output.mvpp > 2 * headroom {
+                return Err(Error::Clipping);
+            }
+        }
+
+        if self.millihertz == 0 {
+            return Err(Error::ZeroFrequency);
+        }
+        match self.mode {
+            Mode::Buffer => {
+                if self.millihertz > SAMPLE_RATE as u64 * 1000 / 2 {
+                    return Err(Error::FrequencyTooHigh);
+                }
+                let capacity = match self.channel2 {
+                    Channel2::Off => 2 * BUFFER_WORDS,
+                    _ => BUFFER_WORDS,
+                };
+                let periodic = outputs.iter().all(|output| output.waveform.is_periodic());
+                if periodic && SAMPLE_RATE as u64 * 1000 / self.millihertz > capacity as u64 {
+                    return Err(Error::FrequencyTooLow);
+                }
+            }
+            Mode::Dds => {
+                if self.millihertz > DDS_SAMPLE_RATE as u64 * 1000 / 2 {
+                    return Err(Error::FrequencyTooHigh);
+                }
+            }
+            Mode::Arbitrary => (),
+        }
+        Ok(())
+    }
+
+    /// The settings channel 1 and channel 2 play with in the buffer mode.
+    fn buffer_outputs(&self) -> [Output; 2] {
+        match self.channel2 {
+            Channel2::Independent => self.outputs,
+            _ => [self.outputs[0]; 2],
+        }
+    }
+}
+
+/// Whether `modulation` can be applied to a carrier that reaches `millihertz` at its highest.
+fn check_modulation(modulation: &Modulation, millihertz: u64) -> Result<(), Error> {
+    match modulation.kind {
+        modulation::Kind::Am if modulation.depth > 100 => Err(Error::DepthTooLarge),
+        modulation::Kind::Fm
+            if millihertz + modulation.depth as u64 * 1000 > DDS_SAMPLE_RATE as u64 * 1000 / 2 =>
+        {
+            Err(Error::DeviationTooLarge)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// What the stream is double-buffering in the buffer mode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Playing {
+    /// 0 for the first half of the buffer, 1 for the second
+    bank: usize,
+    loop_samples: usize,
+    dual: bool,
+}
+
+/// `P` is the timer that paces the samples.
+pub struct SignalGenerator<D, S, P> {
+    samples: [u32; BUFFER_WORDS],
+    /// One period of channel 1's waveform, for the DDS mode
+    table: [u16; TABLE_LEN],
+    accumulator: Accumulator,
+    sweep: Option<Sweep>,
+    /// Samples played since the sweep started
+    elapsed: u64,
+    modulation: Option<Modulation>,
+    /// One period of the modulating waveform
+    modulation_table: [u16; TABLE_LEN],
+    modulator: Option<Modulator>,
+    dac: D,
+    stream: S,
+    timer: P,
+    settings: Settings,
+    /// The length and sample rate of the last uploaded waveform
+    arbitrary_len: usize,
+    arbitrary_rate: usize,
+    /// Set while the buffer mode is double-buffering, so the next update can swap banks
+    playing: Option<Playing>,
+}
+
+impl<D: Dac, S: DmaStream, P: Timer> SignalGenerator<D, S, P> {
+    /// Takes over the DAC, its DMA stream and the timer that paces them; with the real
+    /// peripherals, call `enable_clocks()` first.  Nothing is output until the first call to
+    /// `update()`, which has to happen once the generator is in its final place in memory, since
+    /// the DMA stream points into it.  It is also too large to fit on the stack twice, so construct
+    /// it directly where it will live.
+    pub fn new(dac: D, stream: S, timer: P) -> Self {
+        let output = Output {
+            waveform: Waveform::Sine,
+            mvpp: 2_700,
+            offset_mv: DAC_MILLIVOLTS / 2,
+        };
+        Self {
+            samples: [0; BUFFER_WORDS],
+            table: [0; TABLE_LEN],
+            accumulator: Accumulator::new(),
+            sweep: None,
+            elapsed: 0,
+            modulation: None,
+            modulation_table: [0; TABLE_LEN],
+            modulator: None,
+            dac,
+            stream,
+            timer,
+            settings: Settings {
+                mode: Mode::Buffer,
+                outputs: [output; 2],
+                channel2: Channel2::Off,
+                phase: 0,
+                millihertz: 1_000_000,
+            },
+            arbitrary_len: 0,
+            arbitrary_rate: SAMPLE_RATE,
+            playing: None,
+        }
+    }
+
+    pub fn set_frequency(&mut self, hz: usize) -> Result<(), Error> {
+        self.set_frequency_millihertz((hz as u64).saturating_mul(1000))
+    }
+
+    /// Sets the frequency to a thousandth of a hertz, which only the DDS mode can make use of; the
+    /// buffer mode rounds it to a whole number of samples per period.  This ends any sweep.
+    pub fn set_frequency_millihertz(&mut self, millihertz: u64) -> Result<(), Error> {
+        if let Some(modulation) = self.modulation {
+            check_modulation(&modulation, millihertz)?;
+        }
+        self.change(|settings| settings.millihertz = millihertz)?;
+        self.sweep = None;
+        self.update();
+        Ok(())
+    }
+
+    /// Switches between the modes; leaving the DDS mode ends any sweep or modulation.  Switching to
+    /// the arbitrary mode replays the last upload, which is only possible if no other mode has
+    /// played since, as they write over it.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), Error> {
+        if mode == Mode::Arbitrary && self.arbitrary_len == 0 {
+            return Err(Error::NoUpload);
+        }
+        self.change(|settings| settings.mode = mode)?;
+        if mode != Mode::Dds {
+            self.sweep = None;
+            self.modulation = None;
+        }
+        self.update();
+        Ok(())
+    }
+
+    /// Modulates channel 1's carrier, switching to the DDS mode, or stops modulating it.
+    pub fn set_modulation(&mut self, modulation: Option<Modulation>) -> Result<(), Error> {
+        if let Some(modulation) = modulation {
+            let highest = match self.sweep {
+                Some(sweep) => sweep.start_millihertz.max(sweep.stop_millihertz),
+                None => self.settings.millihertz,
+            };
+            check_modulation(&modulation, highest)?;
+            self.change(|settings| settings.mode = Mode::Dds)?;
+        }
+        self.modulation = modulation;
+        self.update();
+        Ok(())
+    }
+
+    pub fn modulation(&self) -> Option<Modulation> {
+        self.modulation
+    }
+
+    /// Stops the output so that an upload can be written to `arbitrary_samples()`, which plays
+    /// once `play_arbitrary()` is called.  Until then, nothing is output.
+    pub fn begin_upload(&mut self) {
+        self.stop();
+        self.arbitrary_len = 0;
+    }
+
+    /// The buffer an upload goes into, which is only safe to write after `begin_upload()`.
+    pub fn arbitrary_samples(&mut self) -> &mut [u16] {
+        as_halfwords(&mut self.samples)
+    }
+
+    /// Loops the first `len` samples given to `arbitrary_samples()` at `sample_rate`, which is
+    /// limited to what the timer can do and rounded to a whole number of its ticks.
+    pub fn play_arbitrary(&mut self, len: usize, sample_rate: usize) {
+        self.arbitrary_len = min(len, ARBITRARY_CAPACITY);
+        self.arbitrary_rate = sample_rate.max(MIN_ARBITRARY_RATE).min(SAMPLE_RATE);
+        // the arbitrary mode plays whatever the other modes accepted
+        self.set_mode(Mode::Arbitrary).ok();
+    }
+
+    /// Gives up on an upload that did not arrive intact, going back to generating a waveform.  If
+    /// the frequency was set too low for the buffer mode while the upload played, the output stays
+    /// off until it is changed.
+    pub fn discard_arbitrary(&mut self) {
+        self.arbitrary_len = 0;
+        let mode = match self.settings.mode {
+            Mode::Arbitrary => Mode::Buffer,
+            mode => mode,
+        };
+        self.set_mode(mode).ok();
+    }
+
+    /// Starts `sweep` from its beginning, switching to the DDS mode.
+    pub fn start_sweep(&mut self, sweep: Sweep) -> Result<(), Error> {
+        let highest = sweep.start_millihertz.max(sweep.stop_millihertz);
+        if highest > DDS_SAMPLE_RATE as u64 * 1000 / 2 {
+            return Err(Error::FrequencyTooHigh);
+        }
+        if let Some(modulation) = self.modulation {
+            check_modulation(&modulation, highest)?;
+        }
+        self.change(|settings| settings.mode = Mode::Dds)?;
+        self.sweep = Some(sweep);
+        self.elapsed = 0;
+        self.update();
+        Ok(())
+    }
+
+    /// Stops any sweep, holding the frequency set before it started.
+    pub fn stop_sweep(&mut self) {
+        self.sweep = None;
+        self.update();
+    }
+
+    pub fn set_mvpp(&mut self, channel: Channel, mvpp: usize) -> Result<(), Error> {
+        self.change(|settings| settings.outputs[channel as usize].mvpp = mvpp)?;
+        self.update();
+        Ok(())
+    }
+
+    /// Sets the DC level that `channel`'s waveform is centered on, from 0 to 3000mV.  It starts
+    /// at 1500mV, the middle of the DAC's range.
+    pub fn set_offset_mv(&mut self, channel: Channel, offset_mv: usize) -> Result<(), Error> {
+        self.change(|settings| settings.outputs[channel as usize].offset_mv = offset_mv)?;
+        self.update();
+        Ok(())
+    }
+
+    pub fn set_waveform(&mut self, channel: Channel, waveform: Waveform) -> Result<(), Error> {
+        self.change(|settings| settings.outputs[channel as usize].waveform = waveform)?;
+        self.update();
+        Ok(())
+    }
+
+    pub fn waveform(&self, channel: Channel) -> Waveform {
+        self.settings.outputs[channel as usize].waveform
+    }
+
+    /// Turns channel 2 on or off.  Its own settings are kept while it is linked or off.
+    pub fn set_channel2(&mut self, channel2: Channel2) -> Result<(), Error> {
+        self.change(|settings| settings.channel2 = channel2)?;
+        self.update();
+        Ok(())
+    }
+
+    /// Sets how far channel 2 leads channel 1, in degrees of a period; 90 gives quadrature.
+    pub fn set_phase(&mut self, degrees: usize) {
+        self.settings.phase = degrees % 360;
+        self.update();
+    }
+
+    /// Applies `change` to the settings if the result can be played, without playing it yet.
+    fn change(&mut self, change: impl FnOnce(&mut Settings)) -> Result<(), Error> {
+        let mut settings = self.settings;
+        change(&mut settings);
+        settings.check()?;
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Recomputes the samples and plays them.  In the buffer mode, the new samples are prepared in
+    /// whichever bank the stream is not reading, and the stream moves over to them at the end of
+    /// a period, so the output never stops.  The stream cannot change its transfer count on the
+    /// fly, though, so when the number of samples per period changes, or the period is under
+    /// `MIN_SWAP_SAMPLES`, it restarts at the end of a period instead, leaving a gap of a few
+    /// microseconds.  Everything else, and periods too long for one bank, stop the stream while
+    /// the samples are computed.
+    pub fn update(&mut self) {
+        if self.settings.mode != Mode::Arbitrary {
+            // every other mode computes its samples over the upload
+            self.arbitrary_len = 0;
+        }
+        if self.settings.mode == Mode::Buffer {
+            let dual = self.settings.channel2 != Channel2::Off;
+            let outputs = self.settings.buffer_outputs();
+            let capacity = if dual { BANK_WORDS } else { 2 * BANK_WORDS };
+            let waveforms = [outputs[0].waveform, outputs[1].waveform];
+            let waveforms = if dual {
+                &waveforms[..]
+            } else {
+                &waveforms[..1]
+            };
+            if loop_length(waveforms, self.settings.millihertz, usize::MAX) <= capacity
+                || waveforms.iter().any(|waveform| !waveform.is_periodic())
+            {
+                self.update_bank(dual, outputs);
+                return;
+            }
+        }
+        self.restart();
+    }
+
+    /// Computes the samples into the idle bank, then swaps the stream over to it.
+    fn update_bank(&mut self, dual: bool, outputs: [Output; 2]) {
+        let bank = match self.playing {
+            Some(playing) => 1 - playing.bank,
+            None => 0,
+        };
+        let words = &mut self.samples[bank * BANK_WORDS..(bank + 1) * BANK_WORDS];
+        let loop_samples = if dual {
+            fill_dual_samples(
+                words,
+                outputs,
+                self.settings.phase,
+                self.settings.millihertz,
+            )
+        } else {
+            fill_samples(as_halfwords(words), outputs[0], self.settings.millihertz)
+        };
+        if loop_samples == 0 {
+            return;
+        }
+
+        let next = Playing {
+            bank,
+            loop_samples,
+            dual,
+        };
+        let swapped = match self.playing {
+            Some(playing)
+                if playing.loop_samples == loop_samples
+                    && playing.dual == dual
+                    && loop_samples >= MIN_SWAP_SAMPLES =>
+            {
+                self.swap(bank)
+            }
+            Some(_) => {
+                // let the old samples finish their period, so the output changes between
+                // periods rather than part way through one
+                self.wait_for_switch(self.stream.current_target());
+                false
+            }
+            None => false,
+        };
+        if !swapped {
+            self.stop();
+            self.set_sample_rate(SAMPLE_RATE);
+            let address = self.samples[bank * BANK_WORDS..].as_ptr() as u32;
+            self.stream.set_memory1_address(address);
+            let config = if dual {
+                &DUAL_BANK_STREAM
+            } else {
+                &BANK_STREAM
+            };
+            self.start(address, loop_samples, config, dual);
+        }
+        self.playing = Some(next);
+    }
+
+    /// Points the memory register the stream is not reading at `bank`, so that it moves over at
+    /// the end of the current period, and then the other register too, so that it stays there.
+    /// If the period ends between reading CT and either write, that write lands on the register
+    /// in use and the stream stops with a transfer error; returns whether it is still running, so
+    /// that it can be restarted otherwise.
+    fn swap(&mut self, bank: usize) -> bool {
+        let address = self.samples[bank * BANK_WORDS..].as_ptr() as u32;
+        let current = self.stream.current_target();
+        match current {
+            Target::Memory0 => self.stream.set_memory1_address(address),
+            Target::Memory1 => self.stream.set_memory0_address(address),
+        }
+        self.wait_for_switch(current);
+        match current {
+            Target::Memory0 => self.stream.set_memory0_address(address),
+            Target::Memory1 => self.stream.set_memory1_address(address),
+        }
+        self.stream.is_enabled()
+    }
+
+    /// Waits for the stream to reach the end of its transfer count and switch away from `current`,
+    /// or to have stopped, after which it never will.
+    fn wait_for_switch(&self, current: Target) {
+        while self.stream.current_target() == current && self.stream.is_enabled() {}
+    }
+
+    /// Stops the stream and recomputes everything into the start of the buffer, for the modes
+    /// and periods that do not double-buffer.
+    fn restart(&mut self) {
+        self.stop();
+        let sample_rate = match self.settings.mode {
+            Mode::Buffer => SAMPLE_RATE,
+            Mode::Dds => DDS_SAMPLE_RATE,
+            Mode::Arbitrary => self.arbitrary_rate,
+        };
+        self.set_sample_rate(sample_rate);
+
+        // calculate the new samples to be sent, and where they go
+        let outputs = self.settings.buffer_outputs();
+        let (loop_samples, config, dual) = match (self.settings.mode, self.settings.channel2) {
+            (Mode::Dds, _) => {
+                let output = self.settings.outputs[0];
+                // a pulse is timed against the table being played through once a period
+                let table_rate = (TABLE_LEN as u64 * self.settings.millihertz / 1000) as usize;
+                waveform::render(
+                    &mut self.table,
+                    output.waveform,
+                    amplitude(output.mvpp),
+                    code(output.offset_mv),
+                    table_rate,
+                );
+                let step = dds::tuning_word(self.settings.millihertz, DDS_SAMPLE_RATE);
+                self.accumulator.set_step(step);
+                self.accumulator.reset();
+                self.modulator = self.modulation.map(|modulation| {
+                    modulation::render_table(&mut self.modulation_table, &modulation);
+                    Modulator::new(&modulation, step, code(output.offset_mv), DDS_SAMPLE_RATE)
+                });
+                self.play(0, 2 * DDS_HALF);
+                (2 * DDS_HALF, &DDS_STREAM, false)
+            }
+            (Mode::Arbitrary, _) => (self.arbitrary_len, &STREAM, false),
+            (Mode::Buffer, Channel2::Off) => (
+                fill_samples(
+                    as_halfwords(&mut self.samples),
+                    self.settings.outputs[0],
+                    self.settings.millihertz,
+                ),
+                &STREAM,
+                false,
+            ),
+            (Mode::Buffer, Channel2::Linked) | (Mode::Buffer, Channel2::Independent) => (
+                fill_dual_samples(
+                    &mut self.samples,
+                    outputs,
+                    self.settings.phase,
+                    self.settings.millihertz,
+                ),
+                &DUAL_STREAM,
+                true,
+            ),
+        };
+
+        let address = self.samples.as_ptr() as u32;
+        self.start(address, loop_samples, config, dual);
+    }
+
+    /// Disables the stream and waits for it to finish what it was doing.
+    fn stop(&mut self) {
+        // first, disable the stream so the addresses can be updated
+        self.stream.disable();
+        // and wait for anything in progress to finish
+        while self.stream.is_enabled() {}
+        self.playing = None;
+    }
+
+    fn set_sample_rate(&self, sample_rate: usize) {
+        // subtract one because the timer iterates from zero through (and including) this value.
+        // The fixed rates divide the timer clock exactly; an uploaded one gets the nearest.
+        let ticks = (TIMER_CLOCK_RATE + sample_rate / 2) / sample_rate;
+        self.timer.start_trigger((ticks - 1) as u16);
+    }
+
+    /// Starts the stopped stream on `loop_samples` transfers from `address` (and from M1AR too, if
+    /// `config` double-buffers), to one or both DAC channels.
+    fn start(&mut self, address: u32, loop_samples: usize, config: &StreamConfig, dual: bool) {
+        // a stream with nothing to transfer would never start, so leave the output stopped
+        if loop_samples == 0 {
+            return;
+        }
+
+        // from and to address
+        let data_address = if dual {
+            self.dac.dual_data_address()
+        } else {
+            self.dac.channel1_data_address()
+        };
+        self.stream.set_peripheral_address(data_address);
+        self.stream.set_memory0_address(address);
+        // the number of samples is the number of memory transactions, whichever size they are
+        self.stream.set_transfer_count(loop_samples as u16);
+        // the datasheet says the stream's flags need to be clear before enabling it
+        self.stream.clear_flags();
+        self.stream.enable(config);
+        // and tell the DAC to trigger DMA transfers
+        if dual {
+            self.dac.enable_dual_dma(DacTrigger::Tim4);
+        } else {
+            self.dac.enable_channel1_dma(DacTrigger::Tim4);
+        }
+    }
+
+    /// Computes the next samples into whichever half of the buffer the DDS mode has just played.
+    /// Call it whenever the DMA stream interrupts; it does nothing in the buffer mode, or while
+    /// the stream is stopped for an upload.
+    pub fn refill(&mut self) {
+        if self.settings.mode != Mode::Dds || !self.stream.is_enabled() {
+            return;
+        }
+        while let Some(half) = self.stream.take_finished_half() {
+            let start = match half {
+                Half::First => 0,
+                Half::Second => DDS_HALF,
+            };
+            self.play(start, DDS_HALF);
+        }
+    }
+
+    /// Computes `len` samples from the accumulator into the buffer from `start`, moving the
+    /// frequency along any sweep and applying any modulation as it goes.
+    fn play(&mut self, start: usize, len: usize) {
+        let samples = &mut as_halfwords(&mut self.samples)[start..start + len];
+        for chunk in samples.chunks_mut(SWEEP_CHUNK) {
+            if let Some(sweep) = self.sweep {
+                let millihertz = sweep.millihertz_at(self.elapsed, DDS_SAMPLE_RATE);
+                let step = dds::tuning_word(millihertz, DDS_SAMPLE_RATE);
+                self.accumulator.set_step(step);
+                if let Some(modulator) = self.modulator.as_mut() {
+                    modulator.set_carrier_step(step);
+                }
+                self.elapsed += chunk.len() as u64;
+            }
+            match self.modulator.as_mut() {
+                Some(modulator) => modulator.fill(
+                    &mut self.accumulator,
+                    &self.table,
+                    &self.modulation_table,
+                    chunk,
+                ),
+                None => self.accumulator.fill(&self.table, chunk),
+            }
+        }
+    }
+}
+
+/// Part of the buffer as 16-bit samples for channel 1 alone.
+fn as_halfwords(samples: &mut [u32]) -> &mut [u16] {
+    // a u32 buffer is always suitably aligned for u16s, and every bit pattern is a valid u16
+    unsafe { core::slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u16, 2 * samples.len()) }
+}
+
+/// The number of samples in one loop through the buffer.  Noise uses all of it so that it repeats
+/// as rarely as possible.
+fn loop_length(waveforms: &[Waveform], millihertz: u64, capacity: usize) -> usize {
+    if waveforms.iter().all(|waveform| waveform.is_periodic()) {
+        min((SAMPLE_RATE as u64 * 1000 / millihertz) as usize, capacity)
+    } else {
+        capacity
+    }
+}
+
+/// The DAC code for `mv` millivolts, unrounded.
+fn code(mv: usize) -> f32 {
+    let volts = mv as f32 / 1000.0;
+    0x1000 /* 12 bits */ as f32 * volts / DAC_VOLTAGE
+}
+
+/// The swing either side of the offset for `mvpp`, in DAC codes.
+fn amplitude(mvpp: usize) -> f32 {
+    code(mvpp) / 2.0
+}
+
+#[inline(never)]
+fn fill_samples(samples: &mut [u16], output: Output, millihertz: u64) -> usize {
+    let loop_samples = loop_length(&[output.waveform], millihertz, samples.len());
+    waveform::render(
+        &mut samples[..loop_samples],
+        output.waveform,
+        amplitude(output.mvpp),
+        code(output.offset_mv),
+        SAMPLE_RATE,
+    );
+
+    loop_samples
+}
+
+/// Fills `samples` with channel 1 in the low half-word and channel 2, shifted ahead by `phase`
+/// degrees, in the high one, as DHR12RD wants them.
+#[inline(never)]
+fn fill_dual_samples(
+    samples: &mut [u32],
+    outputs: [Output; 2],
+    phase: usize,
+    millihertz: u64,
+) -> usize {
+    let loop_samples = loop_length(
+        &[outputs[0].waveform, outputs[1].waveform],
+        millihertz,
+        samples.len(),
+    );
+    let samples = &mut samples[..loop_samples];
+    if loop_samples == 0 {
+        return 0;
+    }
+
+    let channel1 = waveform::Samples::new(
+        loop_samples,
+        outputs[0].waveform,
+        amplitude(outputs[0].mvpp),
+        code(outputs[0].offset_mv),
+        SAMPLE_RATE,
+    );
+    for (sample, code) in samples.iter_mut().zip(channel1) {
+        *sample = code as u32;
+    }
+
+    // channel 2 leading means it reaches each point of its period `offset` samples earlier
+    let offset = (phase * loop_samples + 180) / 360 % loop_samples;
+    let channel2 = waveform::Samples::new(
+        loop_samples,
+        outputs[1].waveform,
+        amplitude(outputs[1].mvpp),
+        code(outputs[1].offset_mv),
+        SAMPLE_RATE,
+    );
+    for (index, code) in channel2.enumerate() {
+        samples[(index + loop_samples - offset) % loop_samples] |= (code as u32) << 16;
+    }
+
+    loop_samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::sim::{Peripheral, Recorder, Write, DUAL_DATA_OFFSET};
+
+    /// Where DHR12R1 is on the real DAC.
+    const DHR12R1: u32 = 0x4000_7408;
+
+    fn generator<'a>(
+        recorder: &'a Recorder,
+        stream: &'a Peripheral<'a>,
+    ) -> SignalGenerator<Peripheral<'a>, &'a Peripheral<'a>, Peripheral<'a>> {
+        SignalGenerator::new(
+            Peripheral::new(recorder, DHR12R1),
+            stream,
+            Peripheral::new(recorder, 0),
+        )
+    }
+
+    fn bank_address<D, S, P>(generator: &SignalGenerator<D, S, P>, bank: usize) -> u32 {
+        generator.samples[bank * BANK_WORDS..].as_ptr() as u32
+    }
+
+    fn enabled_config(recorder: &Recorder) -> Option<StreamConfig> {
+        (0..recorder.len()).find_map(|index| match recorder.get(index) {
+            Some(Write::DmaEnable(config)) => Some(config),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn channel1_plays_through_channel_7_on_tim4() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+
+        let config = enabled_config(&recorder).unwrap();
+        assert_eq!(config.channel, 7);
+        assert_eq!(config.memory_size, TransferSize::Bits16);
+        assert!(config.double_buffer);
+        assert!(recorder
+            .position(Write::DmaPeripheralAddress(DHR12R1))
+            .is_some());
+        // 1kHz at 10.5MHz, from an 84MHz timer clock divided by 8
+        assert!(recorder.position(Write::DmaTransferCount(10_500)).is_some());
+        assert!(recorder
+            .position(Write::TimerStartTrigger { reload: 7 })
+            .is_some());
+        assert!(recorder
+            .position(Write::DacEnableChannel1Dma(DacTrigger::Tim4))
+            .is_some());
+        // TSEL1 = 0b101 picks TIM4's TRGO in RM0090
+        assert_eq!(DacTrigger::Tim4 as u8, 0b101);
+    }
+
+    #[test]
+    fn stream_is_set_up_while_stopped() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+        recorder.clear();
+        generator.set_mode(Mode::Dds).unwrap();
+
+        let disable = recorder.position(Write::DmaDisable).unwrap();
+        let address = recorder
+            .position(Write::DmaPeripheralAddress(DHR12R1))
+            .unwrap();
+        let count = recorder
+            .position(Write::DmaTransferCount(2 * DDS_HALF as u16))
+            .unwrap();
+        let clear = recorder.position(Write::DmaClearFlags).unwrap();
+        let enable = recorder.position(Write::DmaEnable(DDS_STREAM)).unwrap();
+        let trigger = recorder
+            .position(Write::DacEnableChannel1Dma(DacTrigger::Tim4))
+            .unwrap();
+        assert!(disable < address && address < clear && count < clear);
+        assert!(clear < enable && enable < trigger);
+        // 84MHz divided by 168
+        assert!(recorder
+            .position(Write::TimerStartTrigger { reload: 167 })
+            .is_some());
+    }
+
+    #[test]
+    fn both_channels_write_dhr12rd() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+        recorder.clear();
+        // the change waits for the period playing to end
+        stream.finish_after_reads(1);
+        generator.set_channel2(Channel2::Linked).unwrap();
+
+        assert_eq!(enabled_config(&recorder), Some(DUAL_BANK_STREAM));
+        assert_eq!(DUAL_BANK_STREAM.channel, 7);
+        assert!(recorder
+            .position(Write::DmaPeripheralAddress(DHR12R1 + DUAL_DATA_OFFSET))
+            .is_some());
+        let clear = recorder.position(Write::DmaClearFlags).unwrap();
+        let trigger = recorder
+            .position(Write::DacEnableDualDma(DacTrigger::Tim4))
+            .unwrap();
+        assert!(recorder.position(Write::DmaDisable).unwrap() < clear && clear < trigger);
+        assert!(recorder
+            .position(Write::DacEnableChannel1Dma(DacTrigger::Tim4))
+            .is_none());
+    }
+
+    #[test]
+    fn swap_moves_both_registers_over_between_periods() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+        recorder.clear();
+        // the period ends while the swap waits for it
+        stream.finish_after_reads(2);
+        generator.set_mvpp(Channel::One, 1_000).unwrap();
+
+        let address = bank_address(&generator, 1);
+        let idle = recorder
+            .position(Write::DmaMemory1Address(address))
+            .unwrap();
+        let playing = recorder
+            .position(Write::DmaMemory0Address(address))
+            .unwrap();
+        assert!(idle < playing);
+        assert_eq!(recorder.position(Write::DmaDisable), None);
+        assert!(stream.is_enabled());
+    }
+
+    #[test]
+    fn swap_restarts_a_stream_stopped_by_the_period_ending() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+        recorder.clear();
+        // the period ends right after the swap reads CT, so it writes M1AR just as the stream
+        // moves to it
+        stream.finish_after_reads(1);
+        generator.set_mvpp(Channel::One, 1_000).unwrap();
+
+        let address = bank_address(&generator, 1);
+        let error = recorder
+            .position(Write::DmaMemory1Address(address))
+            .unwrap();
+        let restart = recorder.position(Write::DmaEnable(BANK_STREAM)).unwrap();
+        assert!(error < restart);
+        assert!(recorder.position(Write::DmaClearFlags).unwrap() < restart);
+        assert!(stream.is_enabled());
+    }
+
+    #[test]
+    fn short_periods_restart_instead_of_swapping() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+        stream.finish_after_reads(1);
+        // 21 samples a period
+        generator.set_frequency(500_000).unwrap();
+        recorder.clear();
+        stream.finish_after_reads(1);
+        generator.set_mvpp(Channel::One, 1_000).unwrap();
+
+        let disable = recorder.position(Write::DmaDisable).unwrap();
+        assert_eq!(recorder.get(0), Some(Write::DmaDisable));
+        assert!(disable < recorder.position(Write::DmaEnable(BANK_STREAM)).unwrap());
+        assert!(stream.is_enabled());
+    }
+
+    #[test]
+    fn arbitrary_mode_replays_only_an_intact_upload() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+        assert_eq!(generator.set_mode(Mode::Arbitrary), Err(Error::NoUpload));
+
+        generator.begin_upload();
+        generator.arbitrary_samples()[..100].fill(0x800);
+        generator.play_arbitrary(100, 48_000);
+        assert!(recorder.position(Write::DmaTransferCount(100)).is_some());
+        // settings that the arbitrary mode ignores leave the upload alone
+        generator.set_mvpp(Channel::One, 1_000).unwrap();
+        generator.set_mode(Mode::Arbitrary).unwrap();
+
+        generator.set_mode(Mode::Dds).unwrap();
+        assert_eq!(generator.set_mode(Mode::Arbitrary), Err(Error::NoUpload));
+        assert_eq!(generator.settings.mode, Mode::Dds);
+    }
+
+    #[test]
+    fn fm_cannot_deviate_past_nyquist() {
+        let recorder = Recorder::new();
+        let stream = Peripheral::new(&recorder, 0);
+        let mut generator = generator(&recorder, &stream);
+        generator.update();
+        let fm = Modulation {
+            kind: modulation::Kind::Fm,
+            waveform: Waveform::Sine,
+            millihertz: 5_000,
+            depth: 249_001,
+        };
+        // 1kHz plus 249.001kHz
+        assert_eq!(
+            generator.set_modulation(Some(fm)),
+            Err(Error::DeviationTooLarge)
+        );
+        assert_eq!(generator.modulation(), None);
+
+        let fm = Modulation {
+            depth: 249_000,
+            ..fm
+        };
+        generator.set_modulation(Some(fm)).unwrap();
+        assert_eq!(
+            generator.set_frequency(1_001),
+            Err(Error::DeviationTooLarge)
+        );
+        let sweep = Sweep {
+            start_millihertz: 1_000_000,
+            stop_millihertz: 2_000_000,
+            duration_ms: 1000,
+            shape: crate::sweep::Shape::Linear,
+            repeat: false,
+        };
+        assert_eq!(generator.start_sweep(sweep), Err(Error::DeviationTooLarge));
+        assert_eq!(generator.modulation(), Some(fm));
+    }
+}
@@ -0,0 +1,125 @@
+//! Direct digital synthesis: a 32-bit phase accumulator stepping through one period of a waveform
+//! in a fixed table, so the frequency is set by the step size rather than by how many samples fit
+//! in a period.
+
+/// The table holds 2^TABLE_BITS samples, indexed by the top bits of the phase.  4096 samples keep
+/// the table's quantization below that of the 12-bit DAC.
+pub const TABLE_BITS: u32 = 12;
+pub const TABLE_LEN: usize = 1 << TABLE_BITS;
+
+/// The phase step that produces `millihertz` at `sample_rate`, rounded to the nearest.  Anything
+/// past Nyquist is limited to it.  The arithmetic is 128-bit, since Nyquist at sample rates above
+/// about 8.6MHz is already 2^32 millihertz.
+pub fn tuning_word(millihertz: u64, sample_rate: usize) -> u32 {
+    let divisor = sample_rate as u128 * 1000;
+    let millihertz = core::cmp::min(millihertz as u128, divisor / 2);
+    (((millihertz << 32) + divisor / 2) / divisor) as u32
+}
+
+/// The frequency `word` actually produces at `sample_rate`, in millihertz, rounded down.
+pub fn frequency(word: u32, sample_rate: usize) -> u64 {
+    ((word as u128 * sample_rate as u128 * 1000) >> 32) as u64
+}
+
+/// The sample of `table` at `phase`, where a full turn of the `u32` is one period.
+pub fn lookup(table: &[u16; TABLE_LEN], phase: u32) -> u16 {
+    table[(phase >> (32 - TABLE_BITS)) as usize]
+}
+
+pub struct Accumulator {
+    phase: u32,
+    step: u32,
+}
+
+impl Accumulator {
+    pub const fn new() -> Self {
+        Self { phase: 0, step: 0 }
+    }
+
+    /// Changes the frequency without a jump in phase.
+    pub fn set_step(&mut self, step: u32) {
+        self.step = step;
+    }
+
+    /// Starts again from the beginning of the table.
+    pub fn reset(&mut self) {
+        self.phase = 0;
+    }
+
+    /// The phase of the next sample, moving on to the one after.
+    pub fn advance(&mut self) -> u32 {
+        let phase = self.phase;
+        self.phase = self.phase.wrapping_add(self.step);
+        phase
+    }
+
+    /// Fills `samples` with the next samples from `table`, carrying the phase over to the next
+    /// call.
+    pub fn fill(&mut self, table: &[u16; TABLE_LEN], samples: &mut [u16]) {
+        for sample in samples.iter_mut() {
+            *sample = lookup(table, self.advance());
+        }
+    }
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuning_word_rounds_to_the_nearest_step() {
+        // 2^32 * 1kHz / 500kHz = 8589934.592
+        let word = tuning_word(1_000_000, 500_000);
+        assert_eq!(word, 8_589_935);
+        assert_eq!(frequency(word, 500_000), 1_000_000);
+        assert_eq!(tuning_word(1, 500_000), 9);
+    }
+
+    #[test]
+    fn tuning_word_stops_at_nyquist() {
+        assert_eq!(tuning_word(250_000_000, 500_000), 1 << 31);
+        assert_eq!(tuning_word(u64::MAX >> 32, 500_000), 1 << 31);
+        assert_eq!(tuning_word(u64::MAX, 500_000), 1 << 31);
+    }
+
+    #[test]
+    fn fast_sample_rates_do_not_overflow() {
+        // the buffer mode's rate, where Nyquist is 5.25e9 millihertz
+        assert_eq!(tuning_word(u64::MAX, 10_500_000), 1 << 31);
+        assert_eq!(frequency(1 << 31, 10_500_000), 5_250_000_000);
+        // 2^32 * 1MHz / 10.5MHz = 409044504.38
+        let word = tuning_word(1_000_000_000, 10_500_000);
+        assert_eq!(word, 409_044_504);
+        assert_eq!(frequency(word, 10_500_000), 999_999_999);
+    }
+
+    #[test]
+    fn accumulator_carries_its_phase_between_fills() {
+        let mut table = [0; TABLE_LEN];
+        for (index, sample) in table.iter_mut().enumerate() {
+            *sample = index as u16;
+        }
+        let mut accumulator = Accumulator::new();
+        // three table entries a sample
+        accumulator.set_step(3 << (32 - TABLE_BITS));
+        let mut samples = [0; 4];
+        accumulator.fill(&table, &mut samples);
+        assert_eq!(samples, [0, 3, 6, 9]);
+        accumulator.fill(&table, &mut samples);
+        assert_eq!(samples, [12, 15, 18, 21]);
+
+        // wrapping past the end of the table goes back to its start
+        accumulator.set_step(1 << 31);
+        accumulator.fill(&table, &mut samples);
+        assert_eq!(samples, [24, 2072, 24, 2072]);
+
+        accumulator.reset();
+        assert_eq!(accumulator.advance(), 0);
+    }
+}
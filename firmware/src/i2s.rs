@@ -0,0 +1,63 @@
+//! I2S setup at the register level, since the HAL only goes as far as picking the I2S clock.
+
+use stm32f4xx_hal::stm32;
+
+pub use stm32::spi1::i2scfgr::{
+    CHLEN_A as ChannelLength, CKPOL_A as ClockPolarity, DATLEN_A as DataLength, I2SCFG_A as Mode,
+    I2SSTD_A as Standard,
+};
+
+use crate::regs::I2sRegisters;
+
+/// The SPI_I2SPR settings, which only matter in master modes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prescaler {
+    pub i2sdiv: u8,
+    pub odd: bool,
+    /// Output the master clock, which fixes the sample rate at I2SCLK / (256 * (2 * I2SDIV + ODD))
+    pub mckoe: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub mode: Mode,
+    pub standard: Standard,
+    pub polarity: ClockPolarity,
+    pub data_length: DataLength,
+    pub channel_length: ChannelLength,
+    pub prescaler: Option<Prescaler>,
+}
+
+impl Config {
+    /// Switches `spi` (an SPI or I2Sx_ext block) to I2S mode with these settings, then enables it.
+    pub fn apply<S: I2sRegisters>(&self, spi: &S) {
+        spi.write_config(self);
+        if let Some(prescaler) = self.prescaler {
+            spi.write_prescaler(&prescaler);
+        }
+        spi.enable();
+    }
+}
+
+/// Turns on the SPI2 clock; the HAL only does this when it is used as SPI.
+pub fn enable_spi2() {
+    unsafe {
+        let rcc = &*stm32::RCC::ptr();
+        rcc.apb1enr.modify(|_r, w| w.spi2en().set_bit());
+    }
+}
+
+/// Programs PLLI2S's N and R, starts it, and waits for it to lock.  The F407's PLLI2S divides its
+/// input by the main PLL's PLLM, so `pll.m` has to be whatever the HAL already chose.
+pub fn start_plli2s(pll: &clock_model::PllI2s) {
+    unsafe {
+        let rcc = &*stm32::RCC::ptr();
+        debug_assert_eq!(rcc.pllcfgr.read().pllm().bits() as u64, pll.m);
+        rcc.plli2scfgr.modify(|_r, w| {
+            w.plli2sn().bits(pll.n as u16);
+            w.plli2sr().bits(pll.r as u8)
+        });
+        rcc.cr.modify(|_r, w| w.plli2son().set_bit());
+        while !rcc.cr.read().plli2srdy().bit() {}
+    }
+}
@@ -0,0 +1,29 @@
+//! Debug output over the ITM's stimulus port 0, which the SWO pin carries to the debugger.  The
+//! debugger sets up the trace clock and enables the port while it is capturing (OpenOCD's
+//! `itm port 0 on`); until then, nothing here writes to it.
+
+use core::fmt;
+
+use cortex_m::peripheral::itm::Stim;
+use cortex_m::peripheral::ITM;
+
+/// Stimulus port 0, if the debugger has enabled it.  A port that is not enabled never reports
+/// its FIFO as ready, which is how `iprintln!` hangs when no debugger is attached.
+pub fn port(itm: &mut ITM) -> Option<&mut Stim> {
+    // ITMENA in TCR, and port 0 in the first TER
+    let enabled = itm.tcr.read() & 1 != 0 && itm.ter[0].read() & 1 != 0;
+    if enabled {
+        Some(&mut itm.stim[0])
+    } else {
+        None
+    }
+}
+
+/// Writes `args` and a newline to port 0, or nothing if the debugger is not listening.  Use it with
+/// `format_args!`.
+pub fn println(itm: &mut ITM, args: fmt::Arguments) {
+    if let Some(stim) = port(itm) {
+        cortex_m::itm::write_fmt(stim, args);
+        cortex_m::itm::write_str(stim, "\n");
+    }
+}
@@ -0,0 +1,24 @@
+//! Drivers shared by the experiments in `src/bin`, which all run on an STM32F4DISCOVERY board.
+//! Each binary picks the pieces it needs and wires them to the pins it uses.
+//!
+//! The drivers' tests run on the host against `regs::sim`, with
+//! `cargo test --lib --target x86_64-unknown-linux-gnu`, since `.cargo/config` builds for the board
+//! by default.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod codec;
+pub mod command;
+pub mod dac;
+pub mod dds;
+pub mod i2s;
+pub mod itm;
+pub mod modulation;
+pub mod regs;
+pub mod sweep;
+pub mod upload;
+pub mod usb;
+pub mod waveform;
+
+/// The crystal on the STM32F4DISCOVERY board.
+pub const HSE: clock_model::Hz = clock_model::Hz::new(8_000_000);
@@ -0,0 +1,216 @@
+//! Amplitude, frequency and phase modulation of the DDS carrier by a second, slower waveform, which
+//! runs from its own phase accumulator and table.
+
+use core::cmp::min;
+
+use crate::dds::{self, Accumulator, TABLE_LEN};
+use crate::waveform::{self, Waveform, MIDSCALE};
+
+/// The modulating waveform's table swings this far either side of midscale, so that it reads back
+/// as -1 to 1.
+const TABLE_AMPLITUDE: f32 = 2047.0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// `depth` is the modulation index in percent, up to 100
+    Am,
+    /// `depth` is the peak frequency deviation in Hz
+    Fm,
+    /// `depth` is the peak phase deviation in degrees
+    Pm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Modulation {
+    pub kind: Kind,
+    /// The modulating waveform
+    pub waveform: Waveform,
+    pub millihertz: u64,
+    pub depth: u32,
+}
+
+/// Fills `table` with one period of the modulating waveform for `modulation`.
+pub fn render_table(table: &mut [u16; TABLE_LEN], modulation: &Modulation) {
+    // a pulse is timed against the table being played through once a period
+    let table_rate = (TABLE_LEN as u64 * modulation.millihertz / 1000) as usize;
+    waveform::render(
+        table,
+        modulation.waveform,
+        TABLE_AMPLITUDE,
+        MIDSCALE,
+        table_rate,
+    );
+}
+
+/// The running state of a modulation, at one sample rate.
+pub struct Modulator {
+    kind: Kind,
+    accumulator: Accumulator,
+    /// The unmodulated carrier's step, which FM deviates from
+    carrier_step: u32,
+    /// The DAC code the carrier swings around, which AM scales it towards
+    carrier_center: f32,
+    /// How far the modulating waveform's peak moves the carrier: the fraction of its amplitude
+    /// for AM, the change in step for FM, and for PM the change in phase, where 2^32 is a full
+    /// turn
+    deviation: f32,
+}
+
+impl Modulator {
+    pub fn new(
+        modulation: &Modulation,
+        carrier_step: u32,
+        carrier_center: f32,
+        sample_rate: usize,
+    ) -> Self {
+        let mut accumulator = Accumulator::new();
+        accumulator.set_step(dds::tuning_word(modulation.millihertz, sample_rate));
+        let deviation = match modulation.kind {
+            Kind::Am => min(modulation.depth, 100) as f32 / 100.0,
+            Kind::Fm => dds::tuning_word(modulation.depth as u64 * 1000, sample_rate) as f32,
+            Kind::Pm => modulation.depth as f32 / 360.0 * 4_294_967_296.0,
+        };
+
+        Self {
+            kind: modulation.kind,
+            accumulator,
+            carrier_step,
+            carrier_center,
+            deviation,
+        }
+    }
+
+    /// Moves the frequency that FM deviates from, as a sweep does.
+    pub fn set_carrier_step(&mut self, carrier_step: u32) {
+        self.carrier_step = carrier_step;
+    }
+
+    /// Fills `samples` from the carrier's `table` through `carrier`, modulated by the waveform in
+    /// `table`, carrying both phases over to the next call.
+    pub fn fill(
+        &mut self,
+        carrier: &mut Accumulator,
+        carrier_table: &[u16; TABLE_LEN],
+        table: &[u16; TABLE_LEN],
+        samples: &mut [u16],
+    ) {
+        for sample in samples.iter_mut() {
+            let modulating = dds::lookup(table, self.accumulator.advance()) as f32 - MIDSCALE;
+            let modulating = modulating / TABLE_AMPLITUDE;
+
+            *sample = match self.kind {
+                Kind::Am => {
+                    // scaled down so that the peaks of the modulated carrier are the carrier's own
+                    let carrier =
+                        dds::lookup(carrier_table, carrier.advance()) as f32 - self.carrier_center;
+                    let gain = (1.0 + self.deviation * modulating) / (1.0 + self.deviation);
+                    (self.carrier_center + carrier * gain) as u16
+                }
+                Kind::Fm => {
+                    let step = self.carrier_step as i64 + (self.deviation * modulating) as i64;
+                    carrier.set_step(step as u32);
+                    dds::lookup(carrier_table, carrier.advance())
+                }
+                Kind::Pm => {
+                    let offset = (self.deviation * modulating) as i64 as u32;
+                    dds::lookup(carrier_table, carrier.advance().wrapping_add(offset))
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 4096;
+    /// A quarter of the sample rate, so the modulating waveform is read at four points
+    const MODULATING_MILLIHERTZ: u64 = 1_024_000;
+    /// One table entry a sample, and 1Hz at `SAMPLE_RATE`
+    const ONE_ENTRY: u32 = 1 << (32 - dds::TABLE_BITS);
+
+    /// A modulating table that reads back as 0, 1, 0, -1 at a quarter of the sample rate.
+    fn modulating_table() -> [u16; TABLE_LEN] {
+        let mut table = [MIDSCALE as u16; TABLE_LEN];
+        table[TABLE_LEN / 4] = 0xfff;
+        table[3 * TABLE_LEN / 4] = 1;
+        table
+    }
+
+    /// A carrier table whose samples are their own index, to show where the carrier's phase is.
+    fn ramp_table() -> [u16; TABLE_LEN] {
+        let mut table = [0; TABLE_LEN];
+        for (index, sample) in table.iter_mut().enumerate() {
+            *sample = index as u16;
+        }
+        table
+    }
+
+    fn modulate(kind: Kind, depth: u32, carrier_table: &[u16; TABLE_LEN], center: f32) -> [u16; 8] {
+        let modulation = Modulation {
+            kind,
+            waveform: Waveform::Sine,
+            millihertz: MODULATING_MILLIHERTZ,
+            depth,
+        };
+        let mut modulator = Modulator::new(&modulation, ONE_ENTRY, center, SAMPLE_RATE);
+        let mut carrier = Accumulator::new();
+        carrier.set_step(ONE_ENTRY);
+        let mut samples = [0; 8];
+        modulator.fill(
+            &mut carrier,
+            carrier_table,
+            &modulating_table(),
+            &mut samples,
+        );
+        samples
+    }
+
+    #[test]
+    fn am_scales_the_carrier_towards_its_center() {
+        let carrier = [3048; TABLE_LEN];
+        assert_eq!(modulate(Kind::Am, 0, &carrier, 2048.0), [3048; 8]);
+        assert_eq!(
+            modulate(Kind::Am, 50, &carrier, 2048.0),
+            [2714, 3048, 2714, 2381, 2714, 3048, 2714, 2381]
+        );
+        assert_eq!(
+            modulate(Kind::Am, 100, &carrier, 2048.0),
+            [2548, 3048, 2548, 2048, 2548, 3048, 2548, 2048]
+        );
+    }
+
+    #[test]
+    fn full_scale_am_stays_within_the_dac_range() {
+        let top = [0xfff; TABLE_LEN];
+        assert_eq!(
+            modulate(Kind::Am, 100, &top, 2048.0),
+            [3071, 0xfff, 3071, 2048, 3071, 0xfff, 3071, 2048]
+        );
+        let bottom = [1; TABLE_LEN];
+        assert_eq!(
+            modulate(Kind::Am, 100, &bottom, 2048.0),
+            [1024, 1, 1024, 2048, 1024, 1, 1024, 2048]
+        );
+    }
+
+    #[test]
+    fn fm_changes_the_step() {
+        let ramp = ramp_table();
+        assert_eq!(modulate(Kind::Fm, 0, &ramp, 0.0), [0, 1, 2, 3, 4, 5, 6, 7]);
+        // each step is the carrier's one entry, plus one entry per hertz of deviation
+        assert_eq!(modulate(Kind::Fm, 1, &ramp, 0.0), [0, 1, 3, 4, 4, 5, 7, 8]);
+    }
+
+    #[test]
+    fn pm_offsets_the_phase() {
+        let ramp = ramp_table();
+        assert_eq!(modulate(Kind::Pm, 0, &ramp, 0.0), [0, 1, 2, 3, 4, 5, 6, 7]);
+        // 90 degrees is a quarter of the table either way
+        assert_eq!(
+            modulate(Kind::Pm, 90, &ramp, 0.0),
+            [0, 1025, 2, 3075, 4, 1029, 6, 3079]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! The peripheral register accesses the drivers need, as traits, so that the drivers can run
+//! against the real PAC registers on the board or against a simulation that records every write.
+//! Each method is one register write (or read) from the reference manual, so the simulation sees
+//! the same sequence the hardware would.  The methods take `&self`, like the PAC's own register
+//! accessors.
+
+pub mod pac;
+pub mod sim;
+
+use crate::i2s;
+
+/// The DAC's external trigger selection, as encoded in TSELx of DAC_CR per RM0090.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DacTrigger {
+    Tim6 = 0,
+    Tim8 = 1,
+    Tim7 = 2,
+    Tim5 = 3,
+    Tim2 = 4,
+    Tim4 = 5,
+    Exti9 = 6,
+    Software = 7,
+}
+
+pub trait Dac {
+    /// The address of DHR12R1, for a DMA stream to write channel 1 samples to
+    fn channel1_data_address(&self) -> u32;
+    /// Writes DAC_CR to enable channel 1, converting on `trigger` and requesting DMA on each one
+    fn enable_channel1_dma(&self, trigger: DacTrigger);
+    /// The address of DHR12RD, which takes channel 1 in its low half-word and channel 2 in its high
+    /// one, so that one 32-bit DMA transfer updates both
+    fn dual_data_address(&self) -> u32;
+    /// Writes DAC_CR to enable both channels converting on `trigger`, with only channel 1
+    /// requesting DMA, since one transfer to DHR12RD feeds both
+    fn enable_dual_dma(&self, trigger: DacTrigger);
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransferSize {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+/// The fields of DMA_SxCR that the drivers set; everything else stays at its reset value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StreamConfig {
+    /// CHSEL, which picks the peripheral request that drives the stream
+    pub channel: u8,
+    pub memory_size: TransferSize,
+    pub peripheral_size: TransferSize,
+    pub memory_increment: bool,
+    pub circular: bool,
+    pub double_buffer: bool,
+    /// HTIE and TCIE, to hear about each half of a circular buffer as soon as it has been read
+    pub transfer_interrupts: bool,
+}
+
+/// The memory register a double-buffered stream is reading through, from CT in DMA_SxCR.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    Memory0,
+    Memory1,
+}
+
+/// One half of a circular DMA buffer, split at the midpoint of the transfer count.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Half {
+    First,
+    Second,
+}
+
+/// One memory-to-peripheral DMA stream.
+pub trait DmaStream {
+    /// Clears EN in DMA_SxCR.  The stream keeps going until the current transfer is done, so wait
+    /// for `is_enabled()` to read false before changing anything else.
+    fn disable(&self);
+    fn is_enabled(&self) -> bool;
+    /// DMA_SxPAR
+    fn set_peripheral_address(&self, address: u32);
+    /// DMA_SxM0AR, which a double-buffered stream lets through while it reads from M1AR
+    fn set_memory0_address(&self, address: u32);
+    /// DMA_SxM1AR, which a double-buffered stream lets through while it reads from M0AR
+    fn set_memory1_address(&self, address: u32);
+    /// Which memory register a double-buffered stream is reading from; it switches each time it
+    /// reaches the end of the transfer count
+    fn current_target(&self) -> Target;
+    /// DMA_SxNDTR, in transfers of the peripheral size
+    fn set_transfer_count(&self, count: u16);
+    /// Clears every one of this stream's flags in DMA_LIFCR/HIFCR, which must be done before
+    /// enabling it
+    fn clear_flags(&self);
+    /// Writes DMA_SxCR with `config` and EN set
+    fn enable(&self, config: &StreamConfig);
+    /// Which half of the buffer the stream has finished reading since this was last called, from
+    /// HTIF (the first) or TCIF (the second), clearing the flag it reports.  If both are set, the
+    /// second half is reported first, and the first on the next call.
+    fn take_finished_half(&self) -> Option<Half>;
+}
+
+/// Lets a driver borrow a stream, so that a test can keep hold of a simulated one.
+impl<T: DmaStream> DmaStream for &T {
+    fn disable(&self) {
+        (*self).disable()
+    }
+
+    fn is_enabled(&self) -> bool {
+        (*self).is_enabled()
+    }
+
+    fn set_peripheral_address(&self, address: u32) {
+        (*self).set_peripheral_address(address)
+    }
+
+    fn set_memory0_address(&self, address: u32) {
+        (*self).set_memory0_address(address)
+    }
+
+    fn set_memory1_address(&self, address: u32) {
+        (*self).set_memory1_address(address)
+    }
+
+    fn current_target(&self) -> Target {
+        (*self).current_target()
+    }
+
+    fn set_transfer_count(&self, count: u16) {
+        (*self).set_transfer_count(count)
+    }
+
+    fn clear_flags(&self) {
+        (*self).clear_flags()
+    }
+
+    fn enable(&self, config: &StreamConfig) {
+        (*self).enable(config)
+    }
+
+    fn take_finished_half(&self) -> Option<Half> {
+        (*self).take_finished_half()
+    }
+}
+
+/// A general-purpose timer used only to pace other peripherals with its update event.
+pub trait Timer {
+    /// Writes ARR, routes the update event to TRGO, and starts the counter.  PSC stays at its reset
+    /// value of 0, so the timer updates every `reload + 1` ticks.  Calling it again while the timer
+    /// runs just changes the rate.
+    fn start_trigger(&self, reload: u16);
+}
+
+/// An SPI peripheral (or I2Sx_ext block) in I2S mode.
+pub trait I2sRegisters {
+    /// Writes SPI_I2SCFGR with I2SMOD set and the peripheral still disabled
+    fn write_config(&self, config: &i2s::Config);
+    /// SPI_I2SPR
+    fn write_prescaler(&self, prescaler: &i2s::Prescaler);
+    /// Sets I2SE, after which the configuration must not change
+    fn enable(&self);
+}
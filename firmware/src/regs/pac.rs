@@ -0,0 +1,171 @@
+//! The register traits on the real peripherals, through the PAC.
+
+use stm32f4xx_hal::stm32;
+
+use super::{
+    Dac, DacTrigger, DmaStream, Half, I2sRegisters, StreamConfig, Target, Timer, TransferSize,
+};
+use crate::i2s;
+
+impl Dac for stm32::DAC {
+    fn channel1_data_address(&self) -> u32 {
+        &self.dhr12r1 as *const _ as u32
+    }
+
+    fn enable_channel1_dma(&self, trigger: DacTrigger) {
+        self.cr.write(|w| {
+            w.dmaen1().enabled();
+            // the datasheet values for TSEL do not match the stm32f407 crate, so I'm trusting the datasheet.
+            unsafe { w.tsel1().bits(trigger as u8) };
+            w.ten1().enabled();
+            w.en1().set_bit()
+        });
+    }
+
+    fn dual_data_address(&self) -> u32 {
+        &self.dhr12rd as *const _ as u32
+    }
+
+    fn enable_dual_dma(&self, trigger: DacTrigger) {
+        self.cr.write(|w| {
+            w.dmaen1().enabled();
+            unsafe {
+                w.tsel1().bits(trigger as u8);
+                w.tsel2().bits(trigger as u8)
+            };
+            w.ten1().enabled();
+            w.ten2().enabled();
+            w.en1().set_bit();
+            w.en2().set_bit()
+        });
+    }
+}
+
+/// DMA1 stream 5, which is the one DAC channel 1 requests on (with channel 7).
+pub struct Dma1Stream5(pub stm32::DMA1);
+
+impl DmaStream for Dma1Stream5 {
+    fn disable(&self) {
+        self.0.st[5].cr.write(|w| w.en().clear_bit());
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.0.st[5].cr.read().en().bit()
+    }
+
+    fn set_peripheral_address(&self, address: u32) {
+        self.0.st[5].par.write(|w| unsafe { w.bits(address) });
+    }
+
+    fn set_memory0_address(&self, address: u32) {
+        self.0.st[5].m0ar.write(|w| unsafe { w.bits(address) });
+    }
+
+    fn set_memory1_address(&self, address: u32) {
+        self.0.st[5].m1ar.write(|w| unsafe { w.bits(address) });
+    }
+
+    fn current_target(&self) -> Target {
+        if self.0.st[5].cr.read().ct().bit() {
+            Target::Memory1
+        } else {
+            Target::Memory0
+        }
+    }
+
+    fn set_transfer_count(&self, count: u16) {
+        self.0.st[5].ndtr.write(|w| w.ndt().bits(count));
+    }
+
+    fn clear_flags(&self) {
+        self.0.hifcr.write(|w| {
+            w.ctcif5().set_bit();
+            w.chtif5().set_bit();
+            w.cteif5().set_bit();
+            w.cdmeif5().set_bit();
+            w.cfeif5().set_bit()
+        });
+    }
+
+    fn enable(&self, config: &StreamConfig) {
+        self.0.st[5].cr.write(|w| {
+            w.chsel().bits(config.channel);
+            w.mburst().single(); // single-word transfers
+            w.pburst().single();
+            w.dbm().bit(config.double_buffer);
+            match config.memory_size {
+                TransferSize::Bits8 => w.msize().bits8(),
+                TransferSize::Bits16 => w.msize().bits16(),
+                TransferSize::Bits32 => w.msize().bits32(),
+            };
+            match config.peripheral_size {
+                TransferSize::Bits8 => w.psize().bits8(),
+                TransferSize::Bits16 => w.psize().bits16(),
+                TransferSize::Bits32 => w.psize().bits32(),
+            };
+            w.minc().bit(config.memory_increment);
+            w.pinc().fixed(); // keep writing the same peripheral register
+            w.circ().bit(config.circular);
+            w.htie().bit(config.transfer_interrupts);
+            w.tcie().bit(config.transfer_interrupts);
+            w.dir().memory_to_peripheral();
+            w.pfctrl().dma(); // DMA controller sets the buffer size
+            w.en().enabled()
+        });
+    }
+
+    fn take_finished_half(&self) -> Option<Half> {
+        let flags = self.0.hisr.read();
+        if flags.tcif5().bit() {
+            self.0.hifcr.write(|w| w.ctcif5().set_bit());
+            Some(Half::Second)
+        } else if flags.htif5().bit() {
+            self.0.hifcr.write(|w| w.chtif5().set_bit());
+            Some(Half::First)
+        } else {
+            None
+        }
+    }
+}
+
+impl Timer for stm32::TIM4 {
+    fn start_trigger(&self, reload: u16) {
+        self.arr.write(|w| w.arr().bits(reload));
+        self.cr2.write(|w| w.mms().update()); // send a TRGO event when the timer updates
+        self.cr1.write(|w| w.cen().set_bit());
+    }
+}
+
+/// The SPI blocks that can do I2S, and their full-duplex extensions, all share SPI1's registers.
+macro_rules! i2s_registers {
+    ($($spi:ty),*) => {
+        $(
+            impl I2sRegisters for $spi {
+                fn write_config(&self, config: &i2s::Config) {
+                    self.i2scfgr.write(|w| {
+                        w.i2smod().set_bit();
+                        w.i2scfg().variant(config.mode);
+                        w.i2sstd().variant(config.standard);
+                        w.ckpol().variant(config.polarity);
+                        w.datlen().variant(config.data_length);
+                        w.chlen().variant(config.channel_length)
+                    });
+                }
+
+                fn write_prescaler(&self, prescaler: &i2s::Prescaler) {
+                    self.i2spr.write(|w| {
+                        w.mckoe().bit(prescaler.mckoe);
+                        unsafe { w.i2sdiv().bits(prescaler.i2sdiv) };
+                        w.odd().bit(prescaler.odd)
+                    });
+                }
+
+                fn enable(&self) {
+                    self.i2scfgr.modify(|_r, w| w.i2se().set_bit());
+                }
+            }
+        )*
+    };
+}
+
+i2s_registers!(stm32::SPI2, stm32::SPI3, stm32::I2S2EXT, stm32::I2S3EXT);
@@ -0,0 +1,268 @@
+//! Stand-ins for the peripherals that record each register write instead of touching hardware, so
+//! that the drivers' register sequences can be checked on the host.
+
+use core::cell::{Cell, RefCell};
+
+use super::{Dac, DacTrigger, DmaStream, Half, I2sRegisters, StreamConfig, Target, Timer};
+use crate::i2s;
+
+/// How many writes a `Recorder` holds before it panics.
+pub const CAPACITY: usize = 64;
+
+/// How far DHR12RD is past DHR12R1 in the real DAC, which the simulated one copies.
+pub const DUAL_DATA_OFFSET: u32 = 0x18;
+
+/// How many times CT can be read without the stream switching before the simulation decides that
+/// the driver is waiting for a switch the test never scheduled.
+const MAX_WAITING_READS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Write {
+    DacEnableChannel1Dma(DacTrigger),
+    DacEnableDualDma(DacTrigger),
+    DmaDisable,
+    DmaPeripheralAddress(u32),
+    DmaMemory0Address(u32),
+    DmaMemory1Address(u32),
+    DmaTransferCount(u16),
+    DmaClearFlags,
+    DmaEnable(StreamConfig),
+    DmaClearHalf(Half),
+    TimerStartTrigger { reload: u16 },
+    I2sConfig(i2s::Config),
+    I2sPrescaler(i2s::Prescaler),
+    I2sEnable,
+}
+
+/// The writes to any number of simulated peripherals, in the order they happened.
+pub struct Recorder {
+    writes: RefCell<[Option<Write>; CAPACITY]>,
+    len: Cell<usize>,
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self {
+            writes: RefCell::new([None; CAPACITY]),
+            len: Cell::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Write> {
+        self.writes.borrow().get(index).copied().flatten()
+    }
+
+    /// The index of the first write equal to `write`, for checking the order of writes.
+    pub fn position(&self, write: Write) -> Option<usize> {
+        (0..self.len()).find(|&index| self.get(index) == Some(write))
+    }
+
+    pub fn clear(&self) {
+        self.len.set(0);
+    }
+
+    fn record(&self, write: Write) {
+        let len = self.len.get();
+        assert!(len < CAPACITY, "simulated register writes overflowed");
+        self.writes.borrow_mut()[len] = Some(write);
+        self.len.set(len + 1);
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A simulated peripheral of any kind, which reports writes to `recorder`.
+pub struct Peripheral<'a> {
+    recorder: &'a Recorder,
+    /// What the peripheral reports as the address of its data register
+    data_address: u32,
+    enabled: Cell<bool>,
+    double_buffer: Cell<bool>,
+    /// The halves a simulated stream has finished, as (first, second)
+    finished: Cell<(bool, bool)>,
+    target: Cell<Target>,
+    /// Reads of CT left until the stream finishes its buffer, if a test has scheduled that
+    reads_until_finish: Cell<Option<usize>>,
+    /// Reads of CT since it last changed
+    waiting_reads: Cell<usize>,
+}
+
+impl<'a> Peripheral<'a> {
+    pub fn new(recorder: &'a Recorder, data_address: u32) -> Self {
+        Self {
+            recorder,
+            data_address,
+            enabled: Cell::new(false),
+            double_buffer: Cell::new(false),
+            finished: Cell::new((false, false)),
+            target: Cell::new(Target::Memory0),
+            reads_until_finish: Cell::new(None),
+            waiting_reads: Cell::new(0),
+        }
+    }
+
+    /// Pretends the simulated DMA stream has finished reading `half` of its buffer.  Finishing the
+    /// second half reaches the end of the transfer count, where a double-buffered stream switches
+    /// to its other memory register; nothing else changes CT.
+    pub fn finish(&self, half: Half) {
+        let (first, second) = self.finished.get();
+        self.finished.set(match half {
+            Half::First => (true, second),
+            Half::Second => (first, true),
+        });
+        if half == Half::Second && self.enabled.get() && self.double_buffer.get() {
+            self.target.set(match self.target.get() {
+                Target::Memory0 => Target::Memory1,
+                Target::Memory1 => Target::Memory0,
+            });
+            self.waiting_reads.set(0);
+        }
+    }
+
+    /// Finishes the second half just after the driver has read CT `reads` more times, to land the
+    /// switch at a chosen point of a register sequence.
+    pub fn finish_after_reads(&self, reads: usize) {
+        if reads == 0 {
+            self.finish(Half::Second);
+        } else {
+            self.reads_until_finish.set(Some(reads));
+        }
+    }
+
+    /// Writing the memory register a double-buffered stream is reading from is a transfer error,
+    /// which disables the stream, as RM0090 describes.
+    fn write_memory_address(&self, register: Target, write: Write) {
+        self.recorder.record(write);
+        if self.enabled.get() && self.double_buffer.get() && self.target.get() == register {
+            self.enabled.set(false);
+        }
+    }
+}
+
+impl<'a> Dac for Peripheral<'a> {
+    fn channel1_data_address(&self) -> u32 {
+        self.data_address
+    }
+
+    fn enable_channel1_dma(&self, trigger: DacTrigger) {
+        self.recorder.record(Write::DacEnableChannel1Dma(trigger));
+    }
+
+    fn dual_data_address(&self) -> u32 {
+        self.data_address + DUAL_DATA_OFFSET
+    }
+
+    fn enable_dual_dma(&self, trigger: DacTrigger) {
+        self.recorder.record(Write::DacEnableDualDma(trigger));
+    }
+}
+
+impl<'a> DmaStream for Peripheral<'a> {
+    /// The simulated stream stops as soon as it is disabled.
+    fn disable(&self) {
+        self.recorder.record(Write::DmaDisable);
+        self.enabled.set(false);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn set_peripheral_address(&self, address: u32) {
+        self.recorder.record(Write::DmaPeripheralAddress(address));
+    }
+
+    fn set_memory0_address(&self, address: u32) {
+        self.write_memory_address(Target::Memory0, Write::DmaMemory0Address(address));
+    }
+
+    fn set_memory1_address(&self, address: u32) {
+        self.write_memory_address(Target::Memory1, Write::DmaMemory1Address(address));
+    }
+
+    /// CT only changes when the test finishes the second half, directly or with
+    /// `finish_after_reads`.  Reading it over and over without that panics rather than hangs.
+    fn current_target(&self) -> Target {
+        let target = self.target.get();
+        match self.reads_until_finish.get() {
+            Some(1) => {
+                self.reads_until_finish.set(None);
+                self.finish(Half::Second);
+            }
+            Some(reads) => self.reads_until_finish.set(Some(reads - 1)),
+            None => {
+                let waiting = self.waiting_reads.get() + 1;
+                assert!(
+                    waiting < MAX_WAITING_READS,
+                    "waiting for a DMA stream switch that the test never scheduled"
+                );
+                self.waiting_reads.set(waiting);
+            }
+        }
+        target
+    }
+
+    fn set_transfer_count(&self, count: u16) {
+        self.recorder.record(Write::DmaTransferCount(count));
+    }
+
+    fn clear_flags(&self) {
+        self.recorder.record(Write::DmaClearFlags);
+    }
+
+    fn enable(&self, config: &StreamConfig) {
+        self.recorder.record(Write::DmaEnable(*config));
+        self.enabled.set(true);
+        self.double_buffer.set(config.double_buffer);
+        self.target.set(Target::Memory0);
+        self.waiting_reads.set(0);
+    }
+
+    fn take_finished_half(&self) -> Option<Half> {
+        let half = match self.finished.get() {
+            (first, true) => {
+                self.finished.set((first, false));
+                Half::Second
+            }
+            (true, false) => {
+                self.finished.set((false, false));
+                Half::First
+            }
+            (false, false) => return None,
+        };
+        self.recorder.record(Write::DmaClearHalf(half));
+        Some(half)
+    }
+}
+
+impl<'a> Timer for Peripheral<'a> {
+    fn start_trigger(&self, reload: u16) {
+        self.recorder.record(Write::TimerStartTrigger { reload });
+    }
+}
+
+impl<'a> I2sRegisters for Peripheral<'a> {
+    fn write_config(&self, config: &i2s::Config) {
+        self.recorder.record(Write::I2sConfig(*config));
+    }
+
+    fn write_prescaler(&self, prescaler: &i2s::Prescaler) {
+        self.recorder.record(Write::I2sPrescaler(*prescaler));
+    }
+
+    fn enable(&self) {
+        self.recorder.record(Write::I2sEnable);
+    }
+}
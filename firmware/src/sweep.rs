@@ -0,0 +1,119 @@
+//! Frequency sweeps for characterizing filters: the frequency to play at any point of the sweep.
+//! Playing it through the DDS accumulator keeps the phase continuous as the frequency moves.
+
+use micromath::F32Ext;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shape {
+    /// The same number of hertz per second throughout
+    Linear,
+    /// The same number of octaves per second throughout, as for a Bode plot
+    Logarithmic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sweep {
+    pub start_millihertz: u64,
+    pub stop_millihertz: u64,
+    pub duration_ms: u32,
+    pub shape: Shape,
+    /// Whether to jump back to the start once the stop frequency is reached, rather than stay there
+    pub repeat: bool,
+}
+
+impl Sweep {
+    /// The frequency `elapsed` samples into the sweep, in millihertz.
+    pub fn millihertz_at(&self, elapsed: u64, sample_rate: usize) -> u64 {
+        let length = self.duration_ms as u64 * sample_rate as u64 / 1000;
+        if length == 0 {
+            return self.stop_millihertz;
+        }
+        let position = if self.repeat {
+            elapsed % length
+        } else if elapsed >= length {
+            return self.stop_millihertz;
+        } else {
+            elapsed
+        };
+
+        let start = self.start_millihertz;
+        let stop = self.stop_millihertz;
+        // a logarithmic sweep can never leave 0Hz, so treat that as linear
+        if self.shape == Shape::Linear || start == 0 || stop == 0 {
+            // a wide span times hours of samples overflows 64 bits
+            let span = stop as i128 - start as i128;
+            (start as i128 + span * position as i128 / length as i128) as u64
+        } else {
+            let fraction = position as f32 / length as f32;
+            (start as f32 * (stop as f32 / start as f32).powf(fraction)) as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 500_000;
+
+    fn sweep(start_millihertz: u64, stop_millihertz: u64, shape: Shape, repeat: bool) -> Sweep {
+        Sweep {
+            start_millihertz,
+            stop_millihertz,
+            duration_ms: 1000,
+            shape,
+            repeat,
+        }
+    }
+
+    #[test]
+    fn linear_moves_evenly() {
+        let up = sweep(1_000_000, 3_000_000, Shape::Linear, false);
+        assert_eq!(up.millihertz_at(0, SAMPLE_RATE), 1_000_000);
+        assert_eq!(up.millihertz_at(125_000, SAMPLE_RATE), 1_500_000);
+        assert_eq!(up.millihertz_at(250_000, SAMPLE_RATE), 2_000_000);
+        let down = sweep(3_000_000, 1_000_000, Shape::Linear, false);
+        assert_eq!(down.millihertz_at(250_000, SAMPLE_RATE), 2_000_000);
+    }
+
+    #[test]
+    fn linear_spans_the_widest_sweep_over_the_longest_duration() {
+        let sweep = Sweep {
+            duration_ms: u32::MAX,
+            ..sweep(0, 250_000_000, Shape::Linear, false)
+        };
+        let length = u32::MAX as u64 * SAMPLE_RATE as u64 / 1000;
+        assert_eq!(sweep.millihertz_at(length / 2, SAMPLE_RATE), 125_000_000);
+        assert_eq!(sweep.millihertz_at(length - 1, SAMPLE_RATE), 249_999_999);
+    }
+
+    #[test]
+    fn logarithmic_moves_by_the_same_ratio() {
+        let sweep = sweep(100_000, 10_000_000, Shape::Logarithmic, false);
+        let middle = sweep.millihertz_at(250_000, SAMPLE_RATE) as f32;
+        assert!((middle / 1_000_000.0 - 1.0).abs() < 0.001);
+        let quarter = sweep.millihertz_at(125_000, SAMPLE_RATE) as f32;
+        assert!((quarter / 316_228.0 - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn logarithmic_from_zero_is_linear() {
+        let sweep = sweep(0, 2_000_000, Shape::Logarithmic, false);
+        assert_eq!(sweep.millihertz_at(250_000, SAMPLE_RATE), 1_000_000);
+    }
+
+    #[test]
+    fn end_holds_or_repeats() {
+        let once = sweep(1_000_000, 3_000_000, Shape::Linear, false);
+        assert_eq!(once.millihertz_at(500_000, SAMPLE_RATE), 3_000_000);
+        assert_eq!(once.millihertz_at(2_000_000, SAMPLE_RATE), 3_000_000);
+        let repeating = sweep(1_000_000, 3_000_000, Shape::Linear, true);
+        assert_eq!(repeating.millihertz_at(500_000, SAMPLE_RATE), 1_000_000);
+        assert_eq!(repeating.millihertz_at(750_000, SAMPLE_RATE), 2_000_000);
+        let instant = Sweep {
+            duration_ms: 0,
+            ..once
+        };
+        assert_eq!(instant.millihertz_at(0, SAMPLE_RATE), 3_000_000);
+    }
+}
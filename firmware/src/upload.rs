@@ -0,0 +1,220 @@
+//! The receiving end of an arbitrary waveform upload.  After a `u` command giving the number of
+//! samples, the host sends that many 12-bit samples as little-endian `u16`s, followed by the
+//! Fletcher-16 checksum of those bytes, also as a little-endian `u16`.  The tools crate's
+//! `upload_waveform` sends this from a WAV or CSV file.
+//!
+//! A sample above 0xFFF ends the upload with an error.  Every printable character is at least 0x10,
+//! so a line of text sent in the middle of an upload is bound to end it within two bytes, which is
+//! how the host gives up on one it could not finish.  Text that instead lands in the checksum
+//! fails it, so either way the rest of that line has to be thrown away rather than run.
+
+/// The largest 12-bit DAC code.
+const MAX_SAMPLE: u16 = 0xfff;
+
+/// Why an upload was thrown away.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// A sample above `MAX_SAMPLE`, which would spill into channel 2's half of DHR12RD
+    SampleTooLarge,
+    Checksum,
+}
+
+/// A running Fletcher-16 checksum, which catches the dropped and reordered bytes that a plain sum
+/// would not.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Checksum {
+    sum1: u16,
+    sum2: u16,
+}
+
+impl Checksum {
+    pub fn add(&mut self, byte: u8) {
+        self.sum1 = (self.sum1 + byte as u16) % 255;
+        self.sum2 = (self.sum2 + self.sum1) % 255;
+    }
+
+    pub fn value(&self) -> u16 {
+        self.sum2 << 8 | self.sum1
+    }
+}
+
+/// An upload in progress.
+pub struct Upload {
+    len: usize,
+    /// Bytes received so far, including the checksum
+    received: usize,
+    checksum: Checksum,
+    expected: u16,
+    /// Whether the bytes that failed the upload included a newline
+    line_ended: bool,
+}
+
+impl Upload {
+    /// Expects `len` samples.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            received: 0,
+            checksum: Checksum::default(),
+            expected: 0,
+            line_ended: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the line of text that failed the upload, if that is what it was, already ended in
+    /// the bytes the upload took; otherwise the rest of it is still to come.
+    pub fn line_ended(&self) -> bool {
+        self.line_ended
+    }
+
+    /// Stores as much of `bytes` as belongs to the upload into `samples`, which must hold at least
+    /// `len()` of them.  Returns how many bytes were used, and once the upload is over, whether it
+    /// arrived intact.  It is over once the checksum has arrived, or as soon as a sample is out of
+    /// range, leaving the bytes after that sample unused.
+    pub fn receive(
+        &mut self,
+        bytes: &[u8],
+        samples: &mut [u16],
+    ) -> (usize, Option<Result<(), Error>>) {
+        let sample_bytes = 2 * self.len;
+        let mut used = 0;
+        for &byte in bytes.iter() {
+            if self.received >= sample_bytes + 2 {
+                break;
+            }
+            if self.received < sample_bytes {
+                let sample = &mut samples[self.received / 2];
+                if self.received % 2 == 0 {
+                    *sample = byte as u16;
+                } else {
+                    *sample |= (byte as u16) << 8;
+                    if *sample > MAX_SAMPLE {
+                        self.line_ended = sample.to_le_bytes().contains(&b'\n');
+                        self.received += 1;
+                        return (used + 1, Some(Err(Error::SampleTooLarge)));
+                    }
+                }
+                self.checksum.add(byte);
+            } else if self.received == sample_bytes {
+                self.expected = byte as u16;
+            } else {
+                self.expected |= (byte as u16) << 8;
+            }
+            self.received += 1;
+            used += 1;
+        }
+
+        let result = if self.received == sample_bytes + 2 {
+            if self.checksum.value() == self.expected {
+                Some(Ok(()))
+            } else {
+                self.line_ended = self.expected.to_le_bytes().contains(&b'\n');
+                Some(Err(Error::Checksum))
+            }
+        } else {
+            None
+        };
+        (used, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames `samples` the way `upload_waveform` does.
+    fn frame(samples: &[u16]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut checksum = Checksum::default();
+        for &byte in bytes.iter() {
+            checksum.add(byte);
+        }
+        bytes.extend_from_slice(&checksum.value().to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn checksum_is_fletcher16() {
+        let mut checksum = Checksum::default();
+        for &byte in b"abcde" {
+            checksum.add(byte);
+        }
+        assert_eq!(checksum.value(), 0xc8f0);
+    }
+
+    #[test]
+    fn frame_matches_upload_waveform() {
+        // the same vector as the tools crate's `upload` tests
+        let bytes = frame(&[0x000, 0x123, 0xfff]);
+        assert_eq!(bytes, [0x00, 0x00, 0x23, 0x01, 0xff, 0x0f, 0x33, 0x9e]);
+    }
+
+    #[test]
+    fn receives_in_pieces_and_leaves_what_follows() {
+        let mut bytes = frame(&[0x000, 0x123, 0xfff]);
+        bytes.extend_from_slice(b"f440\n");
+        let mut upload = Upload::new(3);
+        let mut samples = [0; 3];
+        assert_eq!(upload.receive(&bytes[..3], &mut samples), (3, None));
+        let (used, result) = upload.receive(&bytes[3..], &mut samples);
+        assert_eq!(used, 5);
+        assert_eq!(result, Some(Ok(())));
+        assert_eq!(samples, [0x000, 0x123, 0xfff]);
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut bytes = frame(&[0x800, 0x801]);
+        bytes[1] ^= 1;
+        let mut samples = [0; 2];
+        let result = Upload::new(2).receive(&bytes, &mut samples);
+        assert_eq!(result, (6, Some(Err(Error::Checksum))));
+    }
+
+    #[test]
+    fn ends_at_a_sample_out_of_range() {
+        let bytes = frame(&[0x800, 0x1000, 0x800]);
+        let mut samples = [0; 3];
+        let result = Upload::new(3).receive(&bytes, &mut samples);
+        assert_eq!(result, (4, Some(Err(Error::SampleTooLarge))));
+    }
+
+    #[test]
+    fn text_ends_an_interrupted_upload() {
+        let mut upload = Upload::new(100);
+        let mut samples = [0; 100];
+        assert_eq!(upload.receive(&[0x00, 0x08, 0x00], &mut samples), (3, None));
+        let result = upload.receive(b"g0\n", &mut samples);
+        assert_eq!(result, (1, Some(Err(Error::SampleTooLarge))));
+        assert!(!upload.line_ended());
+    }
+
+    #[test]
+    fn text_in_the_checksum_fails_it() {
+        let mut bytes = frame(&[0x800, 0x801]);
+        bytes.truncate(4);
+        bytes.extend_from_slice(b"g2\n");
+        let mut upload = Upload::new(2);
+        let mut samples = [0; 2];
+        // the newline is left for the command parser, which drops the line up to it
+        let result = upload.receive(&bytes, &mut samples);
+        assert_eq!(result, (6, Some(Err(Error::Checksum))));
+        assert!(!upload.line_ended());
+
+        let mut upload = Upload::new(2);
+        bytes.truncate(4);
+        bytes.extend_from_slice(b"x\ng2\n");
+        let result = upload.receive(&bytes, &mut samples);
+        assert_eq!(result, (6, Some(Err(Error::Checksum))));
+        // the next line is a command of its own
+        assert!(upload.line_ended());
+    }
+}
@@ -0,0 +1,50 @@
+//! The USB full-speed device that every experiment enumerates as.
+
+use stm32f4xx_hal::gpio::gpioa::{PA11, PA12};
+use stm32f4xx_hal::otg_fs::{UsbBus, USB};
+use stm32f4xx_hal::rcc::Clocks;
+use stm32f4xx_hal::stm32;
+
+use usb_device::bus::UsbBusAllocator;
+use usb_device::prelude::*;
+
+pub const VID_PID: UsbVidPid = UsbVidPid(0x1337, 0xd00d);
+pub const MANUFACTURER: &str = "Matt Mullins";
+pub const PRODUCT: &str = "STM32F4 experiment";
+
+/// The OTG_FS peripheral and its pins, in the form the HAL's USB bus wants them.
+pub struct Peripherals {
+    pub global: stm32::OTG_FS_GLOBAL,
+    pub device: stm32::OTG_FS_DEVICE,
+    pub pwrclk: stm32::OTG_FS_PWRCLK,
+}
+
+/// Sets up the USB bus on PA11/PA12.  PLL48CLK must already be running at 48MHz, e.g. from
+/// `require_pll48clk()` or a SYSCLK of 168MHz.
+pub fn bus<DM, DP>(
+    clocks: &Clocks,
+    peripherals: Peripherals,
+    pin_dm: PA11<DM>,
+    pin_dp: PA12<DP>,
+    endpoint_memory: &'static mut [u32],
+) -> UsbBusAllocator<UsbBus<USB>> {
+    let usb = USB {
+        hclk: clocks.hclk(),
+        usb_global: peripherals.global,
+        usb_device: peripherals.device,
+        usb_pwrclk: peripherals.pwrclk,
+        pin_dp: pin_dp.into_alternate_af10(),
+        pin_dm: pin_dm.into_alternate_af10(),
+    };
+
+    UsbBus::new(usb, endpoint_memory)
+}
+
+/// Builds the device with our VID/PID and strings.  Allocate the classes from `bus` first, since
+/// they have to be allocated before the device is built.
+pub fn device<B: usb_device::bus::UsbBus>(bus: &UsbBusAllocator<B>) -> UsbDevice<B> {
+    UsbDeviceBuilder::new(bus, VID_PID)
+        .manufacturer(MANUFACTURER)
+        .product(PRODUCT)
+        .build()
+}
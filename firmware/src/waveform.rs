@@ -0,0 +1,245 @@
+//! The shapes the signal generator can output, as 12-bit DAC samples.
+
+use micromath::F32Ext;
+
+/// The DAC code for the middle of the output range, where waveforms are centered without a DC
+/// offset.
+pub const MIDSCALE: f32 = 0x800 as f32;
+/// The largest 12-bit DAC code.
+const FULL_SCALE: f32 = 0xfff as f32;
+
+/// The seed for the noise generator, which only needs to be nonzero.
+const NOISE_SEED: u32 = 0x1337_d00d;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    /// High for `duty` percent of each period
+    Square {
+        duty: u8,
+    },
+    Triangle,
+    /// Rising from the bottom to the top over each period
+    Sawtooth,
+    /// High for `width_us` microseconds at the start of each period, regardless of the frequency
+    Pulse {
+        width_us: u32,
+    },
+    /// Uniform white noise, which repeats with the sample buffer rather than the frequency
+    Noise,
+}
+
+impl Waveform {
+    /// The waveform for the `w` serial command's argument, with default parameters.
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(Waveform::Sine),
+            1 => Some(Waveform::Square { duty: 50 }),
+            2 => Some(Waveform::Triangle),
+            3 => Some(Waveform::Sawtooth),
+            4 => Some(Waveform::Pulse { width_us: 1 }),
+            5 => Some(Waveform::Noise),
+            _ => None,
+        }
+    }
+
+    /// The same waveform with its duty cycle (square) or pulse width (pulse) changed, if it has
+    /// one.
+    pub fn with_parameter(self, value: u32) -> Self {
+        match self {
+            Waveform::Square { .. } => Waveform::Square {
+                duty: core::cmp::min(value, 100) as u8,
+            },
+            Waveform::Pulse { .. } => Waveform::Pulse { width_us: value },
+            other => other,
+        }
+    }
+
+    /// Whether a single period is all that needs to be rendered; noise fills the whole buffer so
+    /// it repeats as rarely as possible.
+    pub fn is_periodic(self) -> bool {
+        self != Waveform::Noise
+    }
+}
+
+/// Fills `samples` with one period of `waveform` (or with noise), swinging `amplitude` DAC codes
+/// either side of the code `center`, and clipping at the ends of the DAC's range.  `sample_rate` is
+/// only needed to time pulses.
+pub fn render(
+    samples: &mut [u16],
+    waveform: Waveform,
+    amplitude: f32,
+    center: f32,
+    sample_rate: usize,
+) {
+    let len = samples.len();
+    for (sample, code) in
+        samples
+            .iter_mut()
+            .zip(Samples::new(len, waveform, amplitude, center, sample_rate))
+    {
+        *sample = code;
+    }
+}
+
+/// The DAC codes `render()` would write into a buffer of `len` samples, one at a time, for when
+/// they are not going into a plain `u16` buffer.
+pub struct Samples {
+    len: usize,
+    waveform: Waveform,
+    amplitude: f32,
+    center: f32,
+    sample_rate: usize,
+    index: usize,
+    noise: u32,
+}
+
+impl Samples {
+    pub fn new(
+        len: usize,
+        waveform: Waveform,
+        amplitude: f32,
+        center: f32,
+        sample_rate: usize,
+    ) -> Self {
+        Self {
+            len,
+            waveform,
+            amplitude,
+            center,
+            sample_rate,
+            index: 0,
+            noise: NOISE_SEED,
+        }
+    }
+}
+
+impl Iterator for Samples {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        if self.index >= self.len {
+            return None;
+        }
+        let i = self.index;
+        self.index += 1;
+
+        let phase = i as f32 / self.len as f32;
+        // from -1 to 1
+        let value = match self.waveform {
+            Waveform::Sine => (2.0 * core::f32::consts::PI * phase).sin(),
+            Waveform::Square { duty } => level(phase * 100.0 < duty as f32),
+            Waveform::Triangle if phase < 0.5 => 4.0 * phase - 1.0,
+            Waveform::Triangle => 3.0 - 4.0 * phase,
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Pulse { width_us } => {
+                level((i as u64) * 1_000_000 < width_us as u64 * self.sample_rate as u64)
+            }
+            Waveform::Noise => {
+                // xorshift32, which is plenty random for audio and costs three shifts a sample
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+            }
+        };
+
+        let code = self.center + self.amplitude * value;
+        Some(code.max(0.0).min(FULL_SCALE) as u16)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Samples {}
+
+fn level(high: bool) -> f32 {
+    if high {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMPLITUDE: f32 = 1000.0;
+    const LOW: u16 = 1048;
+    const HIGH: u16 = 3048;
+
+    fn rendered(len: usize, waveform: Waveform, sample_rate: usize) -> Vec<u16> {
+        let mut samples = vec![0; len];
+        render(&mut samples, waveform, AMPLITUDE, MIDSCALE, sample_rate);
+        samples
+    }
+
+    #[test]
+    fn square_is_high_for_its_duty() {
+        let samples = rendered(100, Waveform::Square { duty: 25 }, 1_000_000);
+        assert!(samples[..25].iter().all(|&code| code == HIGH));
+        assert!(samples[25..].iter().all(|&code| code == LOW));
+    }
+
+    #[test]
+    fn triangle_rises_then_falls() {
+        let samples = rendered(8, Waveform::Triangle, 1_000_000);
+        assert_eq!(samples, [1048, 1548, 2048, 2548, 3048, 2548, 2048, 1548]);
+    }
+
+    #[test]
+    fn sawtooth_rises_over_the_period() {
+        let samples = rendered(4, Waveform::Sawtooth, 1_000_000);
+        assert_eq!(samples, [1048, 1548, 2048, 2548]);
+    }
+
+    #[test]
+    fn sine_starts_at_the_center() {
+        let samples = rendered(4, Waveform::Sine, 1_000_000);
+        // codes are truncated, so sin(pi) a hair below 0 lands one code under the center
+        let expected = [2048, 3048, 2048, 1048];
+        for (&code, &expected) in samples.iter().zip(expected.iter()) {
+            assert!((code as i32 - expected).abs() <= 1, "{:?}", samples);
+        }
+    }
+
+    #[test]
+    fn pulse_width_is_independent_of_the_period() {
+        let samples = rendered(10, Waveform::Pulse { width_us: 2 }, 1_000_000);
+        assert_eq!(samples.iter().filter(|&&code| code == HIGH).count(), 2);
+        assert_eq!(&samples[..2], &[HIGH, HIGH]);
+
+        // 1us at 10.5MHz covers 10.5 samples, so the 11th starts inside it
+        let samples = rendered(100, Waveform::Pulse { width_us: 1 }, 10_500_000);
+        assert_eq!(samples.iter().filter(|&&code| code == HIGH).count(), 11);
+    }
+
+    #[test]
+    fn noise_stays_within_the_amplitude_and_uses_all_of_it() {
+        let samples = rendered(10_000, Waveform::Noise, 1_000_000);
+        assert!(samples.iter().all(|&code| (LOW..=HIGH).contains(&code)));
+        assert!(*samples.iter().min().unwrap() < LOW + 10);
+        assert!(*samples.iter().max().unwrap() > HIGH - 10);
+        let mean = samples.iter().map(|&code| code as f32).sum::<f32>() / samples.len() as f32;
+        assert!((mean - MIDSCALE).abs() < 20.0);
+    }
+
+    #[test]
+    fn clips_at_the_ends_of_the_range() {
+        let mut samples = [0; 4];
+        render(&mut samples, Waveform::Sawtooth, 3000.0, 3000.0, 1_000_000);
+        assert_eq!(samples, [0, 1500, 3000, 0xfff]);
+    }
+
+    #[test]
+    fn samples_match_render() {
+        let rendered = rendered(50, Waveform::Sine, 1_000_000);
+        let samples = Samples::new(50, Waveform::Sine, AMPLITUDE, MIDSCALE, 1_000_000);
+        assert_eq!(samples.len(), 50);
+        assert!(samples.eq(rendered.iter().copied()));
+    }
+}
@@ -0,0 +1,63 @@
+//! Times the Pareto search for an I2S master clock against the original exhaustive one.
+//!
+//! Run with `cargo bench`.  The exhaustive search takes minutes over the whole divider space, so
+//! the comparison fixes the main PLL to the one the firmware uses with an 8MHz crystal, leaving
+//! every PLLI2S and I2SDIV/ODD setting to search.  The unit tests in `mclk` check that the two
+//! agree.
+
+use std::time::{Duration, Instant};
+
+use tools::chip;
+use tools::mclk::{self, MclkSearch};
+use tools::Rational;
+
+fn time<T>(iterations: u32, mut f: impl FnMut() -> T) -> (Duration, T) {
+    let start = Instant::now();
+    let mut result = f();
+    for _ in 1..iterations {
+        result = f();
+    }
+    (start.elapsed() / iterations, result)
+}
+
+fn search(
+    hse: i64,
+    m: std::ops::RangeInclusive<i64>,
+    n: std::ops::RangeInclusive<i64>,
+) -> MclkSearch {
+    let chip = chip::by_name("f407").expect("the F407 is always known");
+    MclkSearch {
+        chip,
+        hse: Rational::from_integer(hse),
+        mclk: Rational::from_integer(12_288_000),
+        tolerance: Rational::new(1, 100),
+        m,
+        n,
+        p: chip.pllp.to_vec(),
+        q: chip.pllq.clone(),
+    }
+}
+
+fn main() {
+    let fixed = search(8_000_000, 8..=8, 336..=336);
+
+    let (exhaustive_time, exhaustive) = time(3, || mclk::exhaustive(&fixed));
+    let (pareto_time, pareto) = time(100, || mclk::pareto(&fixed));
+    println!(
+        "fixed main PLL: exhaustive {:?} for {} settings, pareto {:?} for {} settings ({:.0}x)",
+        exhaustive_time,
+        exhaustive.len(),
+        pareto_time,
+        pareto.len(),
+        exhaustive_time.as_secs_f64() / pareto_time.as_secs_f64()
+    );
+
+    let chip = chip::by_name("f407").expect("the F407 is always known");
+    let whole = search(11_059_200, chip.pllm.clone(), chip.plln.clone());
+    let (whole_time, whole_front) = time(10, || mclk::pareto(&whole));
+    println!(
+        "every divider, 11.0592MHz HSE: pareto {:?} for {} settings",
+        whole_time,
+        whole_front.len()
+    );
+}
@@ -0,0 +1,413 @@
+//! How close the I2S peripheral can get to the standard audio sample rates, in the style of the
+//! "Audio-frequency precision" tables in RM0090.
+
+use std::ops::RangeInclusive;
+
+use num_traits::Signed;
+
+use crate::chip::Chip;
+use crate::solver::{self, I2sDividers, I2sFrame, SampleRateSetting};
+use crate::Rational;
+
+/// Every I2SDIV/ODD setting for `i2s_clk`, fastest first, each compared against whichever of
+/// `sample_rates` is closest.
+pub fn every_divider(
+    chip: &Chip,
+    i2s_clk: Rational,
+    frame: I2sFrame,
+    sample_rates: &[Rational],
+) -> Vec<SampleRateSetting> {
+    let min_divider = 2 * chip.i2sdiv.start();
+    let max_divider = 2 * chip.i2sdiv.end() + 1;
+
+    (min_divider..=max_divider)
+        .filter_map(|divider| {
+            let actual = i2s_clk / (divider * frame.clocks_per_sample());
+            sample_rates
+                .iter()
+                .map(|&desired| SampleRateSetting {
+                    desired,
+                    i2sdiv: divider / 2,
+                    odd: divider % 2,
+                    actual,
+                    error: actual / desired - 1,
+                })
+                .min_by_key(|setting| setting.error.abs())
+        })
+        .collect()
+}
+
+/// Ranges of PLLI2S dividers to search, which may have been narrowed down to single values.
+pub struct Plli2sRanges {
+    pub m: RangeInclusive<i64>,
+    pub n: RangeInclusive<i64>,
+    pub r: RangeInclusive<i64>,
+}
+
+/// Finds the PLLI2S and I2SDIV/ODD setting that gets closest to `desired`.  Ties go to the lowest
+/// VCO frequency, which draws the least current.
+pub fn best_plli2s(
+    chip: &Chip,
+    hse: Rational,
+    ranges: &Plli2sRanges,
+    desired: Rational,
+    frame: I2sFrame,
+) -> Option<(I2sDividers, SampleRateSetting)> {
+    let mut best: Option<(I2sDividers, SampleRateSetting, Rational)> = None;
+
+    for m in ranges.m.clone() {
+        let vco_input = hse / m;
+        if !chip.vco_input.contains(&vco_input) {
+            continue;
+        }
+
+        for n in ranges.n.clone() {
+            let vco_output = vco_input * n;
+            if !chip.vco_output.contains(&vco_output) {
+                continue;
+            }
+
+            for r in ranges.r.clone() {
+                let i2s_clk = vco_output / r;
+                if !chip.plli2s_out.contains(&i2s_clk) {
+                    continue;
+                }
+
+                let setting = match solver::best_divider(chip, i2s_clk, desired, frame) {
+                    Some(setting) => setting,
+                    None => continue,
+                };
+                let better = match &best {
+                    Some((_, current, current_vco)) => {
+                        let (error, current_error) = (setting.error.abs(), current.error.abs());
+                        error < current_error
+                            || (error == current_error && vco_output < *current_vco)
+                    }
+                    None => true,
+                };
+                if better {
+                    best = Some((I2sDividers { m, n, r }, setting, vco_output));
+                }
+            }
+        }
+    }
+
+    best.map(|(dividers, setting, _)| (dividers, setting))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chip, floatify};
+
+    /// Frame, Fs, PLLI2SN, PLLI2SR, I2SDIV, I2SODD, real Fs and error.
+    type Row = (
+        I2sFrame,
+        i64,
+        i64,
+        i64,
+        i64,
+        i64,
+        &'static str,
+        &'static str,
+    );
+
+    /// RM0090's "Audio-frequency precision using standard 8 MHz HSE" table, all with PLLM = 8.
+    const RM0090: &[Row] = &[
+        (
+            I2sFrame::Channel16,
+            8_000,
+            192,
+            2,
+            187,
+            1,
+            "8000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel16,
+            16_000,
+            192,
+            3,
+            62,
+            1,
+            "16000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel16,
+            22_050,
+            290,
+            3,
+            68,
+            1,
+            "22049.88",
+            "-0.0006%",
+        ),
+        (
+            I2sFrame::Channel16,
+            32_000,
+            256,
+            2,
+            62,
+            1,
+            "32000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel16,
+            44_100,
+            302,
+            2,
+            53,
+            1,
+            "44100.47",
+            "0.0011%",
+        ),
+        (
+            I2sFrame::Channel16,
+            48_000,
+            192,
+            5,
+            12,
+            1,
+            "48000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel16,
+            96_000,
+            384,
+            5,
+            12,
+            1,
+            "96000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel16,
+            192_000,
+            424,
+            3,
+            11,
+            1,
+            "192028.99",
+            "0.0151%",
+        ),
+        (
+            I2sFrame::Channel32,
+            8_000,
+            256,
+            2,
+            125,
+            0,
+            "8000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel32,
+            16_000,
+            256,
+            2,
+            62,
+            1,
+            "16000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel32,
+            22_050,
+            302,
+            2,
+            53,
+            1,
+            "22050.23",
+            "0.0011%",
+        ),
+        (
+            I2sFrame::Channel32,
+            32_000,
+            256,
+            5,
+            12,
+            1,
+            "32000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel32,
+            44_100,
+            429,
+            4,
+            19,
+            0,
+            "44099.51",
+            "-0.0011%",
+        ),
+        (
+            I2sFrame::Channel32,
+            48_000,
+            384,
+            5,
+            12,
+            1,
+            "48000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::Channel32,
+            96_000,
+            424,
+            3,
+            11,
+            1,
+            "96014.49",
+            "0.0151%",
+        ),
+        (
+            I2sFrame::Channel32,
+            192_000,
+            258,
+            3,
+            3,
+            1,
+            "191964.29",
+            "-0.0186%",
+        ),
+        (
+            I2sFrame::MasterClock,
+            8_000,
+            256,
+            5,
+            12,
+            1,
+            "8000.00",
+            "0.0000%",
+        ),
+        (
+            I2sFrame::MasterClock,
+            16_000,
+            213,
+            2,
+            13,
+            0,
+            "16000.60",
+            "0.0038%",
+        ),
+        (
+            I2sFrame::MasterClock,
+            22_050,
+            429,
+            4,
+            9,
+            1,
+            "22049.75",
+            "-0.0011%",
+        ),
+        (
+            I2sFrame::MasterClock,
+            32_000,
+            213,
+            2,
+            6,
+            1,
+            "32001.20",
+            "0.0038%",
+        ),
+        (
+            I2sFrame::MasterClock,
+            44_100,
+            271,
+            2,
+            6,
+            0,
+            "44108.07",
+            "0.0183%",
+        ),
+        (
+            I2sFrame::MasterClock,
+            48_000,
+            258,
+            3,
+            3,
+            1,
+            "47991.07",
+            "-0.0186%",
+        ),
+        (
+            I2sFrame::MasterClock,
+            96_000,
+            344,
+            2,
+            3,
+            1,
+            "95982.14",
+            "-0.0186%",
+        ),
+    ];
+
+    #[test]
+    fn reproduces_rm0090() {
+        let chip = chip::by_name("f407").unwrap();
+        for &(frame, fs, n, r, i2sdiv, odd, actual, error) in RM0090 {
+            let ranges = Plli2sRanges {
+                m: 8..=8,
+                n: n..=n,
+                r: r..=r,
+            };
+            let (dividers, setting) = best_plli2s(
+                chip,
+                Rational::from_integer(8_000_000),
+                &ranges,
+                Rational::from_integer(fs),
+                frame,
+            )
+            .unwrap();
+            let row = (
+                frame.name(),
+                fs,
+                dividers.n,
+                dividers.r,
+                setting.i2sdiv,
+                setting.odd,
+                format!("{:.2}", floatify(&setting.actual)),
+                format!("{:.4}%", floatify(&setting.error) * 100.0),
+            );
+            let expected = (
+                frame.name(),
+                fs,
+                n,
+                r,
+                i2sdiv,
+                odd,
+                actual.to_string(),
+                error.to_string(),
+            );
+            assert_eq!(row, expected);
+        }
+    }
+
+    #[test]
+    fn every_divider_includes_rm0090_row() {
+        // 48kHz from PLLI2SN = 192 and PLLI2SR = 5, without MCLK
+        let chip = chip::by_name("f407").unwrap();
+        let settings = every_divider(
+            chip,
+            Rational::from_integer(38_400_000),
+            I2sFrame::Channel16,
+            &[
+                Rational::from_integer(44_100),
+                Rational::from_integer(48_000),
+            ],
+        );
+        // I2SDIV = 2, ODD = 0 up to I2SDIV = 255, ODD = 1
+        assert_eq!(settings.len(), 511 - 4 + 1);
+        assert_eq!((settings[0].i2sdiv, settings[0].odd), (2, 0));
+        let exact = settings
+            .iter()
+            .find(|setting| setting.error == Rational::from_integer(0))
+            .unwrap();
+        assert_eq!((exact.i2sdiv, exact.odd), (12, 1));
+        assert_eq!(exact.desired, Rational::from_integer(48_000));
+    }
+}
@@ -1,116 +1,1274 @@
 use std::ops::RangeInclusive;
 
 use itertools::Itertools;
+use num_traits::Signed;
+use structopt::StructOpt;
 
-type Rational = num_rational::Rational64;
-
-// VCO input must be between 1MHz and 2MHz, per documentation for the PLLM bits of RCC_PLLCFGR
-static VALID_VCO_INPUT: RangeInclusive<Rational> =
-    Rational::new_raw(1_000_000, 1)..=Rational::new_raw(2_000_000, 1);
-// VCO output must be between 100MHz and 432MHz, per documentation for the PLLN bits of RCC_PLLCFGR
-static VALID_VCO_OUTPUT: RangeInclusive<Rational> =
-    Rational::new_raw(100_000_000, 1)..=Rational::new_raw(432_000_000, 1);
-// PLL_OUT must be between 24MHz and 168MHz, per "PLL Characteristics" in the STM32F407 datasheet
-static VALID_PLL_OUT: RangeInclusive<Rational> =
-    Rational::new_raw(24_000_000, 1)..=Rational::new_raw(168_000_000, 1);
-// PLL48_OUT must be between 48MHz and 75MHz, per the same table
-static VALID_PLL48_OUT: RangeInclusive<Rational> =
-    Rational::new_raw(48_000_000, 1)..=Rational::new_raw(75_000_000, 1);
-
-// For now, print the I2S settings that get us within 1% of 12.288MHz
-static DESIRED_I2S_MCLK: RangeInclusive<Rational> =
-    Rational::new_raw(12_165_120, 1)..=Rational::new_raw(12_410_880, 1);
+use tools::accuracy::{self, Plli2sRanges};
+use tools::bus::{self, BusClocks, Prescalers};
+use tools::check::{self, Configuration};
+use tools::chip::{self, Chip, I2sSource};
+use tools::codegen;
+use tools::crystal::{self, Targets};
+use tools::drift::{self, Budget};
+use tools::graph::{self, Tree};
+use tools::mclk::{self, MclkSearch};
+use tools::mco::{self, McoSearch};
+use tools::output::{self, Cell, Table};
+use tools::power;
+use tools::solver::{self, I2sDividers, I2sFrame, Requirements, Solution};
+use tools::{floatify, parse, ppm, Rational};
+
+/// Search for main PLL and PLLI2S settings for STM32F4 parts.
+#[derive(StructOpt)]
+struct Options {
+    /// Which part's PLL limits to use: f401, f411, f407 (f405/f415/f417), f427 (f429/f437/f439) or
+    /// f446
+    #[structopt(long, default_value = "f407", parse(try_from_str = chip::parse))]
+    chip: &'static Chip,
+
+    /// HSE crystal frequency, e.g. "8MHz" or "11059200"
+    #[structopt(long, default_value = "11.0592MHz", parse(try_from_str = parse::frequency))]
+    hse: Rational,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// List the settings that produce a given I2S master clock and cannot be beaten on MCLK error,
+    /// system clock and USB clock all at once
+    Mclk(MclkOptions),
+    /// Rank the settings that meet system clock, USB and I2S sample rate requirements at once
+    Solve(SolveOptions),
+    /// Show the bus and timer clocks for a SYSCLK, and find timer settings for exact update rates
+    Timers(TimersOptions),
+    /// Show how accurately I2S can produce each sample rate, with every MCLK and channel length
+    Accuracy(AccuracyOptions),
+    /// Report the clocks derived from exact register values, and every limit they break
+    Check(CheckOptions),
+    /// Find MCO1/MCO2 settings for a reference clock output, keeping the main PLL as it is
+    Mco(McoOptions),
+    /// Rank standard crystals by how exactly they produce SYSCLK, USB and both audio families
+    Crystals(CrystalOptions),
+    /// Spread the crystal's tolerance over every clock of a configuration, and flag USB or audio
+    /// clocks that could drift out of spec
+    Drift(DriftOptions),
+}
+
+#[derive(StructOpt)]
+struct MclkOptions {
+    /// Desired I2S master clock frequency
+    #[structopt(long, default_value = "12.288MHz", parse(try_from_str = parse::frequency))]
+    mclk: Rational,
+
+    /// Allowed deviation from the desired MCLK, e.g. "1%" or "500ppm"
+    #[structopt(long, default_value = "1%", parse(try_from_str = parse::tolerance))]
+    tolerance: Rational,
+
+    /// Tolerance of the HSE crystal, which the dividers' own error has to leave room for
+    #[structopt(long, default_value = "0ppm", parse(try_from_str = parse::tolerance))]
+    hse_tolerance: Rational,
+
+    /// Only consider this PLLM value
+    #[structopt(short)]
+    m: Option<i64>,
+
+    /// Only consider this PLLN value
+    #[structopt(short)]
+    n: Option<i64>,
+
+    /// Only consider this PLLP value
+    #[structopt(short)]
+    p: Option<i64>,
+
+    /// Only consider this PLLQ value
+    #[structopt(short)]
+    q: Option<i64>,
+
+    /// How to print the results: csv, json, markdown, or a colorized table
+    #[structopt(long, default_value = "csv", possible_values = output::FORMATS)]
+    format: output::Format,
+}
+
+#[derive(StructOpt)]
+struct SolveOptions {
+    /// Slowest acceptable system clock
+    #[structopt(long, default_value = "24MHz", parse(try_from_str = parse::frequency))]
+    sysclk_min: Rational,
+
+    /// Fastest acceptable system clock [default: the chip's maximum]
+    #[structopt(long, parse(try_from_str = parse::frequency))]
+    sysclk_max: Option<Rational>,
+
+    /// Allowed deviation of PLL48CLK from 48MHz, e.g. "0.25%" or "0ppm"
+    #[structopt(long, default_value = "0ppm", parse(try_from_str = parse::tolerance))]
+    usb_tolerance: Rational,
+
+    /// Comma-separated I2S sample rates that must all be reachable with only I2SDIV/ODD changes
+    #[structopt(
+        long,
+        default_value = "32k,44.1k,48k,96k",
+        use_delimiter = true,
+        parse(try_from_str = parse::frequency)
+    )]
+    sample_rates: Vec<Rational>,
+
+    /// Do not output MCLK, so the sample rate is derived from the channel length instead
+    #[structopt(long)]
+    no_mclk: bool,
+
+    /// I2S channel length in bits, which only matters when MCLK is disabled
+    #[structopt(long, default_value = "16", possible_values = &["16", "32"])]
+    channel_length: u32,
+
+    /// Frequency of an external clock on I2S_CKIN, to consider as an I2S kernel clock
+    #[structopt(long, parse(try_from_str = parse::frequency))]
+    i2s_ckin: Option<Rational>,
+
+    /// The lowest supply voltage the board will see, in volts, which sets the flash wait states
+    #[structopt(long, default_value = "2.7", parse(try_from_str = parse::decimal))]
+    vdd: Rational,
+
+    /// Number of solutions to print
+    #[structopt(long, default_value = "10")]
+    limit: usize,
+
+    /// How to print the results: csv, json, markdown, or a colorized table; or "rust" to print
+    /// firmware code, or "dot" to draw the clock tree, for the one chosen by --pick
+    #[structopt(long, default_value = "csv", possible_values = &["csv", "json", "markdown", "table", "rust", "dot"])]
+    format: SolveFormat,
+
+    /// Which solution to turn into code, counting from 1 for the best one
+    #[structopt(long, default_value = "1")]
+    pick: usize,
+}
+
+#[derive(StructOpt)]
+struct TimersOptions {
+    /// System clock frequency [default: the chip's maximum]
+    #[structopt(long, parse(try_from_str = parse::frequency))]
+    sysclk: Option<Rational>,
+
+    /// AHB prescaler [default: the smallest that keeps HCLK in range]
+    #[structopt(long, possible_values = &["1", "2", "4", "8", "16", "64", "128", "256", "512"])]
+    ahb: Option<i64>,
+
+    /// APB1 prescaler [default: the smallest that keeps PCLK1 in range]
+    #[structopt(long, possible_values = &["1", "2", "4", "8", "16"])]
+    apb1: Option<i64>,
+
+    /// APB2 prescaler [default: the smallest that keeps PCLK2 in range]
+    #[structopt(long, possible_values = &["1", "2", "4", "8", "16"])]
+    apb2: Option<i64>,
+
+    /// Set TIMPRE in RCC_DCKCFGR, on parts that have it
+    #[structopt(long)]
+    timpre: bool,
+
+    /// Comma-separated update rates to find PSC and ARR values for, instead of listing the clocks
+    #[structopt(long, use_delimiter = true, parse(try_from_str = parse::frequency))]
+    rates: Vec<Rational>,
+
+    /// Only search settings for this timer, e.g. "tim4"
+    #[structopt(long)]
+    timer: Option<String>,
+
+    /// Number of PSC and ARR settings to print for each timer and rate
+    #[structopt(long, default_value = "3")]
+    limit: usize,
+
+    /// How to print the results: csv, json, markdown, or a colorized table
+    #[structopt(long, default_value = "csv", possible_values = output::FORMATS)]
+    format: output::Format,
+}
+
+#[derive(StructOpt)]
+struct AccuracyOptions {
+    /// List every I2SDIV/ODD setting for this I2S kernel clock, rather than searching PLLI2S
+    #[structopt(long, parse(try_from_str = parse::frequency))]
+    i2s_clk: Option<Rational>,
+
+    /// Only consider this PLLI2SM value (or PLLM, on parts without PLLI2SM)
+    #[structopt(short)]
+    m: Option<i64>,
+
+    /// Only consider this PLLI2SN value
+    #[structopt(long)]
+    plli2sn: Option<i64>,
+
+    /// Only consider this PLLI2SR value
+    #[structopt(long)]
+    plli2sr: Option<i64>,
+
+    /// Comma-separated sample rates to compare against, by default the ones in RM0090's tables
+    #[structopt(
+        long,
+        default_value = "8k,16k,22.05k,32k,44.1k,48k,96k,192k",
+        use_delimiter = true,
+        parse(try_from_str = parse::frequency)
+    )]
+    sample_rates: Vec<Rational>,
+
+    /// When listing every I2SDIV/ODD setting, hide the ones further than this from any sample rate
+    #[structopt(long, parse(try_from_str = parse::tolerance))]
+    max_error: Option<Rational>,
+
+    /// How to print the results: csv, json, markdown, or a colorized table
+    #[structopt(long, default_value = "csv", possible_values = output::FORMATS)]
+    format: output::Format,
+}
+
+/// Register values shared by the subcommands that audit a concrete configuration.
+#[derive(StructOpt)]
+struct RegisterOptions {
+    /// PLLM
+    #[structopt(short)]
+    m: i64,
+
+    /// PLLN
+    #[structopt(short)]
+    n: i64,
+
+    /// PLLP
+    #[structopt(short)]
+    p: i64,
+
+    /// PLLQ
+    #[structopt(short)]
+    q: i64,
+
+    /// PLLI2SM, on parts that have it [default: the same as PLLM]
+    #[structopt(long)]
+    i2sm: Option<i64>,
+
+    /// PLLI2SN
+    #[structopt(long, requires = "i2sr")]
+    i2sn: Option<i64>,
+
+    /// PLLI2SR
+    #[structopt(long, requires = "i2sn")]
+    i2sr: Option<i64>,
+
+    /// I2SDIV in SPI_I2SPR
+    #[structopt(long, requires_all = &["i2sn", "odd"])]
+    i2sdiv: Option<i64>,
+
+    /// ODD in SPI_I2SPR
+    #[structopt(long, requires = "i2sdiv")]
+    odd: Option<i64>,
+
+    /// MCKOE is clear, so the sample rate is derived from the channel length instead
+    #[structopt(long)]
+    no_mclk: bool,
+
+    /// I2S channel length in bits, which only matters when MCLK is disabled
+    #[structopt(long, default_value = "16", possible_values = &["16", "32"])]
+    channel_length: u32,
+}
+
+impl RegisterOptions {
+    fn configuration(&self, chip: &Chip) -> Configuration {
+        if self.i2sm.is_some() && chip.plli2s_shares_pllm() {
+            eprintln!("{} has no PLLI2SM; PLLI2S shares PLLM", chip.name);
+            std::process::exit(1);
+        }
+
+        let plli2s = match (self.i2sn, self.i2sr) {
+            (Some(n), Some(r)) => Some(I2sDividers {
+                m: self.i2sm.unwrap_or(self.m),
+                n,
+                r,
+            }),
+            _ => None,
+        };
+        Configuration {
+            m: self.m,
+            n: self.n,
+            p: self.p,
+            q: self.q,
+            plli2s,
+            i2s_prescaler: self.i2sdiv.zip(self.odd),
+            frame: match (self.no_mclk, self.channel_length) {
+                (false, _) => I2sFrame::MasterClock,
+                (true, 16) => I2sFrame::Channel16,
+                (true, _) => I2sFrame::Channel32,
+            },
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct CheckOptions {
+    #[structopt(flatten)]
+    registers: RegisterOptions,
+
+    /// The regulator voltage scale from PWR_CR.VOS [default: the fastest the chip has]
+    #[structopt(long)]
+    voltage_scale: Option<u8>,
+
+    /// Over-drive mode is enabled
+    #[structopt(long)]
+    overdrive: bool,
+
+    /// How to print the results: csv, json, markdown, or a colorized table; or "dot" to draw the
+    /// clock tree
+    #[structopt(long, default_value = "csv", possible_values = &["csv", "json", "markdown", "table", "dot"])]
+    format: CheckFormat,
+}
+
+enum CheckFormat {
+    Table(output::Format),
+    Dot,
+}
+
+impl std::str::FromStr for CheckFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "dot" => Ok(CheckFormat::Dot),
+            _ => input.parse().map(CheckFormat::Table),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct McoOptions {
+    /// Desired output frequency
+    #[structopt(long, default_value = "12MHz", parse(try_from_str = parse::frequency))]
+    target: Rational,
+
+    /// Allowed deviation from the target, e.g. "1%" or "500ppm"
+    #[structopt(long, default_value = "0ppm", parse(try_from_str = parse::tolerance))]
+    tolerance: Rational,
+
+    /// PLLM of the running main PLL; leave out M, N and P if SYSCLK comes straight from HSE
+    #[structopt(short, requires_all = &["n", "p"])]
+    m: Option<i64>,
+
+    /// PLLN of the running main PLL
+    #[structopt(short, requires = "m")]
+    n: Option<i64>,
+
+    /// PLLP of the running main PLL
+    #[structopt(short, requires = "m")]
+    p: Option<i64>,
+
+    /// PLLI2SN, if PLLI2S is already in use and cannot be changed
+    #[structopt(long, requires = "plli2sr")]
+    plli2sn: Option<i64>,
+
+    /// PLLI2SR, if PLLI2S is already in use and cannot be changed
+    #[structopt(long, requires = "plli2sn")]
+    plli2sr: Option<i64>,
+
+    /// PLLI2SM, on parts that have it, if PLLI2S is already in use
+    #[structopt(long, requires = "plli2sn")]
+    plli2sm: Option<i64>,
+
+    /// Number of settings to print
+    #[structopt(long, default_value = "10")]
+    limit: usize,
+
+    /// How to print the results: csv, json, markdown, or a colorized table
+    #[structopt(long, default_value = "csv", possible_values = output::FORMATS)]
+    format: output::Format,
+}
+
+#[derive(StructOpt)]
+struct CrystalOptions {
+    /// Desired system clock [default: the chip's maximum]
+    #[structopt(long, parse(try_from_str = parse::frequency))]
+    sysclk: Option<Rational>,
+
+    /// Comma-separated sample rates of the 44.1kHz family
+    #[structopt(
+        long,
+        default_value = "22.05k,44.1k,88.2k",
+        use_delimiter = true,
+        parse(try_from_str = parse::frequency)
+    )]
+    family_44k1: Vec<Rational>,
+
+    /// Comma-separated sample rates of the 48kHz family
+    #[structopt(
+        long,
+        default_value = "16k,32k,48k,96k",
+        use_delimiter = true,
+        parse(try_from_str = parse::frequency)
+    )]
+    family_48k: Vec<Rational>,
+
+    /// Do not output MCLK, so the sample rate is derived from the channel length instead
+    #[structopt(long)]
+    no_mclk: bool,
+
+    /// I2S channel length in bits, which only matters when MCLK is disabled
+    #[structopt(long, default_value = "16", possible_values = &["16", "32"])]
+    channel_length: u32,
+
+    /// Comma-separated crystal frequencies to rank instead of the built-in list
+    #[structopt(long, use_delimiter = true, parse(try_from_str = parse::frequency))]
+    crystals: Vec<Rational>,
+
+    /// How to print the results: csv, json, markdown, or a colorized table
+    #[structopt(long, default_value = "csv", possible_values = output::FORMATS)]
+    format: output::Format,
+}
+
+#[derive(StructOpt)]
+struct DriftOptions {
+    #[structopt(flatten)]
+    registers: RegisterOptions,
+
+    /// Tolerance of the HSE crystal, including temperature and aging
+    #[structopt(long, default_value = "50ppm", parse(try_from_str = parse::tolerance))]
+    hse_tolerance: Rational,
+
+    /// Also check the PLLs running from HSI, as after a clock security system failover
+    #[structopt(long)]
+    hsi_fallback: bool,
+
+    /// Accuracy of HSI over the operating temperature range
+    #[structopt(long, default_value = "1%", parse(try_from_str = parse::tolerance))]
+    hsi_tolerance: Rational,
+
+    /// Allowed deviation of PLL48CLK from 48MHz; full-speed USB needs 0.25%
+    #[structopt(long, default_value = "0.25%", parse(try_from_str = parse::tolerance))]
+    usb_tolerance: Rational,
+
+    /// The sample rate the audio path expects [default: the standard rate, from 8k to 192k in
+    /// either family, nearest the one these registers produce]
+    #[structopt(long, parse(try_from_str = parse::frequency))]
+    sample_rate: Option<Rational>,
+
+    /// Allowed deviation of MCLK and the sample rate from what the audio path expects
+    #[structopt(long, default_value = "1%", parse(try_from_str = parse::tolerance))]
+    audio_tolerance: Rational,
+
+    /// How to print the results: csv, json, markdown, or a colorized table
+    #[structopt(long, default_value = "csv", possible_values = output::FORMATS)]
+    format: output::Format,
+}
+
+enum SolveFormat {
+    Table(output::Format),
+    Rust,
+    Dot,
+}
+
+impl std::str::FromStr for SolveFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "rust" => Ok(SolveFormat::Rust),
+            "dot" => Ok(SolveFormat::Dot),
+            _ => input.parse().map(SolveFormat::Table),
+        }
+    }
+}
 
 fn main() {
-    let m_range = 2..=63;
-    let n_range = 50..=432;
-    let p_range = vec![2, 4, 6, 8].into_iter();
-    let q_range = 2..=15;
-    let i2sn_range = 50..=432;
-    let i2sr_range = 2..=7;
-    let i2sdiv_range = 2..=255;
-    let odd_range = 0..=1;
+    let options = Options::from_args();
+
+    match options.command {
+        Command::Mclk(mclk) => search_mclk(options.chip, options.hse, &mclk),
+        Command::Solve(solve) => search_solutions(options.chip, options.hse, &solve),
+        Command::Timers(timers) => search_timers(options.chip, &timers),
+        Command::Accuracy(accuracy) => sample_rate_accuracy(options.chip, options.hse, &accuracy),
+        Command::Check(check) => check_configuration(options.chip, options.hse, &check),
+        Command::Mco(mco) => search_mco(options.chip, options.hse, &mco),
+        Command::Crystals(crystals) => rank_crystals(options.chip, &crystals),
+        Command::Drift(drift) => analyze_drift(options.chip, options.hse, &drift),
+    }
+}
 
-    let hse = Rational::new(11_059_200.into(), 1.into());
+fn search_mclk(chip: &'static Chip, hse: Rational, options: &MclkOptions) {
+    let p = match options.p {
+        Some(p) if chip.pllp.contains(&p) => vec![p],
+        Some(p) => {
+            eprintln!("PLLP {} must be one of {:?}", p, chip.pllp);
+            std::process::exit(1);
+        }
+        None => chip.pllp.to_vec(),
+    };
+    let tolerance = match drift::divider_budget(options.tolerance, options.hse_tolerance) {
+        Some(tolerance) => tolerance,
+        None => {
+            eprintln!("the crystal's tolerance alone is more than the MCLK tolerance");
+            std::process::exit(1);
+        }
+    };
+    let search = MclkSearch {
+        chip,
+        hse,
+        mclk: options.mclk,
+        tolerance,
+        m: fixed_or(options.m, "PLLM", &chip.pllm),
+        n: fixed_or(options.n, "PLLN", &chip.plln),
+        p,
+        q: fixed_or(options.q, "PLLQ", &chip.pllq),
+    };
 
-    println!("M,N,P,Q,I2SN,I2SR,I2SDIV,ODD,PLL_CLK,PLL48_CLK,I2S_MCLK");
+    let mut table = Table::new(&[
+        "M",
+        "N",
+        "P",
+        "Q",
+        "I2SM",
+        "I2SN",
+        "I2SR",
+        "I2SDIV",
+        "ODD",
+        "PLL_CLK",
+        "PLL48_CLK",
+        "I2S_MCLK",
+        "MCLK_PPM",
+    ]);
+    for setting in mclk::pareto(&search) {
+        table.rows.push(vec![
+            Cell::Integer(setting.main.m),
+            Cell::Integer(setting.main.n),
+            Cell::Integer(setting.main.p),
+            Cell::Integer(setting.main.q),
+            Cell::Integer(setting.i2s.m),
+            Cell::Integer(setting.i2s.n),
+            Cell::Integer(setting.i2s.r),
+            Cell::Integer(setting.i2sdiv),
+            Cell::Integer(setting.odd),
+            Cell::Number(floatify(&setting.main.sysclk)),
+            Cell::Number(floatify(&setting.main.pll48_clk)),
+            Cell::Number(floatify(&setting.mclk)),
+            Cell::Ppm(ppm(&setting.error)),
+        ]);
+    }
+    print!("{}", table.render(options.format));
+}
 
-    for m in m_range {
-        let vco_input = hse / m;
-        if !VALID_VCO_INPUT.contains(&vco_input) {
-            continue;
+fn search_solutions(chip: &'static Chip, hse: Rational, options: &SolveOptions) {
+    let i2s_frame = match (options.no_mclk, options.channel_length) {
+        (false, _) => I2sFrame::MasterClock,
+        (true, 16) => I2sFrame::Channel16,
+        (true, _) => I2sFrame::Channel32,
+    };
+    let sysclk_max = options.sysclk_max.unwrap_or(*chip.pll_out.end());
+    let requirements = Requirements {
+        chip,
+        hse,
+        sysclk: options.sysclk_min..=sysclk_max,
+        usb_tolerance: options.usb_tolerance,
+        sample_rates: options.sample_rates.clone(),
+        i2s_frame,
+        i2s_ckin: options.i2s_ckin,
+    };
+
+    let solutions = solver::solve(&requirements, std::cmp::max(options.limit, options.pick));
+    if solutions.is_empty() {
+        match solver::closest_usb_error(&requirements) {
+            None => eprintln!(
+                "no PLL settings give a SYSCLK from {}Hz to {}Hz with a valid PLL48CLK",
+                floatify(requirements.sysclk.start()),
+                floatify(requirements.sysclk.end())
+            ),
+            Some(error) if error.abs() > options.usb_tolerance => eprintln!(
+                "PLL48CLK cannot be within {}ppm of 48MHz from this HSE, the closest being \
+                 {:.3}ppm off; USB allows 2500ppm, so pass --usb-tolerance",
+                ppm(&options.usb_tolerance),
+                ppm(&error.abs())
+            ),
+            Some(_) => eprintln!("no I2S clock can reach all of the sample rates"),
         }
+        std::process::exit(1);
+    }
 
-        for n in n_range.clone() {
-            let vco_output = vco_input * n;
-            if !VALID_VCO_OUTPUT.contains(&vco_output) {
-                continue;
+    match options.format {
+        SolveFormat::Table(format) => {
+            let table = solutions_table(
+                &requirements,
+                &solutions[..options.limit.min(solutions.len())],
+                options.vdd,
+            );
+            print!("{}", table.render(format));
+        }
+        SolveFormat::Rust | SolveFormat::Dot => {
+            let solution = match solutions.get(options.pick.saturating_sub(1)) {
+                Some(solution) => solution,
+                None => {
+                    eprintln!(
+                        "only {} solutions meet the requirements; cannot pick #{}",
+                        solutions.len(),
+                        options.pick
+                    );
+                    std::process::exit(1);
+                }
+            };
+
+            if let SolveFormat::Rust = options.format {
+                let command_line = std::env::args().collect::<Vec<_>>().join(" ");
+                print!(
+                    "{}",
+                    codegen::rust_snippet(&requirements, solution, &command_line)
+                );
+            } else {
+                let tree = Tree {
+                    chip,
+                    hse,
+                    main: &solution.main,
+                    i2s: Some(&solution.i2s),
+                    i2s_frame,
+                    channel_bits: options.channel_length.into(),
+                    sysclk: requirements.sysclk.clone(),
+                };
+                print!("{}", graph::dot(&tree));
             }
+        }
+    }
+}
 
-            for p in p_range.clone() {
-                let pll_clk = vco_output / p;
-                if !VALID_PLL_OUT.contains(&pll_clk) {
-                    continue;
+fn solutions_table(requirements: &Requirements, solutions: &[Solution], vdd: Rational) -> Table {
+    let mut columns = [
+        "M",
+        "N",
+        "P",
+        "Q",
+        "I2S_SRC",
+        "I2SM",
+        "I2SN",
+        "I2SR",
+        "SYSCLK",
+        "PLL48_CLK",
+        "USB_PPM",
+        "I2S_CLK",
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .collect::<Vec<_>>();
+    for rate in requirements.sample_rates.iter() {
+        let rate = floatify(rate);
+        columns.push(format!("I2SDIV_{}", rate));
+        columns.push(format!("ODD_{}", rate));
+        columns.push(format!("FS_{}", rate));
+        columns.push(format!("PPM_{}", rate));
+    }
+    columns.push("WORST_PPM".to_string());
+    for column in ["WAIT_STATES", "VOS", "OVERDRIVE", "CURRENT_MA"].iter() {
+        columns.push(column.to_string());
+    }
+
+    let mut table = Table::new(&columns);
+    for solution in solutions {
+        let main = &solution.main;
+        let i2s = &solution.i2s;
+        let (i2sm, i2sn, i2sr) = match i2s.dividers {
+            Some(d) => (Cell::Integer(d.m), Cell::Integer(d.n), Cell::Integer(d.r)),
+            None => (Cell::Blank, Cell::Blank, Cell::Blank),
+        };
+        let mut row = vec![
+            Cell::Integer(main.m),
+            Cell::Integer(main.n),
+            Cell::Integer(main.p),
+            Cell::Integer(main.q),
+            Cell::Text(i2s.source.name().to_string()),
+            i2sm,
+            i2sn,
+            i2sr,
+            Cell::Number(floatify(&main.sysclk)),
+            Cell::Number(floatify(&main.pll48_clk)),
+            Cell::Ppm(ppm(&main.usb_error)),
+            Cell::Number(floatify(&i2s.i2s_clk)),
+        ];
+        for rate in i2s.sample_rates.iter() {
+            row.push(Cell::Integer(rate.i2sdiv));
+            row.push(Cell::Integer(rate.odd));
+            row.push(Cell::Number(floatify(&rate.actual)));
+            row.push(Cell::Ppm(ppm(&rate.error)));
+        }
+        row.push(Cell::Ppm(ppm(&solution.worst_error())));
+
+        let mut vco_outputs = vec![requirements.hse / main.m * main.n];
+        if let (I2sSource::PllI2s, Some(d)) = (i2s.source, i2s.dividers) {
+            vco_outputs.push(requirements.hse / d.m * d.n);
+        }
+        // the AHB prescaler stays at 1, so HCLK is SYSCLK
+        match power::estimate(
+            requirements.chip,
+            vdd,
+            main.sysclk,
+            main.sysclk,
+            &vco_outputs,
+        ) {
+            Ok(power) => {
+                row.push(Cell::Integer(power.wait_states as i64));
+                row.push(Cell::Integer(power.voltage_scale.into()));
+                row.push(Cell::Text(
+                    if power.overdrive { "yes" } else { "no" }.to_string(),
+                ));
+                row.push(Cell::Number(
+                    (floatify(&power.current) * 10.0).round() / 10.0,
+                ));
+            }
+            // the solution is still worth showing; it just cannot run at this supply voltage
+            Err(_) => row.extend(std::iter::repeat(Cell::Blank).take(4)),
+        }
+        table.rows.push(row);
+    }
+
+    table
+}
+
+fn search_timers(chip: &Chip, options: &TimersOptions) {
+    let sysclk = options.sysclk.unwrap_or(*chip.pll_out.end());
+    if options.timpre && !chip.timpre {
+        eprintln!("{} has no TIMPRE bit", chip.name);
+        std::process::exit(1);
+    }
+    let fastest = match bus::fastest_prescalers(chip, sysclk, options.timpre) {
+        Some(prescalers) => prescalers,
+        None => {
+            eprintln!(
+                "no bus prescalers can bring a {}Hz SYSCLK into range",
+                floatify(&sysclk)
+            );
+            std::process::exit(1);
+        }
+    };
+    let prescalers = Prescalers {
+        ahb: options.ahb.unwrap_or(fastest.ahb),
+        apb1: options.apb1.unwrap_or(fastest.apb1),
+        apb2: options.apb2.unwrap_or(fastest.apb2),
+        timpre: options.timpre,
+    };
+
+    let clocks = BusClocks::new(sysclk, &prescalers);
+    let violations = clocks.violations(chip);
+    if !violations.is_empty() {
+        for violation in violations {
+            eprintln!("{}", violation);
+        }
+        std::process::exit(1);
+    }
+
+    let timers = chip
+        .timers
+        .iter()
+        .filter(|timer| match &options.timer {
+            Some(name) => timer.name.eq_ignore_ascii_case(name),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    if timers.is_empty() {
+        let names = chip.timers.iter().map(|timer| timer.name).join(", ");
+        eprintln!("{} has no such timer; choose one of {}", chip.name, names);
+        std::process::exit(1);
+    }
+
+    let table = if options.rates.is_empty() {
+        let mut table = Table::new(&["CLOCK", "BUS", "PRESCALER", "BITS", "FREQUENCY"]);
+        let buses = [
+            ("SYSCLK", "", Cell::Blank, clocks.sysclk),
+            ("HCLK", "AHB", Cell::Integer(prescalers.ahb), clocks.hclk),
+            (
+                "PCLK1",
+                "APB1",
+                Cell::Integer(prescalers.apb1),
+                clocks.pclk1,
+            ),
+            (
+                "PCLK2",
+                "APB2",
+                Cell::Integer(prescalers.apb2),
+                clocks.pclk2,
+            ),
+        ];
+        for (name, bus, prescaler, clock) in buses.iter() {
+            table.rows.push(vec![
+                Cell::Text(name.to_string()),
+                Cell::Text(bus.to_string()),
+                prescaler.clone(),
+                Cell::Blank,
+                Cell::Number(floatify(clock)),
+            ]);
+        }
+        for timer in timers.iter() {
+            table.rows.push(vec![
+                Cell::Text(timer.name.to_string()),
+                Cell::Text(timer.bus.name().to_string()),
+                Cell::Blank,
+                Cell::Integer(timer.counter_bits.into()),
+                Cell::Number(floatify(&clocks.timer_clock(timer.bus))),
+            ]);
+        }
+        table
+    } else {
+        let mut table = Table::new(&["TIMER", "TIMER_CLK", "RATE", "PSC", "ARR", "ACTUAL", "PPM"]);
+        for timer in timers.iter() {
+            let timer_clk = clocks.timer_clock(timer.bus);
+            for &rate in options.rates.iter() {
+                for setting in bus::timer_settings(timer, timer_clk, rate, options.limit) {
+                    table.rows.push(vec![
+                        Cell::Text(timer.name.to_string()),
+                        Cell::Number(floatify(&timer_clk)),
+                        Cell::Number(floatify(&rate)),
+                        Cell::Integer(setting.psc),
+                        Cell::Integer(setting.arr),
+                        Cell::Number(floatify(&setting.actual)),
+                        Cell::Ppm(ppm(&setting.error)),
+                    ]);
                 }
+            }
+        }
+        table
+    };
 
-                for q in q_range.clone().rev() {
-                    let pll48_clk = vco_output / q;
-                    if !VALID_PLL48_OUT.contains(&pll48_clk) {
-                        continue;
-                    }
+    print!("{}", table.render(options.format));
+}
 
-                    for i2sn in i2sn_range.clone() {
-                        let i2s_vco_output = vco_input * i2sn;
-                        if !VALID_VCO_OUTPUT.contains(&i2s_vco_output) {
-                            continue;
-                        }
+fn sample_rate_accuracy(chip: &Chip, hse: Rational, options: &AccuracyOptions) {
+    let ranges = Plli2sRanges {
+        m: fixed_or(
+            options.m,
+            "PLLI2SM",
+            chip.plli2s.m.as_ref().unwrap_or(&chip.pllm),
+        ),
+        n: fixed_or(options.plli2sn, "PLLI2SN", &chip.plli2s.n),
+        r: fixed_or(
+            options.plli2sr,
+            "PLLI2SR",
+            chip.plli2s
+                .r
+                .as_ref()
+                .expect("every chip's PLLI2S has an R output for I2S"),
+        ),
+    };
+
+    // a single I2S clock, either given outright or by a fully-specified PLLI2S
+    let i2s_clk = match (options.i2s_clk, options.m, options.plli2sn, options.plli2sr) {
+        (Some(i2s_clk), _, _, _) => Some(i2s_clk),
+        (None, Some(m), Some(n), Some(r)) => {
+            let vco_input = hse / m;
+            let vco_output = vco_input * n;
+            let i2s_clk = vco_output / r;
+            let limits = [
+                ("VCO input", vco_input, &chip.vco_input),
+                ("VCO output", vco_output, &chip.vco_output),
+                ("PLLI2S output", i2s_clk, &chip.plli2s_out),
+            ];
+            for (name, clock, range) in limits.iter() {
+                if !range.contains(clock) {
+                    eprintln!(
+                        "{} of {}Hz is outside of {}Hz to {}Hz",
+                        name,
+                        floatify(clock),
+                        floatify(range.start()),
+                        floatify(range.end())
+                    );
+                    std::process::exit(1);
+                }
+            }
+            Some(i2s_clk)
+        }
+        _ => None,
+    };
 
-                        for i2sr in i2sr_range.clone() {
-                            let i2s_clock = i2s_vco_output / i2sr;
-                            if !VALID_PLL_OUT.contains(&i2s_clock) {
-                                continue;
-                            }
-
-                            for (i2sdiv, odd) in
-                                i2sdiv_range.clone().cartesian_product(odd_range.clone())
-                            {
-                                let mclk = i2s_clock / (2 * i2sdiv + odd);
-                                if !DESIRED_I2S_MCLK.contains(&mclk) {
-                                    continue;
-                                }
-
-                                println!(
-                                    "{},{},{},{},{},{},{},{},{},{},{}",
-                                    m,
-                                    n,
-                                    p,
-                                    q,
-                                    i2sn,
-                                    i2sr,
-                                    i2sdiv,
-                                    odd,
-                                    floatify(&pll_clk),
-                                    floatify(&pll48_clk),
-                                    floatify(&mclk),
-                                )
-                            }
+    let table = match i2s_clk {
+        Some(i2s_clk) => {
+            let mut table = Table::new(&["FRAME", "I2SDIV", "ODD", "FS", "NEAREST", "PPM"]);
+            for &frame in I2sFrame::ALL.iter() {
+                for setting in accuracy::every_divider(chip, i2s_clk, frame, &options.sample_rates)
+                {
+                    if let Some(max_error) = options.max_error {
+                        if setting.error.abs() > max_error {
+                            continue;
                         }
                     }
+                    table.rows.push(vec![
+                        Cell::Text(frame.name().to_string()),
+                        Cell::Integer(setting.i2sdiv),
+                        Cell::Integer(setting.odd),
+                        Cell::Number(floatify(&setting.actual)),
+                        Cell::Number(floatify(&setting.desired)),
+                        Cell::Ppm(ppm(&setting.error)),
+                    ]);
+                }
+            }
+            table
+        }
+        None => {
+            let mut table = Table::new(&[
+                "FRAME", "FS", "I2SM", "I2SN", "I2SR", "I2SDIV", "ODD", "ACTUAL", "PPM",
+            ]);
+            for &frame in I2sFrame::ALL.iter() {
+                for &desired in options.sample_rates.iter() {
+                    let mut row = vec![
+                        Cell::Text(frame.name().to_string()),
+                        Cell::Number(floatify(&desired)),
+                    ];
+                    match accuracy::best_plli2s(chip, hse, &ranges, desired, frame) {
+                        Some((dividers, setting)) => row.extend(vec![
+                            Cell::Integer(dividers.m),
+                            Cell::Integer(dividers.n),
+                            Cell::Integer(dividers.r),
+                            Cell::Integer(setting.i2sdiv),
+                            Cell::Integer(setting.odd),
+                            Cell::Number(floatify(&setting.actual)),
+                            Cell::Ppm(ppm(&setting.error)),
+                        ]),
+                        // unreachable with these dividers; leave a hole rather than hiding the row
+                        None => row.extend(std::iter::repeat(Cell::Blank).take(7)),
+                    }
+                    table.rows.push(row);
+                }
+            }
+            table
+        }
+    };
+
+    print!("{}", table.render(options.format));
+}
+
+fn check_configuration(chip: &Chip, hse: Rational, options: &CheckOptions) {
+    let voltage_scale = match options.voltage_scale {
+        Some(scale) => match chip.voltage_scale(scale) {
+            Some(voltage_scale) => voltage_scale,
+            None => {
+                let scales = chip.voltage_scales.iter().map(|vos| vos.scale).join(", ");
+                eprintln!("{} only has voltage scales {}", chip.name, scales);
+                std::process::exit(1);
+            }
+        },
+        None => &chip.voltage_scales[0],
+    };
+    let configuration = options.registers.configuration(chip);
+
+    let report = check::check(chip, hse, &configuration, voltage_scale, options.overdrive);
+    match options.format {
+        CheckFormat::Table(format) => print!("{}", check_table(&report).render(format)),
+        CheckFormat::Dot => {
+            if let Some((main, i2s)) = configuration.clocks(hse) {
+                let tree = Tree {
+                    chip,
+                    hse,
+                    main: &main,
+                    i2s: i2s.as_ref(),
+                    i2s_frame: configuration.frame,
+                    channel_bits: options.registers.channel_length.into(),
+                    sysclk: check::sysclk_limits(chip, voltage_scale, options.overdrive),
+                };
+                print!("{}", graph::dot(&tree));
+            }
+        }
+    }
+
+    if !report.violations.is_empty() {
+        for violation in report.violations.iter() {
+            eprintln!("{}", violation);
+        }
+        std::process::exit(1);
+    }
+}
+
+fn check_table(report: &check::Report) -> Table {
+    let mut table = Table::new(&["CLOCK", "FREQUENCY", "MIN", "MAX", "OK"]);
+    for clock in report.clocks.iter() {
+        let (min, max) = match &clock.limits {
+            Some(limits) => (
+                Cell::Number(floatify(limits.start())),
+                Cell::Number(floatify(limits.end())),
+            ),
+            None => (Cell::Blank, Cell::Blank),
+        };
+        table.rows.push(vec![
+            Cell::Text(clock.name.to_string()),
+            Cell::Number(floatify(&clock.frequency)),
+            min,
+            max,
+            Cell::Text(if clock.in_range() { "yes" } else { "NO" }.to_string()),
+        ]);
+    }
+    table
+}
+
+fn search_mco(chip: &'static Chip, hse: Rational, options: &McoOptions) {
+    let main = match (options.m, options.n, options.p) {
+        (Some(m), Some(n), Some(p)) => {
+            fixed_or(Some(m), "PLLM", &chip.pllm);
+            fixed_or(Some(n), "PLLN", &chip.plln);
+            if !chip.pllp.contains(&p) {
+                eprintln!("PLLP {} must be one of {:?}", p, chip.pllp);
+                std::process::exit(1);
+            }
+            Some(mco::MainPll { m, n, p })
+        }
+        _ => None,
+    };
+    if options.plli2sm.is_some() && chip.plli2s_shares_pllm() {
+        eprintln!("{} has no PLLI2SM; PLLI2S shares PLLM", chip.name);
+        std::process::exit(1);
+    }
+    let plli2s = match (options.plli2sn, options.plli2sr) {
+        (Some(n), Some(r)) => {
+            let m = match (options.plli2sm, main) {
+                (Some(m), _) => m,
+                (None, Some(main)) if chip.plli2s_shares_pllm() => main.m,
+                _ => {
+                    eprintln!("a fixed PLLI2S also needs its input divider, from -m or --plli2sm");
+                    std::process::exit(1);
+                }
+            };
+            Some(I2sDividers { m, n, r })
+        }
+        _ => None,
+    };
+
+    let search = McoSearch {
+        chip,
+        hse,
+        main,
+        plli2s,
+        target: options.target,
+        tolerance: options.tolerance,
+    };
+    let settings = mco::search(&search);
+    if settings.is_empty() && options.target > chip.gpio_max {
+        eprintln!(
+            "the MCO pins cannot output more than {}Hz",
+            floatify(&chip.gpio_max)
+        );
+        std::process::exit(1);
+    }
+    if settings.is_empty() {
+        eprintln!("no MCO settings are close enough to the target");
+        std::process::exit(1);
+    }
+
+    let mut table = Table::new(&[
+        "MCO",
+        "PIN",
+        "SOURCE",
+        "PRESCALER",
+        "I2SM",
+        "I2SN",
+        "I2SR",
+        "FREQUENCY",
+        "PPM",
+    ]);
+    for setting in settings.iter().take(options.limit) {
+        let (i2sm, i2sn, i2sr) = match setting.plli2s {
+            Some(d) => (Cell::Integer(d.m), Cell::Integer(d.n), Cell::Integer(d.r)),
+            None => (Cell::Blank, Cell::Blank, Cell::Blank),
+        };
+        table.rows.push(vec![
+            Cell::Text(setting.output.name().to_string()),
+            Cell::Text(setting.output.pin().to_string()),
+            Cell::Text(setting.source.name().to_string()),
+            Cell::Integer(setting.prescaler),
+            i2sm,
+            i2sn,
+            i2sr,
+            Cell::Number(floatify(&setting.frequency)),
+            Cell::Ppm(ppm(&setting.error)),
+        ]);
+    }
+    print!("{}", table.render(options.format));
+}
+
+fn rank_crystals(chip: &'static Chip, options: &CrystalOptions) {
+    let targets = Targets {
+        sysclk: options.sysclk.unwrap_or(*chip.pll_out.end()),
+        families: vec![options.family_44k1.clone(), options.family_48k.clone()],
+        i2s_frame: match (options.no_mclk, options.channel_length) {
+            (false, _) => I2sFrame::MasterClock,
+            (true, 16) => I2sFrame::Channel16,
+            (true, _) => I2sFrame::Channel32,
+        },
+    };
+    let crystals = if options.crystals.is_empty() {
+        crystal::STANDARD_CRYSTALS
+            .iter()
+            .map(|&hz| Rational::from_integer(hz))
+            .collect()
+    } else {
+        options.crystals.clone()
+    };
+
+    let mut ratings = crystals
+        .into_iter()
+        .filter_map(|hse| crystal::rate(chip, hse, &targets))
+        .collect::<Vec<_>>();
+    // the sort is stable, so equally-good crystals stay in ascending order
+    ratings.sort_by_key(|rating| (rating.worst_error().is_none(), rating.worst_error()));
+
+    let mut table = Table::new(&[
+        "HSE",
+        "M",
+        "N",
+        "P",
+        "Q",
+        "SYSCLK",
+        "SYSCLK_PPM",
+        "USB_PPM",
+        "FS_44K1_PPM",
+        "FS_48K_PPM",
+        "BOTH_PPM",
+        "WORST_PPM",
+    ]);
+    // errors are magnitudes here, and a blank means some rate is out of reach entirely
+    let error = |error: Option<Rational>| error.map_or(Cell::Blank, |e| Cell::Ppm(ppm(&e)));
+    for rating in ratings.iter() {
+        let mut row = vec![
+            Cell::Number(floatify(&rating.hse)),
+            Cell::Integer(rating.main.m),
+            Cell::Integer(rating.main.n),
+            Cell::Integer(rating.main.p),
+            Cell::Integer(rating.main.q),
+            Cell::Number(floatify(&rating.main.sysclk)),
+            Cell::Ppm(ppm(&rating.sysclk_error)),
+            Cell::Ppm(ppm(&rating.main.usb_error)),
+        ];
+        row.extend(rating.families.iter().map(|&family| error(family)));
+        row.push(error(rating.combined));
+        row.push(error(rating.worst_error()));
+        table.rows.push(row);
+    }
+    print!("{}", table.render(options.format));
+}
+
+fn analyze_drift(chip: &Chip, hse: Rational, options: &DriftOptions) {
+    let configuration = options.registers.configuration(chip);
+    let clocks = |configuration: &Configuration, input: Rational| match configuration.clocks(input)
+    {
+        Some(clocks) => clocks,
+        None => {
+            eprintln!("PLLM, PLLP and PLLQ must not be zero; see the check subcommand");
+            std::process::exit(1);
+        }
+    };
+
+    // the audio target stays where HSE puts it, even when running from HSI
+    let (_, i2s) = clocks(&configuration, hse);
+    let sample_rate = options.sample_rate.or_else(|| {
+        i2s.as_ref()
+            .and_then(|i2s| i2s.sample_rates.first())
+            .map(|rate| drift::nearest_standard_rate(rate.actual))
+    });
 
-                    // only check the largest value of `q` that fits the criteria, corresponding to
-                    // the slowest PLL48 clock we can make with the chosen M and N settings -- i.e.
-                    // the closest to 48MHz.
-                    break;
+    let mut sources = vec![("HSE", hse, options.hse_tolerance, configuration)];
+    if options.hsi_fallback {
+        let fallback = drift::hsi_fallback(chip, hse, &sources[0].3);
+        sources.push((
+            "HSI",
+            Rational::from_integer(mco::HSI),
+            options.hsi_tolerance,
+            fallback,
+        ));
+    }
+
+    let mut table = Table::new(&[
+        "SOURCE",
+        "PLLM",
+        "CLOCK",
+        "NOMINAL",
+        "SLOWEST",
+        "FASTEST",
+        "WORST_PPM",
+        "ALLOWED_PPM",
+        "OK",
+    ]);
+    let mut violations = Vec::new();
+    for (source, input, tolerance, configuration) in sources.iter() {
+        let (main, i2s) = clocks(configuration, *input);
+        let mut budgets = vec![
+            Budget {
+                name: "SYSCLK",
+                nominal: main.sysclk,
+                target: main.sysclk,
+                allowed: None,
+            },
+            Budget {
+                name: "PLL48CLK",
+                nominal: main.pll48_clk,
+                target: Rational::from_integer(48_000_000),
+                allowed: Some(options.usb_tolerance),
+            },
+        ];
+        if let Some(i2s) = i2s {
+            budgets.push(Budget {
+                name: "I2SCLK",
+                nominal: i2s.i2s_clk,
+                target: i2s.i2s_clk,
+                allowed: None,
+            });
+            if let (Some(rate), Some(target)) = (i2s.sample_rates.first(), sample_rate) {
+                if configuration.frame == I2sFrame::MasterClock {
+                    budgets.push(Budget {
+                        name: "MCLK",
+                        nominal: rate.actual * 256,
+                        target: target * 256,
+                        allowed: Some(options.audio_tolerance),
+                    });
                 }
+                budgets.push(Budget {
+                    name: "FS",
+                    nominal: rate.actual,
+                    target,
+                    allowed: Some(options.audio_tolerance),
+                });
+            }
+        }
 
-                // only check the smallest value of `p` that fits the criteria, corresponding to the
-                // fastest core clock we can make with the already-chosen M and N settings.
-                break;
+        for clock in drift::propagate(&budgets, *tolerance) {
+            if !clock.in_spec() {
+                violations.push(format!(
+                    "{} from {} could be off by {:.0}ppm",
+                    clock.name,
+                    source,
+                    ppm(&clock.worst_error)
+                ));
             }
+            table.rows.push(vec![
+                Cell::Text(source.to_string()),
+                Cell::Integer(configuration.m),
+                Cell::Text(clock.name.to_string()),
+                Cell::Number(floatify(&clock.nominal)),
+                Cell::Number(floatify(&clock.slowest)),
+                Cell::Number(floatify(&clock.fastest)),
+                Cell::Ppm(ppm(&clock.worst_error)),
+                clock
+                    .allowed
+                    // a limit rather than an error, so it is not colored like one
+                    .map_or(Cell::Blank, |allowed| Cell::Number(ppm(&allowed))),
+                Cell::Text(if clock.in_spec() { "yes" } else { "NO" }.to_string()),
+            ]);
+        }
+    }
+    print!("{}", table.render(options.format));
+
+    if !violations.is_empty() {
+        for violation in violations.iter() {
+            eprintln!("{}", violation);
         }
+        std::process::exit(1);
     }
 }
 
-fn floatify(input: &Rational) -> f64 {
-    *input.numer() as f64 / *input.denom() as f64
+/// Narrows `default` down to a single value given on the command line, after checking that the
+/// value is legal for this chip.
+fn fixed_or(fixed: Option<i64>, name: &str, default: &RangeInclusive<i64>) -> RangeInclusive<i64> {
+    match fixed {
+        Some(value) if default.contains(&value) => value..=value,
+        Some(value) => {
+            eprintln!(
+                "{} {} must be between {} and {}",
+                name,
+                value,
+                default.start(),
+                default.end()
+            );
+            std::process::exit(1);
+        }
+        None => default.clone(),
+    }
 }
@@ -0,0 +1,89 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use tools::upload::{self, Capture};
+use tools::{parse, Rational};
+
+/// Send a WAV or CSV capture to the signal generator in the firmware's `audio` binary, which then
+/// loops it through the DAC.
+#[derive(StructOpt)]
+struct Options {
+    /// The capture: a WAV file, or a CSV file with one sample per line (the last column, if there
+    /// are several)
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The signal generator's USB serial port, which has to be in raw mode first, e.g. with
+    /// `stty -F /dev/ttyACM0 raw`
+    #[structopt(long, default_value = "/dev/ttyACM0", parse(from_os_str))]
+    device: PathBuf,
+
+    /// Rate to play the samples at; defaults to a WAV file's own rate, and is required for CSV
+    #[structopt(long, parse(try_from_str = parse::frequency))]
+    sample_rate: Option<Rational>,
+
+    /// Peak-to-peak output in millivolts that the capture's peaks are stretched to
+    #[structopt(long, default_value = "2700")]
+    mvpp: u32,
+}
+
+fn main() {
+    let options = Options::from_args();
+
+    let bytes = fs::read(&options.input).unwrap_or_else(|e| {
+        eprintln!("cannot read {}: {}", options.input.display(), e);
+        std::process::exit(1);
+    });
+    let capture = if bytes.starts_with(b"RIFF") {
+        upload::read_wav(&bytes)
+    } else {
+        String::from_utf8(bytes)
+            .map_err(|_| "not a WAV file or a text CSV file".to_string())
+            .and_then(|text| upload::read_csv(&text))
+    };
+    let Capture {
+        samples,
+        sample_rate,
+    } = capture.unwrap_or_else(|e| {
+        eprintln!("{}: {}", options.input.display(), e);
+        std::process::exit(1);
+    });
+
+    let sample_rate = match (options.sample_rate, sample_rate) {
+        (Some(rate), _) if rate.is_integer() => *rate.numer() as u32,
+        (Some(_), _) => {
+            eprintln!("the sample rate must be a whole number of Hz");
+            std::process::exit(1);
+        }
+        (None, Some(rate)) => rate,
+        (None, None) => {
+            eprintln!("CSV files need a --sample-rate");
+            std::process::exit(1);
+        }
+    };
+
+    let codes = upload::dac_codes(&samples, options.mvpp);
+    let frame = upload::frame(&codes, sample_rate).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .open(&options.device)
+        .and_then(|mut device| device.write_all(&frame));
+    if let Err(e) = result {
+        eprintln!("cannot write to {}: {}", options.device.display(), e);
+        std::process::exit(1);
+    }
+
+    println!(
+        "sent {} samples to play at {}Hz ({:.3}ms per loop)",
+        codes.len(),
+        sample_rate,
+        codes.len() as f64 * 1000.0 / sample_rate as f64
+    );
+}
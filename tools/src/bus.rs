@@ -0,0 +1,231 @@
+//! The AHB and APB bus clocks derived from SYSCLK, the timer kernel clocks derived from those, and
+//! the timer prescaler and auto-reload settings that produce a given update rate.
+
+use num_traits::Signed;
+
+use crate::chip::{Bus, Chip, Timer};
+use crate::Rational;
+
+/// The HPRE divisions allowed in RCC_CFGR; note the lack of 32.
+pub const AHB_PRESCALERS: &[i64] = &[1, 2, 4, 8, 16, 64, 128, 256, 512];
+/// The PPRE1 and PPRE2 divisions allowed in RCC_CFGR.
+pub const APB_PRESCALERS: &[i64] = &[1, 2, 4, 8, 16];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Prescalers {
+    pub ahb: i64,
+    pub apb1: i64,
+    pub apb2: i64,
+    /// The TIMPRE bit of RCC_DCKCFGR
+    pub timpre: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct BusClocks {
+    pub sysclk: Rational,
+    pub hclk: Rational,
+    pub pclk1: Rational,
+    pub pclk2: Rational,
+    /// The kernel clock of the timers on APB1
+    pub apb1_timers: Rational,
+    /// The kernel clock of the timers on APB2
+    pub apb2_timers: Rational,
+}
+
+impl BusClocks {
+    pub fn new(sysclk: Rational, prescalers: &Prescalers) -> Self {
+        let hclk = sysclk / prescalers.ahb;
+        let pclk1 = hclk / prescalers.apb1;
+        let pclk2 = hclk / prescalers.apb2;
+
+        BusClocks {
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            apb1_timers: timer_clock(hclk, prescalers.apb1, prescalers.timpre),
+            apb2_timers: timer_clock(hclk, prescalers.apb2, prescalers.timpre),
+        }
+    }
+
+    pub fn timer_clock(&self, bus: Bus) -> Rational {
+        match bus {
+            Bus::Apb1 => self.apb1_timers,
+            Bus::Apb2 => self.apb2_timers,
+        }
+    }
+
+    /// Describes every bus clock that is faster than `chip` allows.
+    pub fn violations(&self, chip: &Chip) -> Vec<String> {
+        let limits = [
+            ("HCLK", self.hclk, *chip.pll_out.end()),
+            ("PCLK1", self.pclk1, chip.pclk1_max),
+            ("PCLK2", self.pclk2, chip.pclk2_max),
+        ];
+
+        limits
+            .iter()
+            .filter(|(_, clock, max)| clock > max)
+            .map(|(name, clock, max)| {
+                format!(
+                    "{} of {}Hz exceeds the {}Hz maximum",
+                    name,
+                    crate::floatify(clock),
+                    crate::floatify(max)
+                )
+            })
+            .collect()
+    }
+}
+
+/// The kernel clock of the timers on an APB with the given prescaler.
+pub fn timer_clock(hclk: Rational, apb_prescaler: i64, timpre: bool) -> Rational {
+    let multiplier = clock_model::bus::timer_multiplier(apb_prescaler as u64, timpre);
+    hclk / apb_prescaler * multiplier as i64
+}
+
+/// The smallest prescalers that keep every bus within `chip`'s limits, which is what the HAL picks.
+pub fn fastest_prescalers(chip: &Chip, sysclk: Rational, timpre: bool) -> Option<Prescalers> {
+    let smallest = |max: Rational, input: Rational, choices: &[i64]| {
+        choices.iter().copied().find(|&div| input / div <= max)
+    };
+
+    let ahb = smallest(*chip.pll_out.end(), sysclk, AHB_PRESCALERS)?;
+    let hclk = sysclk / ahb;
+    Some(Prescalers {
+        ahb,
+        apb1: smallest(chip.pclk1_max, hclk, APB_PRESCALERS)?,
+        apb2: smallest(chip.pclk2_max, hclk, APB_PRESCALERS)?,
+        timpre,
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct TimerSetting {
+    /// The value written to TIMx_PSC, which divides by one more than this
+    pub psc: i64,
+    /// The value written to TIMx_ARR, which divides by one more than this
+    pub arr: i64,
+    pub actual: Rational,
+    /// Relative deviation of `actual` from the desired rate
+    pub error: Rational,
+}
+
+/// Finds the PSC and ARR values whose update rate is closest to `desired`, best first.  Among
+/// equally accurate settings the smallest prescaler wins, since it leaves the counter the most
+/// resolution.
+pub fn timer_settings(
+    timer: &Timer,
+    timer_clk: Rational,
+    desired: Rational,
+    limit: usize,
+) -> Vec<TimerSetting> {
+    let max_reload = 1i64 << timer.counter_bits;
+    let ideal = timer_clk / desired;
+    let mut result = Vec::new();
+
+    for prescale in 1..=65536 {
+        let reload = ideal / prescale;
+        if reload > Rational::from_integer(max_reload) {
+            continue;
+        }
+        if reload < Rational::from_integer(1) {
+            break;
+        }
+
+        let candidates = [reload.floor().to_integer(), reload.ceil().to_integer()];
+        let best = candidates
+            .iter()
+            // ARR = 0 stops the counter, so it never reaches an update event
+            .filter(|&&reload| (2..=max_reload).contains(&reload))
+            .map(|&reload| {
+                let actual = timer_clk / (prescale * reload);
+                TimerSetting {
+                    psc: prescale - 1,
+                    arr: reload - 1,
+                    actual,
+                    error: actual / desired - 1,
+                }
+            })
+            .min_by_key(|setting| setting.error.abs());
+        result.extend(best);
+    }
+
+    // the sort is stable, so ties stay ordered by prescaler
+    result.sort_by_key(|setting| setting.error.abs());
+    result.truncate(limit);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip;
+
+    fn mhz(value: i64) -> Rational {
+        Rational::from_integer(value * 1_000_000)
+    }
+
+    #[test]
+    fn prescalers_for_168mhz_match_the_hal() {
+        let chip = chip::by_name("f407").unwrap();
+        let prescalers = fastest_prescalers(chip, mhz(168), false).unwrap();
+        assert_eq!(
+            (prescalers.ahb, prescalers.apb1, prescalers.apb2),
+            (1, 4, 2)
+        );
+        let clocks = BusClocks::new(mhz(168), &prescalers);
+        assert_eq!(clocks.pclk1, mhz(42));
+        assert_eq!(clocks.pclk2, mhz(84));
+        assert_eq!(clocks.timer_clock(Bus::Apb1), mhz(84));
+        assert_eq!(clocks.timer_clock(Bus::Apb2), mhz(168));
+        assert!(clocks.violations(chip).is_empty());
+    }
+
+    #[test]
+    fn violations_name_the_fast_bus() {
+        let chip = chip::by_name("f407").unwrap();
+        let prescalers = Prescalers {
+            ahb: 1,
+            apb1: 2,
+            apb2: 2,
+            timpre: false,
+        };
+        let violations = BusClocks::new(mhz(168), &prescalers).violations(chip);
+        assert_eq!(
+            violations,
+            ["PCLK1 of 84000000Hz exceeds the 42000000Hz maximum"]
+        );
+    }
+
+    #[test]
+    fn timer_settings_prefer_exact_rates_and_small_prescalers() {
+        let chip = chip::by_name("f407").unwrap();
+        let tim4 = chip
+            .timers
+            .iter()
+            .find(|timer| timer.name == "TIM4")
+            .unwrap();
+
+        let best = &timer_settings(tim4, mhz(84), Rational::new(21_000_000, 2), 1)[0];
+        assert_eq!((best.psc, best.arr), (0, 7));
+        assert_eq!(best.error, Rational::from_integer(0));
+
+        // 84MHz needs a prescaler of at least 1282 to fit 1Hz in 16 bits, and 1344 is the first
+        // that divides it exactly
+        let best = &timer_settings(tim4, mhz(84), Rational::from_integer(1), 1)[0];
+        assert_eq!((best.psc, best.arr), (1343, 62_499));
+        assert_eq!(best.error, Rational::from_integer(0));
+    }
+
+    #[test]
+    fn timer_settings_never_offer_arr_zero() {
+        let chip = chip::by_name("f407").unwrap();
+        let tim4 = chip
+            .timers
+            .iter()
+            .find(|timer| timer.name == "TIM4")
+            .unwrap();
+        assert!(timer_settings(tim4, mhz(84), mhz(84), 10).is_empty());
+    }
+}
@@ -0,0 +1,343 @@
+//! Audits a concrete set of register values, like the ones hardcoded in a firmware binary, against
+//! a chip's datasheet limits.
+
+use std::ops::RangeInclusive;
+
+use crate::chip::{Chip, I2sSource, VoltageScale};
+use crate::solver::{I2sClock, I2sDividers, I2sFrame, MainPll, SampleRateSetting};
+use crate::{floatify, Rational};
+
+/// The divider values as they would be written to RCC_PLLCFGR, RCC_PLLI2SCFGR and SPI_I2SPR.
+pub struct Configuration {
+    pub m: i64,
+    pub n: i64,
+    pub p: i64,
+    pub q: i64,
+    pub plli2s: Option<I2sDividers>,
+    /// I2SDIV and ODD
+    pub i2s_prescaler: Option<(i64, i64)>,
+    pub frame: I2sFrame,
+}
+
+impl Configuration {
+    /// The main PLL and I2S clocks in the form the solver produces, or `None` for the main PLL if
+    /// a divider is zero.
+    pub fn clocks(&self, hse: Rational) -> Option<(MainPll, Option<I2sClock>)> {
+        if self.m < 1 || self.p < 1 || self.q < 1 {
+            return None;
+        }
+        let vco_output = hse / self.m * self.n;
+        let pll48_clk = vco_output / self.q;
+        let main = MainPll {
+            m: self.m,
+            n: self.n,
+            p: self.p,
+            q: self.q,
+            sysclk: vco_output / self.p,
+            pll48_clk,
+            usb_error: pll48_clk / 48_000_000 - 1,
+        };
+
+        let i2s = self.plli2s.filter(|d| d.m > 0 && d.r > 0).map(|dividers| {
+            let i2s_clk = hse / dividers.m * dividers.n / dividers.r;
+            let sample_rates = self
+                .i2s_prescaler
+                .filter(|&(i2sdiv, odd)| 2 * i2sdiv + odd > 0)
+                .map(|(i2sdiv, odd)| {
+                    let actual = i2s_clk / ((2 * i2sdiv + odd) * self.frame.clocks_per_sample());
+                    SampleRateSetting {
+                        desired: actual,
+                        i2sdiv,
+                        odd,
+                        actual,
+                        error: Rational::from_integer(0),
+                    }
+                })
+                .into_iter()
+                .collect();
+            I2sClock {
+                source: I2sSource::PllI2s,
+                dividers: Some(dividers),
+                i2s_clk,
+                sample_rates,
+            }
+        });
+
+        Some((main, i2s))
+    }
+}
+
+pub struct DerivedClock {
+    pub name: &'static str,
+    pub frequency: Rational,
+    /// `None` for clocks that the datasheet does not constrain
+    pub limits: Option<RangeInclusive<Rational>>,
+}
+
+impl DerivedClock {
+    pub fn in_range(&self) -> bool {
+        self.limits
+            .as_ref()
+            .map_or(true, |limits| limits.contains(&self.frequency))
+    }
+}
+
+pub struct Report {
+    pub clocks: Vec<DerivedClock>,
+    /// Every broken limit, including dividers that cannot be programmed at all
+    pub violations: Vec<String>,
+}
+
+/// The range SYSCLK must be in, given the regulator settings.
+pub fn sysclk_limits(
+    chip: &Chip,
+    voltage_scale: &VoltageScale,
+    overdrive: bool,
+) -> RangeInclusive<Rational> {
+    let sysclk_max = match (overdrive, voltage_scale.overdrive_max) {
+        (true, Some(max)) => max,
+        _ => voltage_scale.sysclk_max,
+    };
+    *chip.pll_out.start()..=std::cmp::min(sysclk_max, *chip.pll_out.end())
+}
+
+/// Derives every clock from `configuration` and lists each datasheet limit it breaks.
+pub fn check(
+    chip: &Chip,
+    hse: Rational,
+    configuration: &Configuration,
+    voltage_scale: &VoltageScale,
+    overdrive: bool,
+) -> Report {
+    let mut violations = Vec::new();
+    let mut divider = |name: &str, value: i64, allowed: &RangeInclusive<i64>| {
+        if !allowed.contains(&value) {
+            violations.push(format!(
+                "{} = {} is not between {} and {}",
+                name,
+                value,
+                allowed.start(),
+                allowed.end()
+            ));
+        }
+    };
+
+    divider("PLLM", configuration.m, &chip.pllm);
+    divider("PLLN", configuration.n, &chip.plln);
+    divider("PLLQ", configuration.q, &chip.pllq);
+    if let Some(plli2s) = configuration.plli2s {
+        if let Some(allowed) = chip.plli2s.m.as_ref() {
+            divider("PLLI2SM", plli2s.m, allowed);
+        }
+        divider("PLLI2SN", plli2s.n, &chip.plli2s.n);
+        if let Some(allowed) = chip.plli2s.r.as_ref() {
+            divider("PLLI2SR", plli2s.r, allowed);
+        }
+    }
+    if let Some((i2sdiv, odd)) = configuration.i2s_prescaler {
+        divider("I2SDIV", i2sdiv, &chip.i2sdiv);
+        divider("ODD", odd, &(0..=1));
+    }
+    if !chip.pllp.contains(&configuration.p) {
+        violations.push(format!(
+            "PLLP = {} is not one of {:?}",
+            configuration.p, chip.pllp
+        ));
+    }
+    if overdrive && voltage_scale.overdrive_max.is_none() {
+        violations.push(format!(
+            "over-drive is not available in voltage scale {}",
+            voltage_scale.scale
+        ));
+    }
+
+    // nothing can be derived from a divider of zero, beyond the violation already reported
+    let plli2s_positive = configuration
+        .plli2s
+        .map_or(true, |plli2s| plli2s.m > 0 && plli2s.r > 0);
+    if configuration.m < 1 || configuration.p < 1 || configuration.q < 1 || !plli2s_positive {
+        return Report {
+            clocks: Vec::new(),
+            violations,
+        };
+    }
+
+    let vco_input = hse / configuration.m;
+    let vco_output = vco_input * configuration.n;
+    let mut clocks = vec![
+        DerivedClock {
+            name: "VCO_IN",
+            frequency: vco_input,
+            limits: Some(chip.vco_input.clone()),
+        },
+        DerivedClock {
+            name: "VCO_OUT",
+            frequency: vco_output,
+            limits: Some(chip.vco_output.clone()),
+        },
+        DerivedClock {
+            name: "SYSCLK",
+            frequency: vco_output / configuration.p,
+            limits: Some(sysclk_limits(chip, voltage_scale, overdrive)),
+        },
+        DerivedClock {
+            name: "PLL48CLK",
+            frequency: vco_output / configuration.q,
+            limits: Some(chip.pll48_out.clone()),
+        },
+    ];
+
+    if let Some(plli2s) = configuration.plli2s {
+        let i2s_vco_input = hse / plli2s.m;
+        let i2s_vco_output = i2s_vco_input * plli2s.n;
+        let i2s_clk = i2s_vco_output / plli2s.r;
+        if !chip.plli2s_shares_pllm() {
+            clocks.push(DerivedClock {
+                name: "PLLI2S_VCO_IN",
+                frequency: i2s_vco_input,
+                limits: Some(chip.vco_input.clone()),
+            });
+        }
+        clocks.push(DerivedClock {
+            name: "PLLI2S_VCO_OUT",
+            frequency: i2s_vco_output,
+            limits: Some(chip.vco_output.clone()),
+        });
+        clocks.push(DerivedClock {
+            name: "I2SCLK",
+            frequency: i2s_clk,
+            limits: Some(chip.plli2s_out.clone()),
+        });
+
+        if let Some((i2sdiv, odd)) = configuration.i2s_prescaler {
+            let divider = 2 * i2sdiv + odd;
+            // a zero divider is already reported above, and would otherwise divide by zero here
+            if divider > 0 {
+                let fs = i2s_clk / (divider * configuration.frame.clocks_per_sample());
+                if configuration.frame == I2sFrame::MasterClock {
+                    clocks.push(DerivedClock {
+                        name: "MCLK",
+                        frequency: fs * 256,
+                        limits: None,
+                    });
+                }
+                clocks.push(DerivedClock {
+                    name: "FS",
+                    frequency: fs,
+                    limits: None,
+                });
+            }
+        }
+    }
+
+    for clock in clocks.iter().filter(|clock| !clock.in_range()) {
+        let limits = clock
+            .limits
+            .as_ref()
+            .expect("only limited clocks can be out of range");
+        violations.push(format!(
+            "{} of {}Hz is outside of {}Hz to {}Hz",
+            clock.name,
+            floatify(&clock.frequency),
+            floatify(limits.start()),
+            floatify(limits.end())
+        ));
+    }
+
+    Report { clocks, violations }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip;
+
+    /// RM0090's example: 168MHz and 48MHz from an 8MHz crystal, and 48kHz audio with MCLK.
+    fn rm0090() -> Configuration {
+        Configuration {
+            m: 8,
+            n: 336,
+            p: 2,
+            q: 7,
+            plli2s: Some(I2sDividers { m: 8, n: 258, r: 3 }),
+            i2s_prescaler: Some((3, 1)),
+            frame: I2sFrame::MasterClock,
+        }
+    }
+
+    fn frequency(report: &Report, name: &str) -> Rational {
+        report
+            .clocks
+            .iter()
+            .find(|clock| clock.name == name)
+            .unwrap()
+            .frequency
+    }
+
+    #[test]
+    fn rm0090_example_is_within_limits() {
+        let chip = chip::by_name("f407").unwrap();
+        let report = check(
+            chip,
+            Rational::from_integer(8_000_000),
+            &rm0090(),
+            chip.voltage_scale(1).unwrap(),
+            false,
+        );
+        assert!(report.violations.is_empty(), "{:?}", report.violations);
+        assert_eq!(
+            frequency(&report, "SYSCLK"),
+            Rational::from_integer(168_000_000)
+        );
+        assert_eq!(
+            frequency(&report, "PLL48CLK"),
+            Rational::from_integer(48_000_000)
+        );
+        assert_eq!(
+            frequency(&report, "I2SCLK"),
+            Rational::from_integer(86_000_000)
+        );
+        assert_eq!(frequency(&report, "FS"), Rational::new(86_000_000, 7 * 256));
+    }
+
+    #[test]
+    fn reports_each_broken_limit() {
+        let chip = chip::by_name("f407").unwrap();
+        let configuration = Configuration {
+            p: 3,
+            q: 2,
+            i2s_prescaler: Some((1, 0)),
+            ..rm0090()
+        };
+        let report = check(
+            chip,
+            Rational::from_integer(8_000_000),
+            &configuration,
+            chip.voltage_scale(1).unwrap(),
+            false,
+        );
+        assert_eq!(
+            report.violations,
+            [
+                "I2SDIV = 1 is not between 2 and 255",
+                "PLLP = 3 is not one of [2, 4, 6, 8]",
+                "PLL48CLK of 168000000Hz is outside of 48000000Hz to 75000000Hz",
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_dividers_derive_nothing() {
+        let chip = chip::by_name("f407").unwrap();
+        let configuration = Configuration { m: 0, ..rm0090() };
+        let report = check(
+            chip,
+            Rational::from_integer(8_000_000),
+            &configuration,
+            chip.voltage_scale(1).unwrap(),
+            false,
+        );
+        assert!(report.clocks.is_empty());
+        assert_eq!(report.violations, ["PLLM = 0 is not between 2 and 63"]);
+    }
+}
@@ -0,0 +1,470 @@
+//! PLL limits and clock tree options for the STM32F4 parts the clock tool knows about.
+
+use std::ops::RangeInclusive;
+
+use clock_model::limits;
+
+use crate::Rational;
+
+/// Where the I2S peripherals can take their kernel clock from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum I2sSource {
+    /// The R output of PLLI2S
+    PllI2s,
+    /// The R output of the main PLL
+    PllR,
+    /// An external clock on the I2S_CKIN pin
+    Ckin,
+    /// The main PLL's input clock (HSE or HSI), without going through any PLL
+    PllSource,
+}
+
+impl I2sSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            I2sSource::PllI2s => "PLLI2S",
+            I2sSource::PllR => "PLLR",
+            I2sSource::Ckin => "I2S_CKIN",
+            I2sSource::PllSource => "HSE",
+        }
+    }
+}
+
+/// The peripheral buses that timers can hang off of.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bus {
+    Apb1,
+    Apb2,
+}
+
+impl Bus {
+    pub fn name(self) -> &'static str {
+        match self {
+            Bus::Apb1 => "APB1",
+            Bus::Apb2 => "APB2",
+        }
+    }
+}
+
+pub struct Timer {
+    pub name: &'static str,
+    pub bus: Bus,
+    /// Width of the counter and auto-reload register; the prescaler is always 16 bits
+    pub counter_bits: u32,
+}
+
+/// A setting of the regulator's VOS bits, which caps how fast the core may run.
+pub struct VoltageScale {
+    pub scale: u8,
+    pub sysclk_max: Rational,
+    /// The higher limit with over-drive enabled, on parts that have it
+    pub overdrive_max: Option<Rational>,
+}
+
+/// The FLASH_ACR latency each HCLK needs within one supply voltage range.
+pub struct FlashLatency {
+    /// The supply voltage range, in millivolts
+    pub vdd_mv: RangeInclusive<u32>,
+    /// The fastest HCLK allowed with 0, 1, 2... wait states, in MHz; the last entry is the fastest
+    /// HCLK at this voltage at all
+    pub hclk_max_mhz: &'static [i64],
+}
+
+/// The dividers of PLLI2S or PLLSAI.  A missing `m` means the PLL shares PLLM with the main PLL,
+/// and a missing `p`, `q` or `r` means that output does not exist on this part.
+pub struct AuxiliaryPll {
+    pub m: Option<RangeInclusive<i64>>,
+    pub n: RangeInclusive<i64>,
+    pub p: Option<&'static [i64]>,
+    pub q: Option<RangeInclusive<i64>>,
+    pub r: Option<RangeInclusive<i64>>,
+}
+
+pub struct Chip {
+    pub name: &'static str,
+    /// Other part numbers that share this clock tree
+    pub aliases: &'static [&'static str],
+
+    pub vco_input: RangeInclusive<Rational>,
+    pub vco_output: RangeInclusive<Rational>,
+    /// The range of each PLL's P and R outputs, whose upper end is the fastest allowed SYSCLK
+    pub pll_out: RangeInclusive<Rational>,
+    pub pll48_out: RangeInclusive<Rational>,
+    /// The range of the PLLI2S outputs that feed the I2S peripherals
+    pub plli2s_out: RangeInclusive<Rational>,
+
+    pub pllm: RangeInclusive<i64>,
+    pub plln: RangeInclusive<i64>,
+    pub pllp: &'static [i64],
+    pub pllq: RangeInclusive<i64>,
+    /// Only some parts can route a main PLL R output to SYSCLK or the I2S peripherals
+    pub pllr: Option<RangeInclusive<i64>>,
+
+    pub plli2s: AuxiliaryPll,
+    pub pllsai: Option<AuxiliaryPll>,
+    pub i2s_sources: &'static [I2sSource],
+
+    // I2SDIV = 0 and I2SDIV = 1 are forbidden, per the SPI_I2SPR documentation
+    pub i2sdiv: RangeInclusive<i64>,
+
+    /// The fastest allowed PCLK1 and PCLK2; HCLK is limited to the fastest SYSCLK
+    pub pclk1_max: Rational,
+    pub pclk2_max: Rational,
+    /// Whether RCC_DCKCFGR has a TIMPRE bit to run the timers even faster than 2 * PCLKx
+    pub timpre: bool,
+    pub timers: &'static [Timer],
+
+    /// The available voltage scales, highest performance first
+    pub voltage_scales: &'static [VoltageScale],
+    /// The flash wait states from the reference manual, highest supply voltage first
+    pub flash_latency: &'static [FlashLatency],
+    /// Typical run-mode current from flash with every peripheral clock off, in uA per MHz of HCLK
+    pub run_ua_per_mhz: i64,
+    /// The fastest clock a GPIO such as an MCO pin can output, at the fastest OSPEEDR setting
+    /// with a 30pF load and VDD of at least 2.7V
+    pub gpio_max: Rational,
+}
+
+impl Chip {
+    pub fn voltage_scale(&self, scale: u8) -> Option<&VoltageScale> {
+        self.voltage_scales.iter().find(|vos| vos.scale == scale)
+    }
+
+    /// Whether PLLI2S divides its input with the main PLL's PLLM, rather than its own PLLI2SM.
+    pub fn plli2s_shares_pllm(&self) -> bool {
+        self.plli2s.m.is_none()
+    }
+
+    /// The I2S kernel clock source selection is in RCC_DCKCFGR on parts with more than two choices,
+    /// and is the I2SSRC bit of RCC_CFGR otherwise.
+    pub fn i2s_source_in_dckcfgr(&self) -> bool {
+        self.i2s_sources.len() > 2
+    }
+}
+
+const fn mhz(value: i64) -> Rational {
+    Rational::new_raw(value * 1_000_000, 1)
+}
+
+const fn hz(value: u64) -> Rational {
+    Rational::new_raw(value as i64, 1)
+}
+
+const fn range(range: clock_model::Range) -> RangeInclusive<Rational> {
+    hz(range.min)..=hz(range.max)
+}
+
+const fn vos(scale: u8, sysclk_max: i64, overdrive_max: Option<Rational>) -> VoltageScale {
+    VoltageScale {
+        scale,
+        sysclk_max: mhz(sysclk_max),
+        overdrive_max,
+    }
+}
+
+const fn flash(vdd_mv: RangeInclusive<u32>, hclk_max_mhz: &'static [i64]) -> FlashLatency {
+    FlashLatency {
+        vdd_mv,
+        hclk_max_mhz,
+    }
+}
+
+const fn timer(name: &'static str, bus: Bus, counter_bits: u32) -> Timer {
+    Timer {
+        name,
+        bus,
+        counter_bits,
+    }
+}
+
+/// The timers of the F401 and F411, which lack the basic timers and the second advanced timer
+static TIMERS_F401: [Timer; 8] = [
+    timer("TIM1", Bus::Apb2, 16),
+    timer("TIM2", Bus::Apb1, 32),
+    timer("TIM3", Bus::Apb1, 16),
+    timer("TIM4", Bus::Apb1, 16),
+    timer("TIM5", Bus::Apb1, 32),
+    timer("TIM9", Bus::Apb2, 16),
+    timer("TIM10", Bus::Apb2, 16),
+    timer("TIM11", Bus::Apb2, 16),
+];
+
+static TIMERS_F407: [Timer; 14] = [
+    timer("TIM1", Bus::Apb2, 16),
+    timer("TIM2", Bus::Apb1, 32),
+    timer("TIM3", Bus::Apb1, 16),
+    timer("TIM4", Bus::Apb1, 16),
+    timer("TIM5", Bus::Apb1, 32),
+    timer("TIM6", Bus::Apb1, 16),
+    timer("TIM7", Bus::Apb1, 16),
+    timer("TIM8", Bus::Apb2, 16),
+    timer("TIM9", Bus::Apb2, 16),
+    timer("TIM10", Bus::Apb2, 16),
+    timer("TIM11", Bus::Apb2, 16),
+    timer("TIM12", Bus::Apb1, 16),
+    timer("TIM13", Bus::Apb1, 16),
+    timer("TIM14", Bus::Apb1, 16),
+];
+
+/// RM0368 table 6
+static FLASH_F401: [FlashLatency; 4] = [
+    flash(2700..=3600, &[30, 60, 84]),
+    flash(2400..=2700, &[24, 48, 72, 84]),
+    flash(2100..=2400, &[18, 36, 54, 72, 84]),
+    flash(1710..=2100, &[16, 32, 48, 64, 80, 84]),
+];
+
+/// RM0383 table 6
+static FLASH_F411: [FlashLatency; 4] = [
+    flash(2700..=3600, &[30, 64, 90, 100]),
+    flash(2400..=2700, &[24, 48, 72, 96, 100]),
+    flash(2100..=2400, &[18, 36, 54, 72, 90, 100]),
+    flash(1710..=2100, &[16, 32, 48, 64, 80, 96, 100]),
+];
+
+/// RM0090 table 10
+static FLASH_F407: [FlashLatency; 4] = [
+    flash(2700..=3600, &[30, 60, 90, 120, 150, 168]),
+    flash(2400..=2700, &[24, 48, 72, 96, 120, 144, 168]),
+    flash(2100..=2400, &[22, 44, 66, 88, 110, 132, 154, 168]),
+    flash(1800..=2100, &[20, 40, 60, 80, 100, 120, 140, 160]),
+];
+
+/// RM0090 table 11, which RM0390 repeats for the F446 apart from its 1.7V minimum
+static FLASH_F427: [FlashLatency; 4] = [
+    flash(2700..=3600, &FLASH_F427_2V7),
+    flash(2400..=2700, &FLASH_F427_2V4),
+    flash(2100..=2400, &FLASH_F427_2V1),
+    flash(1800..=2100, &FLASH_F427_1V8),
+];
+
+static FLASH_F446: [FlashLatency; 4] = [
+    flash(2700..=3600, &FLASH_F427_2V7),
+    flash(2400..=2700, &FLASH_F427_2V4),
+    flash(2100..=2400, &FLASH_F427_2V1),
+    flash(1700..=2100, &FLASH_F427_1V8),
+];
+
+static FLASH_F427_2V7: [i64; 6] = [30, 60, 90, 120, 150, 180];
+
+static FLASH_F427_2V4: [i64; 8] = [24, 48, 72, 96, 120, 144, 168, 180];
+
+static FLASH_F427_2V1: [i64; 9] = [22, 44, 66, 88, 110, 132, 154, 176, 180];
+
+static FLASH_F427_1V8: [i64; 9] = [20, 40, 60, 80, 100, 120, 140, 160, 168];
+
+pub static CHIPS: [Chip; 5] = [
+    Chip {
+        name: "f401",
+        aliases: &[],
+        vco_input: range(limits::F401.vco_input),
+        vco_output: range(limits::F401.vco_output),
+        pll_out: range(limits::F401.pll_out),
+        pll48_out: range(limits::F401.pll48_out),
+        plli2s_out: range(limits::F401.plli2s_out),
+        pllm: 2..=63,
+        plln: 192..=432,
+        pllp: &[2, 4, 6, 8],
+        pllq: 2..=15,
+        pllr: None,
+        plli2s: AuxiliaryPll {
+            m: None,
+            n: 192..=432,
+            p: None,
+            q: None,
+            r: Some(2..=7),
+        },
+        pllsai: None,
+        i2s_sources: &[I2sSource::PllI2s, I2sSource::Ckin],
+        i2sdiv: 2..=255,
+        pclk1_max: hz(limits::F401.pclk1_max),
+        pclk2_max: hz(limits::F401.pclk2_max),
+        timpre: true,
+        timers: &TIMERS_F401,
+        // RM0368 only has scales 2 and 3
+        voltage_scales: &[vos(2, 84, None), vos(3, 60, None)],
+        flash_latency: &FLASH_F401,
+        run_ua_per_mhz: 128,
+        gpio_max: mhz(100),
+    },
+    Chip {
+        name: "f411",
+        aliases: &[],
+        vco_input: range(limits::F411.vco_input),
+        vco_output: range(limits::F411.vco_output),
+        pll_out: range(limits::F411.pll_out),
+        pll48_out: range(limits::F411.pll48_out),
+        plli2s_out: range(limits::F411.plli2s_out),
+        pllm: 2..=63,
+        plln: 50..=432,
+        pllp: &[2, 4, 6, 8],
+        pllq: 2..=15,
+        pllr: None,
+        // RM0383 gives PLLI2S its own input divider
+        plli2s: AuxiliaryPll {
+            m: Some(2..=63),
+            n: 50..=432,
+            p: None,
+            q: None,
+            r: Some(2..=7),
+        },
+        pllsai: None,
+        i2s_sources: &[I2sSource::PllI2s, I2sSource::Ckin],
+        i2sdiv: 2..=255,
+        pclk1_max: hz(limits::F411.pclk1_max),
+        pclk2_max: hz(limits::F411.pclk2_max),
+        timpre: true,
+        timers: &TIMERS_F401,
+        voltage_scales: &[vos(1, 100, None), vos(2, 84, None), vos(3, 64, None)],
+        flash_latency: &FLASH_F411,
+        run_ua_per_mhz: 100,
+        gpio_max: mhz(100),
+    },
+    Chip {
+        name: "f407",
+        aliases: &["f405", "f415", "f417"],
+        vco_input: range(limits::F407.vco_input),
+        vco_output: range(limits::F407.vco_output),
+        pll_out: range(limits::F407.pll_out),
+        pll48_out: range(limits::F407.pll48_out),
+        plli2s_out: range(limits::F407.plli2s_out),
+        pllm: 2..=63,
+        plln: 50..=432,
+        pllp: &[2, 4, 6, 8],
+        pllq: 2..=15,
+        pllr: None,
+        plli2s: AuxiliaryPll {
+            m: None,
+            n: 50..=432,
+            p: None,
+            q: None,
+            r: Some(2..=7),
+        },
+        pllsai: None,
+        i2s_sources: &[I2sSource::PllI2s, I2sSource::Ckin],
+        i2sdiv: 2..=255,
+        pclk1_max: hz(limits::F407.pclk1_max),
+        pclk2_max: hz(limits::F407.pclk2_max),
+        timpre: false,
+        timers: &TIMERS_F407,
+        // the VOS bit selects between scale 1 and scale 2, per the PWR_CR documentation
+        voltage_scales: &[vos(1, 168, None), vos(2, 144, None)],
+        flash_latency: &FLASH_F407,
+        run_ua_per_mhz: 238,
+        gpio_max: mhz(100),
+    },
+    Chip {
+        name: "f427",
+        aliases: &["f429", "f437", "f439"],
+        vco_input: range(limits::F427.vco_input),
+        vco_output: range(limits::F427.vco_output),
+        pll_out: range(limits::F427.pll_out),
+        pll48_out: range(limits::F427.pll48_out),
+        plli2s_out: range(limits::F427.plli2s_out),
+        pllm: 2..=63,
+        plln: 50..=432,
+        pllp: &[2, 4, 6, 8],
+        pllq: 2..=15,
+        pllr: None,
+        // PLLI2SQ feeds the SAI block
+        plli2s: AuxiliaryPll {
+            m: None,
+            n: 50..=432,
+            p: None,
+            q: Some(2..=15),
+            r: Some(2..=7),
+        },
+        // PLLSAIR feeds the LCD-TFT controller
+        pllsai: Some(AuxiliaryPll {
+            m: None,
+            n: 50..=432,
+            p: None,
+            q: Some(2..=15),
+            r: Some(2..=7),
+        }),
+        i2s_sources: &[I2sSource::PllI2s, I2sSource::Ckin],
+        i2sdiv: 2..=255,
+        pclk1_max: hz(limits::F427.pclk1_max),
+        pclk2_max: hz(limits::F427.pclk2_max),
+        timpre: true,
+        timers: &TIMERS_F407,
+        voltage_scales: &[
+            vos(1, 168, Some(mhz(180))),
+            vos(2, 144, Some(mhz(168))),
+            vos(3, 120, None),
+        ],
+        flash_latency: &FLASH_F427,
+        run_ua_per_mhz: 260,
+        gpio_max: mhz(100),
+    },
+    Chip {
+        name: "f446",
+        aliases: &[],
+        vco_input: range(limits::F446.vco_input),
+        vco_output: range(limits::F446.vco_output),
+        pll_out: range(limits::F446.pll_out),
+        pll48_out: range(limits::F446.pll48_out),
+        plli2s_out: range(limits::F446.plli2s_out),
+        pllm: 2..=63,
+        plln: 50..=432,
+        pllp: &[2, 4, 6, 8],
+        pllq: 2..=15,
+        pllr: Some(2..=7),
+        plli2s: AuxiliaryPll {
+            m: Some(2..=63),
+            n: 50..=432,
+            p: Some(&[2, 4, 6, 8]),
+            q: Some(2..=15),
+            r: Some(2..=7),
+        },
+        pllsai: Some(AuxiliaryPll {
+            m: Some(2..=63),
+            n: 50..=432,
+            p: Some(&[2, 4, 6, 8]),
+            q: Some(2..=15),
+            r: None,
+        }),
+        // RM0390 lets each of the two I2S clock domains pick from any of these in RCC_DCKCFGR
+        i2s_sources: &[
+            I2sSource::PllI2s,
+            I2sSource::Ckin,
+            I2sSource::PllR,
+            I2sSource::PllSource,
+        ],
+        i2sdiv: 2..=255,
+        pclk1_max: hz(limits::F446.pclk1_max),
+        pclk2_max: hz(limits::F446.pclk2_max),
+        timpre: true,
+        timers: &TIMERS_F407,
+        voltage_scales: &[
+            vos(1, 168, Some(mhz(180))),
+            vos(2, 144, Some(mhz(168))),
+            vos(3, 120, None),
+        ],
+        flash_latency: &FLASH_F446,
+        run_ua_per_mhz: 200,
+        gpio_max: mhz(100),
+    },
+];
+
+/// Looks up a chip by its name or one of its aliases, like "f407" or "stm32f429".
+pub fn by_name(name: &str) -> Option<&'static Chip> {
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_prefix("stm32").unwrap_or(&name);
+
+    CHIPS
+        .iter()
+        .find(|chip| chip.name == name || chip.aliases.contains(&name))
+}
+
+/// Parses a chip name for the `--chip` argument.
+pub fn parse(input: &str) -> Result<&'static Chip, String> {
+    by_name(input).ok_or_else(|| {
+        let names = CHIPS.iter().map(|chip| chip.name).collect::<Vec<_>>();
+        format!(
+            "unknown chip {:?}; choose one of {}",
+            input,
+            names.join(", ")
+        )
+    })
+}
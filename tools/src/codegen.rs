@@ -0,0 +1,353 @@
+//! Turns a solver result into Rust source that can be pasted into a firmware binary.
+
+use std::fmt::Write;
+
+use num_traits::Signed;
+
+use crate::chip::I2sSource;
+use crate::solver::{I2sFrame, Requirements, Solution};
+use crate::{floatify, ppm, Rational};
+
+/// Renders `solution` as constants, compile-time checks, the `cfgr` builder calls and raw register
+/// writes for the PLL dividers, which the HAL picks for itself and would not choose the same way.
+pub fn rust_snippet(
+    requirements: &Requirements,
+    solution: &Solution,
+    command_line: &str,
+) -> String {
+    let mut out = String::new();
+    write_snippet(&mut out, requirements, solution, command_line)
+        .expect("writing to a String cannot fail");
+    out
+}
+
+fn write_snippet(
+    out: &mut String,
+    requirements: &Requirements,
+    solution: &Solution,
+    command_line: &str,
+) -> std::fmt::Result {
+    let chip = requirements.chip;
+    let main = &solution.main;
+    let i2s = &solution.i2s;
+
+    writeln!(out, "// Generated by `{}`", command_line)?;
+    writeln!(
+        out,
+        "// SYSCLK = {}Hz, PLL48CLK = {}Hz ({:+.3}ppm), I2SCLK = {}Hz from {}",
+        floatify(&main.sysclk),
+        floatify(&main.pll48_clk),
+        ppm(&main.usb_error),
+        floatify(&i2s.i2s_clk),
+        i2s.source.name(),
+    )?;
+    writeln!(out, "const HSE_HZ: u64 = {};", integer(&requirements.hse))?;
+    writeln!(out, "const PLLM: u64 = {};", main.m)?;
+    writeln!(out, "const PLLN: u64 = {};", main.n)?;
+    writeln!(out, "const PLLP: u64 = {};", main.p)?;
+    writeln!(out, "const PLLQ: u64 = {};", main.q)?;
+    // the names of the constants holding the M, N and R dividers of the I2S clock's PLL, if any
+    let i2s_pll = match (i2s.source, i2s.dividers) {
+        (I2sSource::PllI2s, Some(dividers)) => {
+            let m = if chip.plli2s_shares_pllm() {
+                "PLLM"
+            } else {
+                writeln!(out, "const PLLI2SM: u64 = {};", dividers.m)?;
+                "PLLI2SM"
+            };
+            writeln!(out, "const PLLI2SN: u64 = {};", dividers.n)?;
+            writeln!(out, "const PLLI2SR: u64 = {};", dividers.r)?;
+            Some((m, "PLLI2SN", "PLLI2SR"))
+        }
+        (I2sSource::PllR, Some(dividers)) => {
+            writeln!(out, "const PLLR: u64 = {};", dividers.r)?;
+            Some(("PLLM", "PLLN", "PLLR"))
+        }
+        _ => None,
+    };
+    writeln!(out)?;
+
+    // the fields of RCC_PLLCFGR and RCC_PLLI2SCFGR that are written, leaving the rest as they are
+    let uses_pllr = i2s.source == I2sSource::PllR;
+    writeln!(
+        out,
+        "const PLLCFGR_MASK: u32 = {};",
+        if uses_pllr {
+            "0x7f43_7fff"
+        } else {
+            "0x0f43_7fff"
+        }
+    )?;
+    writeln!(
+        out,
+        "const PLLCFGR: u32 =\n    \
+         ({}PLLQ << 24 | 1 << 22 /* HSE */ | (PLLP / 2 - 1) << 16 | PLLN << 6 | PLLM) as u32;",
+        if uses_pllr { "PLLR << 28 | " } else { "" }
+    )?;
+    if i2s.source == I2sSource::PllI2s {
+        if chip.plli2s_shares_pllm() {
+            writeln!(out, "const PLLI2SCFGR_MASK: u32 = 0x7000_7fc0;")?;
+            writeln!(
+                out,
+                "const PLLI2SCFGR: u32 = (PLLI2SR << 28 | PLLI2SN << 6) as u32;"
+            )?;
+        } else {
+            writeln!(out, "const PLLI2SCFGR_MASK: u32 = 0x7000_7fff;")?;
+            writeln!(
+                out,
+                "const PLLI2SCFGR: u32 = (PLLI2SR << 28 | PLLI2SN << 6 | PLLI2SM) as u32;"
+            )?;
+        }
+    }
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// (sample rate, I2SDIV, ODD) for each supported sample rate"
+    )?;
+    writeln!(
+        out,
+        "const I2S_DIVIDERS: [(u32, u8, bool); {}] = [",
+        i2s.sample_rates.len()
+    )?;
+    for rate in i2s.sample_rates.iter() {
+        writeln!(
+            out,
+            "    ({}, {}, {}), // actually {}Hz, {:+.3}ppm",
+            integer(&rate.desired),
+            rate.i2sdiv,
+            rate.odd == 1,
+            floatify(&rate.actual),
+            ppm(&rate.error),
+        )?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    let vco_input_min = integer(chip.vco_input.start());
+    let vco_input_max = integer(chip.vco_input.end());
+    let vco_output_min = integer(chip.vco_output.start());
+    let vco_output_max = integer(chip.vco_output.end());
+    writeln!(
+        out,
+        "// VCO input must be between {}Hz and {}Hz",
+        vco_input_min, vco_input_max
+    )?;
+    writeln!(
+        out,
+        "static_assertions::const_assert!(HSE_HZ >= {0} * PLLM && HSE_HZ <= {1} * PLLM);",
+        vco_input_min, vco_input_max
+    )?;
+    if let Some(("PLLI2SM", _, _)) = i2s_pll {
+        writeln!(
+            out,
+            "static_assertions::const_assert!(HSE_HZ >= {0} * PLLI2SM && HSE_HZ <= {1} * PLLI2SM);",
+            vco_input_min, vco_input_max
+        )?;
+    }
+    writeln!(
+        out,
+        "// VCO output must be between {}Hz and {}Hz",
+        vco_output_min, vco_output_max
+    )?;
+    writeln!(
+        out,
+        "static_assertions::const_assert!(\n    \
+             HSE_HZ * PLLN >= {0} * PLLM && HSE_HZ * PLLN <= {1} * PLLM\n\
+         );",
+        vco_output_min, vco_output_max
+    )?;
+    if let Some((m, "PLLI2SN", _)) = i2s_pll {
+        writeln!(
+            out,
+            "static_assertions::const_assert!(\n    \
+                 HSE_HZ * PLLI2SN >= {0} * {2} && HSE_HZ * PLLI2SN <= {1} * {2}\n\
+             );",
+            vco_output_min, vco_output_max, m
+        )?;
+    }
+    if main.sysclk.is_integer() {
+        writeln!(
+            out,
+            "static_assertions::const_assert_eq!(HSE_HZ * PLLN, {} * PLLM * PLLP);",
+            integer(&main.sysclk)
+        )?;
+    }
+    if main.usb_error == Rational::from_integer(0) {
+        writeln!(
+            out,
+            "// USB needs exactly 48MHz\n\
+             static_assertions::const_assert_eq!(HSE_HZ * PLLN, 48_000_000 * PLLM * PLLQ);"
+        )?;
+    } else {
+        let bound = (main.usb_error.abs() * 1_000_000).ceil().to_integer();
+        writeln!(
+            out,
+            "// USB needs 48MHz, and this setting is within {0}ppm of it\n\
+             static_assertions::const_assert!(\n    \
+                 HSE_HZ * PLLN * 1_000_000 >= \
+                 48_000_000 * PLLM * PLLQ * (1_000_000 - {0})\n        \
+                     && HSE_HZ * PLLN * 1_000_000 <= \
+                     48_000_000 * PLLM * PLLQ * (1_000_000 + {0})\n\
+             );",
+            bound
+        )?;
+    }
+    if let (Some((m, n, r)), true) = (i2s_pll, i2s.i2s_clk.is_integer()) {
+        writeln!(
+            out,
+            "static_assertions::const_assert_eq!(HSE_HZ * {}, {} * {} * {});",
+            n,
+            integer(&i2s.i2s_clk),
+            m,
+            r
+        )?;
+    }
+    writeln!(out, "// I2SDIV = 0 and I2SDIV = 1 are forbidden")?;
+    for index in 0..i2s.sample_rates.len() {
+        writeln!(
+            out,
+            "static_assertions::const_assert!(I2S_DIVIDERS[{}].1 >= 2);",
+            index
+        )?;
+    }
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "let clocks = rcc\n    \
+             .cfgr\n    \
+             .use_hse({}.hz())\n    \
+             .sysclk({}.hz())\n    \
+             .require_pll48clk()\n    \
+             .freeze();",
+        integer(&requirements.hse),
+        integer(&main.sysclk.floor()),
+    )?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "// The HAL picks its own PLL dividers, so run from HSE while the main PLL is given\n\
+         // the ones checked above.  `clocks` keeps describing the SYSCLK the HAL aimed for,\n\
+         // {}Hz, with the bus prescalers and flash wait states it chose for it.\n\
+         unsafe {{\n    \
+             let rcc = &*stm32f4xx_hal::stm32::RCC::ptr();\n    \
+             rcc.cfgr.modify(|_r, w| w.sw().hse());\n    \
+             while !rcc.cfgr.read().sws().is_hse() {{}}\n    \
+             rcc.cr.modify(|_r, w| w.pllon().clear_bit());\n    \
+             while rcc.cr.read().pllrdy().bit() {{}}\n    \
+             rcc.pllcfgr.modify(|r, w| w.bits(r.bits() & !PLLCFGR_MASK | PLLCFGR));\n    \
+             rcc.cr.modify(|_r, w| w.pllon().set_bit());\n    \
+             while !rcc.cr.read().pllrdy().bit() {{}}\n    \
+             rcc.cfgr.modify(|_r, w| w.sw().pll());\n    \
+             while !rcc.cfgr.read().sws().is_pll() {{}}",
+        integer(&main.sysclk.floor()),
+    )?;
+    if i2s.source == I2sSource::PllI2s {
+        writeln!(
+            out,
+            "\n    rcc.plli2scfgr\n        \
+                 .modify(|r, w| w.bits(r.bits() & !PLLI2SCFGR_MASK | PLLI2SCFGR));\n    \
+             rcc.cr.modify(|_r, w| w.plli2son().set_bit());\n    \
+             while !rcc.cr.read().plli2srdy().bit() {{}}"
+        )?;
+    }
+    if chip.i2s_source_in_dckcfgr() {
+        let selection = match i2s.source {
+            I2sSource::PllI2s => 0,
+            I2sSource::Ckin => 1,
+            I2sSource::PllR => 2,
+            I2sSource::PllSource => 3,
+        };
+        writeln!(
+            out,
+            "\n    // select {} as the kernel clock of both I2S clock domains\n    \
+                 rcc.dckcfgr.modify(|_r, w| {{\n        \
+                     w.i2s1src().bits({});\n        \
+                     w.i2s2src().bits({})\n    \
+                 }});",
+            i2s.source.name(),
+            selection,
+            selection
+        )?;
+    } else if i2s.source == I2sSource::Ckin {
+        writeln!(
+            out,
+            "\n    // select I2S_CKIN as the I2S kernel clock\n    \
+                 rcc.cfgr.modify(|_r, w| w.i2ssrc().set_bit());"
+        )?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    let mckoe = requirements.i2s_frame == I2sFrame::MasterClock;
+    writeln!(
+        out,
+        "let (_sample_rate, i2sdiv, odd) = I2S_DIVIDERS[0];\n\
+         spi.i2spr.write(|w| {{\n    \
+             w.mckoe().bit({});\n    \
+             unsafe {{ w.i2sdiv().bits(i2sdiv) }};\n    \
+             w.odd().bit(odd)\n\
+         }});",
+        mckoe
+    )
+}
+
+/// Formats a frequency as an integer literal with `_` digit separators, rounding if needed.
+fn integer(input: &Rational) -> String {
+    let digits = input.round().to_integer().to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push('_');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip;
+    use crate::solver;
+
+    #[test]
+    fn snippet_for_eight_mhz() {
+        let chip = chip::by_name("f407").unwrap();
+        let requirements = Requirements {
+            chip,
+            hse: Rational::from_integer(8_000_000),
+            sysclk: Rational::from_integer(24_000_000)..=*chip.pll_out.end(),
+            usb_tolerance: Rational::from_integer(0),
+            sample_rates: vec![Rational::from_integer(48_000)],
+            i2s_frame: I2sFrame::MasterClock,
+            i2s_ckin: None,
+        };
+        let solution = &solver::solve(&requirements, 1)[0];
+        let snippet = rust_snippet(&requirements, solution, "pll_configurations");
+
+        let lines = snippet.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "// Generated by `pll_configurations`");
+        assert!(lines.contains(&"const HSE_HZ: u64 = 8_000_000;"));
+        // the F407's PLLI2S shares PLLM, so it has no PLLI2SM field to write
+        assert!(!snippet.contains("PLLI2SM"));
+        assert!(lines.contains(&"const PLLCFGR_MASK: u32 = 0x0f43_7fff;"));
+        assert!(lines.contains(&"const PLLI2SCFGR_MASK: u32 = 0x7000_7fc0;"));
+        assert!(snippet.contains(
+            "static_assertions::const_assert_eq!(HSE_HZ * PLLN, 48_000_000 * PLLM * PLLQ);"
+        ));
+        assert!(snippet.contains("rcc.cr.modify(|_r, w| w.plli2son().set_bit());"));
+        // the F407 selects PLLI2S without RCC_DCKCFGR
+        assert!(!snippet.contains("dckcfgr"));
+    }
+
+    #[test]
+    fn integers_have_separators() {
+        assert_eq!(integer(&Rational::from_integer(168_000_000)), "168_000_000");
+        assert_eq!(integer(&Rational::from_integer(48_000)), "48_000");
+        assert_eq!(integer(&Rational::from_integer(500)), "500");
+        assert_eq!(integer(&Rational::new(1_000_001, 2)), "500_001");
+    }
+}
@@ -0,0 +1,108 @@
+//! Ranks off-the-shelf crystal frequencies by how exactly they can produce the system, USB and
+//! audio clocks.
+
+use num_traits::Signed;
+
+use crate::chip::Chip;
+use crate::solver::{self, I2sClock, I2sFrame, MainPll, Requirements};
+use crate::Rational;
+
+/// Common crystal frequencies between 4MHz and 26MHz, the range HSE accepts, in Hz.
+pub const STANDARD_CRYSTALS: &[i64] = &[
+    4_000_000, 4_096_000, 6_000_000, 6_144_000, 8_000_000, 10_000_000, 11_059_200, 11_289_600,
+    12_000_000, 12_288_000, 14_318_180, 14_745_600, 16_000_000, 16_384_000, 16_934_400, 18_432_000,
+    20_000_000, 22_579_200, 24_000_000, 24_576_000, 25_000_000, 26_000_000,
+];
+
+pub struct Targets {
+    pub sysclk: Rational,
+    /// Groups of sample rates that should each be reachable from a single I2S clock
+    pub families: Vec<Vec<Rational>>,
+    pub i2s_frame: I2sFrame,
+}
+
+pub struct Rating {
+    pub hse: Rational,
+    /// The main PLL that comes closest to the system clock while keeping USB within spec, if it can
+    pub main: MainPll,
+    /// Relative deviation of the system clock from the target
+    pub sysclk_error: Rational,
+    /// The magnitude of the worst error within each family, each with its own I2S clock
+    pub families: Vec<Option<Rational>>,
+    /// The magnitude of the worst error of any family, all from one I2S clock without reprogramming
+    /// PLLI2S
+    pub combined: Option<Rational>,
+}
+
+impl Rating {
+    /// The magnitude of the largest error of any clock, with PLLI2S reprogrammed to switch between
+    /// families.  Crystals that cannot reach some sample rate at all are ranked last.
+    pub fn worst_error(&self) -> Option<Rational> {
+        let mut worst = std::cmp::max(self.sysclk_error.abs(), self.main.usb_error.abs());
+        for &family in self.families.iter() {
+            worst = std::cmp::max(worst, family?);
+        }
+        Some(worst)
+    }
+}
+
+/// Rates `hse` against `targets`, or returns `None` if no main PLL setting works at all.
+pub fn rate(chip: &'static Chip, hse: Rational, targets: &Targets) -> Option<Rating> {
+    let mut requirements = Requirements {
+        chip,
+        hse,
+        sysclk: chip.pll_out.clone(),
+        // any PLL48CLK the datasheet allows, so that the error can be reported
+        usb_tolerance: Rational::from_integer(1),
+        sample_rates: Vec::new(),
+        i2s_frame: targets.i2s_frame,
+        i2s_ckin: None,
+    };
+
+    // USB full speed needs its 48MHz clock within 0.25%
+    let usb_tolerance = Rational::new(25, 10_000);
+    let main = chip
+        .pllm
+        .clone()
+        .filter(|&m| chip.vco_input.contains(&(hse / m)))
+        .flat_map(|m| solver::main_plls(&requirements, m, hse / m))
+        .min_by_key(|main| {
+            let usb_error = main.usb_error.abs();
+            (
+                usb_error > usb_tolerance,
+                (main.sysclk / targets.sysclk - 1).abs(),
+                usb_error,
+            )
+        })?;
+
+    // PLLI2S has to share PLLM with the main PLL chosen above on parts without PLLI2SM
+    let i2s_inputs = match chip.plli2s.m.clone() {
+        Some(range) => range
+            .filter(|&m| chip.vco_input.contains(&(hse / m)))
+            .collect::<Vec<_>>(),
+        None => vec![main.m],
+    };
+    let mut best_i2s = |sample_rates: Vec<Rational>| {
+        requirements.sample_rates = sample_rates;
+        i2s_inputs
+            .iter()
+            .flat_map(|&m| solver::i2s_plls(&requirements, m, hse / m))
+            .map(|i2s: I2sClock| i2s.worst_error())
+            .min()
+    };
+
+    let families = targets
+        .families
+        .iter()
+        .map(|family| best_i2s(family.clone()))
+        .collect();
+    let combined = best_i2s(targets.families.concat());
+
+    Some(Rating {
+        hse,
+        sysclk_error: main.sysclk / targets.sysclk - 1,
+        main,
+        families,
+        combined,
+    })
+}
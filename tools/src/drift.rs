@@ -0,0 +1,118 @@
+//! Worst-case propagation of the reference oscillator's tolerance through the clock tree.  Every
+//! PLL and divider output is an exact ratio of the PLL input, so each one drifts by the same
+//! relative amount as the crystal, on top of whatever error the dividers already have.
+
+use num_traits::Signed;
+
+use crate::check::Configuration;
+use crate::chip::Chip;
+use crate::mco::HSI;
+use crate::Rational;
+
+/// The usual audio sample rates of the 44.1kHz and 48kHz families.
+pub const STANDARD_SAMPLE_RATES: &[i64] = &[
+    8_000, 11_025, 16_000, 22_050, 32_000, 44_100, 48_000, 88_200, 96_000, 176_400, 192_000,
+];
+
+/// The standard sample rate that `rate` is most likely meant to be: the one it is the fewest ppm
+/// away from.
+pub fn nearest_standard_rate(rate: Rational) -> Rational {
+    STANDARD_SAMPLE_RATES
+        .iter()
+        .map(|&standard| Rational::from_integer(standard))
+        .min_by_key(|&standard| (rate / standard - 1).abs())
+        .expect("there are standard rates")
+}
+
+/// A clock to follow through the drift, and what it is supposed to be.
+pub struct Budget {
+    pub name: &'static str,
+    pub nominal: Rational,
+    pub target: Rational,
+    /// The largest relative deviation from `target` that is still in spec, if there is a spec
+    pub allowed: Option<Rational>,
+}
+
+pub struct ClockDrift {
+    pub name: &'static str,
+    pub nominal: Rational,
+    /// The clock with the reference at the slow end of its tolerance
+    pub slowest: Rational,
+    /// The clock with the reference at the fast end of its tolerance
+    pub fastest: Rational,
+    /// The magnitude of the largest relative deviation from the target over the whole tolerance
+    pub worst_error: Rational,
+    pub allowed: Option<Rational>,
+}
+
+impl ClockDrift {
+    pub fn in_spec(&self) -> bool {
+        self.allowed
+            .map_or(true, |allowed| self.worst_error <= allowed)
+    }
+}
+
+/// Spreads each clock in `budgets` over a reference that may be off by up to `tolerance`.
+pub fn propagate(budgets: &[Budget], tolerance: Rational) -> Vec<ClockDrift> {
+    let one = Rational::from_integer(1);
+    budgets
+        .iter()
+        .map(|budget| {
+            let slowest = budget.nominal * (one - tolerance);
+            let fastest = budget.nominal * (one + tolerance);
+            let worst_error = std::cmp::max(
+                (slowest / budget.target - 1).abs(),
+                (fastest / budget.target - 1).abs(),
+            );
+            ClockDrift {
+                name: budget.name,
+                nominal: budget.nominal,
+                slowest,
+                fastest,
+                worst_error,
+                allowed: budget.allowed,
+            }
+        })
+        .collect()
+}
+
+/// How far the dividers alone may be off so that a reference within `reference` still keeps the
+/// clock within `total`, or `None` if the reference alone already uses up the budget.
+pub fn divider_budget(total: Rational, reference: Rational) -> Option<Rational> {
+    let one = Rational::from_integer(1);
+    let remaining = (one + total) / (one + reference) - 1;
+    if remaining < Rational::from_integer(0) {
+        None
+    } else {
+        Some(remaining)
+    }
+}
+
+/// The same configuration with the PLLs fed from HSI instead of `hse`, as after a clock security
+/// system failover that restarts the PLLs.  PLLM (and PLLI2SM, on parts that have it) are scaled to
+/// keep the VCO input as close to the same as the chip allows, and the rest is left alone.
+pub fn hsi_fallback(chip: &Chip, hse: Rational, configuration: &Configuration) -> Configuration {
+    let scale = |m: i64| {
+        let scaled = (Rational::from_integer(m * HSI) / hse).round().to_integer();
+        scaled.clamp(*chip.pllm.start(), *chip.pllm.end())
+    };
+
+    let m = scale(configuration.m);
+    let plli2s = configuration.plli2s.map(|mut plli2s| {
+        plli2s.m = if chip.plli2s_shares_pllm() {
+            m
+        } else {
+            scale(plli2s.m)
+        };
+        plli2s
+    });
+    Configuration {
+        m,
+        n: configuration.n,
+        p: configuration.p,
+        q: configuration.q,
+        plli2s,
+        i2s_prescaler: configuration.i2s_prescaler,
+        frame: configuration.frame,
+    }
+}
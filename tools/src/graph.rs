@@ -0,0 +1,212 @@
+//! Draws a clock setup as a Graphviz DOT graph of the RCC tree, so it can be reviewed without
+//! redrawing it by hand.  Render it with e.g. `dot -Tsvg`.
+
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use crate::chip::{Chip, I2sSource};
+use crate::solver::{I2sClock, I2sFrame, MainPll};
+use crate::{floatify, Rational};
+
+pub struct Tree<'a> {
+    pub chip: &'a Chip,
+    pub hse: Rational,
+    pub main: &'a MainPll,
+    pub i2s: Option<&'a I2sClock>,
+    pub i2s_frame: I2sFrame,
+    /// The I2S channel length, which sets the bit clock when MCLK is enabled
+    pub channel_bits: i64,
+    /// The allowed system clock range, which may be narrower than the PLL's
+    pub sysclk: RangeInclusive<Rational>,
+}
+
+struct Node {
+    id: String,
+    name: String,
+    frequency: Rational,
+    limits: Option<RangeInclusive<Rational>>,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    /// (from, to, label)
+    edges: Vec<(String, String, String)>,
+}
+
+impl Graph {
+    fn node(
+        &mut self,
+        id: &str,
+        name: &str,
+        frequency: Rational,
+        limits: Option<RangeInclusive<Rational>>,
+    ) {
+        self.nodes.push(Node {
+            id: id.to_string(),
+            name: name.to_string(),
+            frequency,
+            limits,
+        });
+    }
+
+    fn edge(&mut self, from: &str, to: &str, label: String) {
+        self.edges.push((from.to_string(), to.to_string(), label));
+    }
+
+    fn render(&self) -> Result<String, std::fmt::Error> {
+        let mut out = String::new();
+        writeln!(out, "digraph clocks {{")?;
+        writeln!(out, "    rankdir=LR;")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+        for node in self.nodes.iter() {
+            let out_of_range = node
+                .limits
+                .as_ref()
+                .map_or(false, |limits| !limits.contains(&node.frequency));
+            write!(
+                out,
+                "    {} [label=\"{}\\n{}\"",
+                node.id,
+                node.name,
+                frequency(&node.frequency)
+            )?;
+            if out_of_range {
+                write!(out, ", color=red, fontcolor=red")?;
+            }
+            writeln!(out, "];")?;
+        }
+        for (from, to, label) in self.edges.iter() {
+            writeln!(out, "    {} -> {} [label=\"{}\"];", from, to, label)?;
+        }
+        writeln!(out, "}}")?;
+        Ok(out)
+    }
+}
+
+/// Renders `tree` as DOT, with every node that is out of its datasheet range in red.
+pub fn dot(tree: &Tree) -> String {
+    let chip = tree.chip;
+    let main = tree.main;
+    let mut graph = Graph::default();
+
+    graph.node("hse", "HSE", tree.hse, None);
+
+    let vco_input = tree.hse / main.m;
+    let vco_output = vco_input * main.n;
+    graph.node(
+        "vco_in",
+        "VCO input",
+        vco_input,
+        Some(chip.vco_input.clone()),
+    );
+    graph.node("vco", "VCO", vco_output, Some(chip.vco_output.clone()));
+    graph.node("sysclk", "SYSCLK", main.sysclk, Some(tree.sysclk.clone()));
+    graph.node(
+        "pll48clk",
+        "PLL48CLK",
+        main.pll48_clk,
+        Some(chip.pll48_out.clone()),
+    );
+    graph.edge("hse", "vco_in", format!("/M = {}", main.m));
+    graph.edge("vco_in", "vco", format!("×N = {}", main.n));
+    graph.edge("vco", "sysclk", format!("/P = {}", main.p));
+    graph.edge("vco", "pll48clk", format!("/Q = {}", main.q));
+
+    let i2s = match tree.i2s {
+        Some(i2s) => i2s,
+        None => return graph.render().expect("writing to a String cannot fail"),
+    };
+
+    match (i2s.source, i2s.dividers) {
+        (I2sSource::PllI2s, Some(dividers)) => {
+            let input = if chip.plli2s_shares_pllm() {
+                "vco_in"
+            } else {
+                let i2s_vco_input = tree.hse / dividers.m;
+                graph.node(
+                    "plli2s_vco_in",
+                    "PLLI2S VCO input",
+                    i2s_vco_input,
+                    Some(chip.vco_input.clone()),
+                );
+                graph.edge("hse", "plli2s_vco_in", format!("/PLLI2SM = {}", dividers.m));
+                "plli2s_vco_in"
+            };
+            graph.node(
+                "plli2s_vco",
+                "PLLI2S VCO",
+                tree.hse / dividers.m * dividers.n,
+                Some(chip.vco_output.clone()),
+            );
+            graph.node(
+                "i2sclk",
+                "I2SCLK",
+                i2s.i2s_clk,
+                Some(chip.plli2s_out.clone()),
+            );
+            graph.edge(input, "plli2s_vco", format!("×PLLI2SN = {}", dividers.n));
+            graph.edge("plli2s_vco", "i2sclk", format!("/PLLI2SR = {}", dividers.r));
+        }
+        (I2sSource::PllR, Some(dividers)) => {
+            graph.node("i2sclk", "I2SCLK", i2s.i2s_clk, Some(chip.pll_out.clone()));
+            graph.edge("vco", "i2sclk", format!("/R = {}", dividers.r));
+        }
+        (I2sSource::Ckin, _) => {
+            graph.node("i2s_ckin", "I2S_CKIN", i2s.i2s_clk, None);
+            graph.node("i2sclk", "I2SCLK", i2s.i2s_clk, None);
+            graph.edge("i2s_ckin", "i2sclk", String::new());
+        }
+        _ => {
+            graph.node("i2sclk", "I2SCLK", i2s.i2s_clk, None);
+            graph.edge("hse", "i2sclk", String::new());
+        }
+    }
+
+    let channel_bits = match tree.i2s_frame {
+        I2sFrame::MasterClock => tree.channel_bits,
+        I2sFrame::Channel16 => 16,
+        I2sFrame::Channel32 => 32,
+    };
+    let bits_per_frame = 2 * channel_bits;
+    for (index, rate) in i2s.sample_rates.iter().enumerate() {
+        let divider = 2 * rate.i2sdiv + rate.odd;
+        let divider_label = format!(
+            "/(2 × I2SDIV + ODD) = /(2 × {} + {})",
+            rate.i2sdiv, rate.odd
+        );
+        let sck = format!("sck{}", index);
+        let ws = format!("ws{}", index);
+        let sck_clk = rate.actual * bits_per_frame;
+
+        if tree.i2s_frame == I2sFrame::MasterClock {
+            let mclk = format!("mclk{}", index);
+            graph.node(&mclk, "MCLK", i2s.i2s_clk / divider, None);
+            graph.edge("i2sclk", &mclk, divider_label);
+            graph.node(&sck, "SCK", sck_clk, None);
+            graph.edge(&mclk, &sck, format!("/{}", 256 / bits_per_frame));
+        } else {
+            graph.node(&sck, "SCK", sck_clk, None);
+            graph.edge("i2sclk", &sck, divider_label);
+        }
+        graph.node(&ws, "WS", rate.actual, None);
+        graph.edge(&sck, &ws, format!("/{}", bits_per_frame));
+    }
+
+    graph.render().expect("writing to a String cannot fail")
+}
+
+/// Formats a frequency with the largest unit that keeps it at least 1, like "12.288 MHz".
+fn frequency(input: &Rational) -> String {
+    let hz = floatify(input);
+    let (value, unit) = if hz >= 1_000_000.0 {
+        (hz / 1_000_000.0, "MHz")
+    } else if hz >= 1_000.0 {
+        (hz / 1_000.0, "kHz")
+    } else {
+        (hz, "Hz")
+    };
+    let digits = format!("{:.6}", value);
+    let digits = digits.trim_end_matches('0').trim_end_matches('.');
+    format!("{} {}", digits, unit)
+}
@@ -0,0 +1,26 @@
+pub mod accuracy;
+pub mod bus;
+pub mod check;
+pub mod chip;
+pub mod codegen;
+pub mod crystal;
+pub mod drift;
+pub mod graph;
+pub mod mclk;
+pub mod mco;
+pub mod output;
+pub mod parse;
+pub mod power;
+pub mod solver;
+pub mod upload;
+
+pub type Rational = num_rational::Rational64;
+
+pub fn floatify(input: &Rational) -> f64 {
+    *input.numer() as f64 / *input.denom() as f64
+}
+
+/// Converts a relative error, like `actual / desired - 1`, to parts-per-million.
+pub fn ppm(error: &Rational) -> f64 {
+    floatify(error) * 1_000_000.0
+}
@@ -0,0 +1,427 @@
+//! Search for main PLL and PLLI2S settings that produce a single I2S master clock, trading off the
+//! MCLK error against the system clock and the USB clock.
+
+use std::ops::RangeInclusive;
+
+use itertools::Itertools;
+use num_traits::Signed;
+
+use crate::chip::Chip;
+use crate::solver::{I2sDividers, MainPll};
+use crate::Rational;
+
+pub struct MclkSearch {
+    pub chip: &'static Chip,
+    pub hse: Rational,
+    pub mclk: Rational,
+    /// Allowed relative deviation of the master clock from `mclk`
+    pub tolerance: Rational,
+    pub m: RangeInclusive<i64>,
+    pub n: RangeInclusive<i64>,
+    pub p: Vec<i64>,
+    pub q: RangeInclusive<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MclkSetting {
+    pub main: MainPll,
+    pub i2s: I2sDividers,
+    pub i2sdiv: i64,
+    pub odd: i64,
+    pub mclk: Rational,
+    /// Relative deviation of `mclk` from the desired master clock
+    pub error: Rational,
+}
+
+impl MclkSetting {
+    /// The MCLK error, the system clock and the USB error, arranged so that smaller is better.
+    fn objectives(&self) -> Vec<Rational> {
+        vec![
+            self.error.abs(),
+            -self.main.sysclk,
+            self.main.usb_error.abs(),
+        ]
+    }
+}
+
+/// The PLLI2S and I2SDIV/ODD half of a setting.
+#[derive(Clone, Debug)]
+struct I2sSetting {
+    dividers: I2sDividers,
+    i2sdiv: i64,
+    odd: i64,
+    mclk: Rational,
+    error: Rational,
+}
+
+/// Returns every setting that is not beaten on MCLK error, system clock and USB error all at once
+/// by some other setting, best MCLK error first.
+///
+/// Rather than trying every combination of dividers, this computes the N, Q and I2SDIV values that
+/// can possibly land in range from the target frequencies, and relies on the main PLL and the I2S
+/// clock being independent apart from a shared PLLM: the optimal settings for a given PLLM can
+/// only combine optimal main PLLs with the most accurate I2S clocks.
+pub fn pareto(search: &MclkSearch) -> Vec<MclkSetting> {
+    let chip = search.chip;
+
+    // parts with PLLI2SM can pick the best I2S clock regardless of PLLM
+    let independent = chip.plli2s.m.clone().map(|range| {
+        let inputs = range
+            .map(|i2sm| (i2sm, search.hse / i2sm))
+            .filter(|(_, input)| chip.vco_input.contains(input))
+            .collect::<Vec<_>>();
+        most_accurate_i2s(search, &inputs)
+    });
+
+    let mut candidates = Vec::new();
+    for m in search.m.clone() {
+        let vco_input = search.hse / m;
+        if !chip.vco_input.contains(&vco_input) {
+            continue;
+        }
+
+        let i2s = match &independent {
+            Some(i2s) => i2s.clone(),
+            None => most_accurate_i2s(search, &[(m, vco_input)]),
+        };
+        if i2s.is_empty() {
+            continue;
+        }
+
+        let mains = pareto_front(main_plls(search, m, vco_input), |main| {
+            vec![-main.sysclk, main.usb_error.abs()]
+        });
+        for (main, i2s) in mains.iter().cartesian_product(i2s.iter()) {
+            candidates.push(MclkSetting {
+                main: main.clone(),
+                i2s: i2s.dividers,
+                i2sdiv: i2s.i2sdiv,
+                odd: i2s.odd,
+                mclk: i2s.mclk,
+                error: i2s.error,
+            });
+        }
+    }
+
+    let mut result = pareto_front(candidates, MclkSetting::objectives);
+    // the sort is stable, so equally-good settings stay in search order
+    result.sort_by_key(MclkSetting::objectives);
+    result
+}
+
+/// The fastest system clock and most accurate USB clock for each PLLN that fits in the VCO range.
+fn main_plls(search: &MclkSearch, m: i64, vco_input: Rational) -> Vec<MainPll> {
+    let chip = search.chip;
+    let usb_clock = Rational::from_integer(48_000_000);
+    let mut result = Vec::new();
+
+    let n_range = divider_range(
+        *chip.vco_output.start() / vco_input,
+        *chip.vco_output.end() / vco_input,
+        &search.n,
+    );
+    for n in n_range {
+        let vco_output = vco_input * n;
+
+        // the smallest PLLP is always the fastest system clock
+        let p = match search
+            .p
+            .iter()
+            .copied()
+            .filter(|&p| chip.pll_out.contains(&(vco_output / p)))
+            .min()
+        {
+            Some(p) => p,
+            None => continue,
+        };
+
+        // PLL48CLK only gets further from 48MHz as PLLQ moves away from VCO / 48MHz, so the best
+        // PLLQ is at one end of the allowed range
+        let q_range = divider_range(
+            vco_output / *chip.pll48_out.end(),
+            vco_output / *chip.pll48_out.start(),
+            &search.q,
+        );
+        let q = match [*q_range.start(), *q_range.end()]
+            .iter()
+            .copied()
+            .filter(|q| q_range.contains(q))
+            .min_by_key(|&q| (vco_output / q - usb_clock).abs())
+        {
+            Some(q) => q,
+            None => continue,
+        };
+
+        let pll48_clk = vco_output / q;
+        result.push(MainPll {
+            m,
+            n,
+            p,
+            q,
+            sysclk: vco_output / p,
+            pll48_clk,
+            usb_error: pll48_clk / usb_clock - 1,
+        });
+    }
+
+    result
+}
+
+/// Every PLLI2S and I2SDIV/ODD setting that ties for the smallest MCLK error within the tolerance,
+/// given the (PLLI2SM, VCO input) pairs to choose from.
+fn most_accurate_i2s(search: &MclkSearch, inputs: &[(i64, Rational)]) -> Vec<I2sSetting> {
+    let chip = search.chip;
+    let r_range = chip
+        .plli2s
+        .r
+        .clone()
+        .expect("every chip's PLLI2S has an R output for I2S");
+    let divider_limits = 2 * chip.i2sdiv.start()..=2 * chip.i2sdiv.end() + 1;
+    let mut result: Vec<I2sSetting> = Vec::new();
+
+    for &(i2sm, vco_input) in inputs {
+        let n_range = divider_range(
+            *chip.vco_output.start() / vco_input,
+            *chip.vco_output.end() / vco_input,
+            &chip.plli2s.n,
+        );
+        for (n, r) in n_range.cartesian_product(r_range.clone()) {
+            let i2s_clk = vco_input * n / r;
+            if !chip.plli2s_out.contains(&i2s_clk) {
+                continue;
+            }
+
+            // only the dividers on either side of the ideal one can be the closest
+            let ideal = i2s_clk / search.mclk;
+            let dividers = [ideal.floor().to_integer(), ideal.ceil().to_integer()];
+            for divider in dividers.iter().copied().dedup() {
+                let divider = divider.clamp(*divider_limits.start(), *divider_limits.end());
+                let mclk = i2s_clk / divider;
+                let error = mclk / search.mclk - 1;
+                if error.abs() > search.tolerance {
+                    continue;
+                }
+
+                let best = result.first().map(|setting| setting.error.abs());
+                if best.map_or(false, |best| error.abs() > best) {
+                    continue;
+                }
+                if best.map_or(false, |best| error.abs() < best) {
+                    result.clear();
+                }
+                // clamping can turn both candidates into the same divider
+                if result.last().map_or(false, |last| {
+                    last.dividers.m == i2sm
+                        && last.dividers.n == n
+                        && last.dividers.r == r
+                        && 2 * last.i2sdiv + last.odd == divider
+                }) {
+                    continue;
+                }
+                result.push(I2sSetting {
+                    dividers: I2sDividers { m: i2sm, n, r },
+                    i2sdiv: divider / 2,
+                    odd: divider % 2,
+                    mclk,
+                    error,
+                });
+            }
+        }
+    }
+
+    result
+}
+
+/// The integers between `low` and `high`, inclusive, that are also in `allowed`.
+fn divider_range(
+    low: Rational,
+    high: Rational,
+    allowed: &RangeInclusive<i64>,
+) -> RangeInclusive<i64> {
+    let low = std::cmp::max(low.ceil().to_integer(), *allowed.start());
+    let high = std::cmp::min(high.floor().to_integer(), *allowed.end());
+    low..=high
+}
+
+/// Keeps the items whose objectives, all to be minimized, are not dominated by another item's.
+fn pareto_front<T>(items: Vec<T>, objectives: impl Fn(&T) -> Vec<Rational>) -> Vec<T> {
+    let mut points = items.iter().map(&objectives).collect::<Vec<_>>();
+    points.sort();
+    points.dedup();
+
+    let dominates = |a: &Vec<Rational>, b: &Vec<Rational>| {
+        a != b && a.iter().zip(b.iter()).all(|(a, b)| a <= b)
+    };
+    let front = points
+        .iter()
+        .filter(|point| !points.iter().any(|other| dominates(other, point)))
+        .collect::<Vec<_>>();
+
+    items
+        .into_iter()
+        .filter(|item| front.binary_search(&&objectives(item)).is_ok())
+        .collect()
+}
+
+/// The original exhaustive search, which tries every divider and keeps only the first P and Q that
+/// fit for each M and N.  It is only kept as a baseline for the benchmark and the tests.
+pub fn exhaustive(search: &MclkSearch) -> Vec<MclkSetting> {
+    let chip = search.chip;
+    let usb_clock = Rational::from_integer(48_000_000);
+    let i2sr_range = chip
+        .plli2s
+        .r
+        .clone()
+        .expect("every chip's PLLI2S has an R output for I2S");
+    let desired_i2s_mclk = (search.mclk - search.mclk * search.tolerance)
+        ..=(search.mclk + search.mclk * search.tolerance);
+    let mut results = Vec::new();
+
+    for m in search.m.clone() {
+        let vco_input = search.hse / m;
+        if !chip.vco_input.contains(&vco_input) {
+            continue;
+        }
+
+        // the PLLI2S input is either divided by the same PLLM, or has a divider of its own
+        let i2s_vco_inputs = match chip.plli2s.m.clone() {
+            Some(range) => range
+                .map(|i2sm| (i2sm, search.hse / i2sm))
+                .filter(|(_, input)| chip.vco_input.contains(input))
+                .collect::<Vec<_>>(),
+            None => vec![(m, vco_input)],
+        };
+
+        for n in search.n.clone() {
+            let vco_output = vco_input * n;
+            if !chip.vco_output.contains(&vco_output) {
+                continue;
+            }
+
+            for &p in search.p.iter() {
+                let pll_clk = vco_output / p;
+                if !chip.pll_out.contains(&pll_clk) {
+                    continue;
+                }
+
+                for q in search.q.clone().rev() {
+                    let pll48_clk = vco_output / q;
+                    if !chip.pll48_out.contains(&pll48_clk) {
+                        continue;
+                    }
+                    let main = MainPll {
+                        m,
+                        n,
+                        p,
+                        q,
+                        sysclk: pll_clk,
+                        pll48_clk,
+                        usb_error: pll48_clk / usb_clock - 1,
+                    };
+
+                    for &(i2sm, i2s_vco_input) in i2s_vco_inputs.iter() {
+                        for i2sn in chip.plli2s.n.clone() {
+                            let i2s_vco_output = i2s_vco_input * i2sn;
+                            if !chip.vco_output.contains(&i2s_vco_output) {
+                                continue;
+                            }
+
+                            for i2sr in i2sr_range.clone() {
+                                let i2s_clock = i2s_vco_output / i2sr;
+                                if !chip.plli2s_out.contains(&i2s_clock) {
+                                    continue;
+                                }
+
+                                for (i2sdiv, odd) in chip.i2sdiv.clone().cartesian_product(0..=1) {
+                                    let mclk = i2s_clock / (2 * i2sdiv + odd);
+                                    if !desired_i2s_mclk.contains(&mclk) {
+                                        continue;
+                                    }
+
+                                    results.push(MclkSetting {
+                                        main: main.clone(),
+                                        i2s: I2sDividers {
+                                            m: i2sm,
+                                            n: i2sn,
+                                            r: i2sr,
+                                        },
+                                        i2sdiv,
+                                        odd,
+                                        mclk,
+                                        error: mclk / search.mclk - 1,
+                                    });
+                                }
+                            }
+                        }
+                    }
+
+                    // only check the largest value of `q` that fits the criteria, corresponding to
+                    // the slowest PLL48 clock we can make with the chosen M and N settings -- i.e.
+                    // the closest to 48MHz.
+                    break;
+                }
+
+                // only check the smallest value of `p` that fits the criteria, corresponding to the
+                // fastest core clock we can make with the already-chosen M and N settings.
+                break;
+            }
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip;
+
+    /// The main PLL the firmware uses with an 8MHz crystal, leaving every PLLI2S and I2SDIV/ODD
+    /// setting to search, which keeps the exhaustive search quick.
+    fn fixed_main_pll() -> MclkSearch {
+        let chip = chip::by_name("f407").unwrap();
+        MclkSearch {
+            chip,
+            hse: Rational::from_integer(8_000_000),
+            mclk: Rational::from_integer(12_288_000),
+            tolerance: Rational::new(1, 100),
+            m: 8..=8,
+            n: 336..=336,
+            p: chip.pllp.to_vec(),
+            q: chip.pllq.clone(),
+        }
+    }
+
+    /// Whether `a` is at least as good as `b` in MCLK error, system clock and USB error.
+    fn at_least_as_good(a: &MclkSetting, b: &MclkSetting) -> bool {
+        a.error.abs() <= b.error.abs()
+            && a.main.sysclk >= b.main.sysclk
+            && a.main.usb_error.abs() <= b.main.usb_error.abs()
+    }
+
+    #[test]
+    fn pareto_is_never_beaten_by_the_exhaustive_search() {
+        let search = fixed_main_pll();
+        let exhaustive = exhaustive(&search);
+        let pareto = pareto(&search);
+        assert!(!exhaustive.is_empty());
+        for setting in exhaustive.iter() {
+            assert!(
+                pareto.iter().any(|best| at_least_as_good(best, setting)),
+                "the exhaustive search found a better setting: {:?}",
+                setting
+            );
+        }
+    }
+
+    #[test]
+    fn pareto_finds_the_closest_master_clock() {
+        let front = pareto(&fixed_main_pll());
+        // 1MHz * N / (R * (2 * I2SDIV + ODD)) can get no closer to 12.288MHz than 86MHz / 7
+        let best = &front[0];
+        assert_eq!(best.error, Rational::new(-1, 5376));
+        assert_eq!(best.mclk, Rational::new(86_000_000, 7));
+        assert_eq!(best.main.sysclk, Rational::from_integer(168_000_000));
+        assert_eq!(best.main.usb_error, Rational::from_integer(0));
+    }
+}
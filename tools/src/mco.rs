@@ -0,0 +1,194 @@
+//! Search for microcontroller clock output (MCO1/MCO2) settings that produce a reference clock for
+//! external chips, without touching the main PLL.
+
+use num_traits::Signed;
+
+use crate::chip::Chip;
+use crate::solver::I2sDividers;
+use crate::Rational;
+
+/// The internal RC oscillator's nominal frequency.
+pub const HSI: i64 = 16_000_000;
+
+/// The divisions allowed by MCO1PRE and MCO2PRE in RCC_CFGR.
+pub const PRESCALERS: std::ops::RangeInclusive<i64> = 1..=5;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Output {
+    Mco1,
+    Mco2,
+}
+
+impl Output {
+    pub fn name(self) -> &'static str {
+        match self {
+            Output::Mco1 => "MCO1",
+            Output::Mco2 => "MCO2",
+        }
+    }
+
+    pub fn pin(self) -> &'static str {
+        match self {
+            Output::Mco1 => "PA8",
+            Output::Mco2 => "PC9",
+        }
+    }
+
+    /// The sources each output can select, in the order of their RCC_CFGR encodings.  MCO1 can also
+    /// output LSE, which is too slow to be of interest here.
+    pub fn sources(self) -> &'static [Source] {
+        match self {
+            Output::Mco1 => &[Source::Hsi, Source::Hse, Source::Pll],
+            Output::Mco2 => &[Source::Sysclk, Source::PllI2s, Source::Hse, Source::Pll],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Source {
+    Hsi,
+    Hse,
+    /// The main PLL's P output
+    Pll,
+    /// The R output of PLLI2S
+    PllI2s,
+    Sysclk,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Hsi => "HSI",
+            Source::Hse => "HSE",
+            Source::Pll => "PLL",
+            Source::PllI2s => "PLLI2S",
+            Source::Sysclk => "SYSCLK",
+        }
+    }
+}
+
+/// The main PLL that is already running, which the search must not change.
+#[derive(Clone, Copy, Debug)]
+pub struct MainPll {
+    pub m: i64,
+    pub n: i64,
+    pub p: i64,
+}
+
+pub struct McoSearch {
+    pub chip: &'static Chip,
+    pub hse: Rational,
+    /// `None` when SYSCLK runs straight from HSE, so PLLM is still free for PLLI2S
+    pub main: Option<MainPll>,
+    /// PLLI2S dividers that are already in use for I2S, and so cannot be changed
+    pub plli2s: Option<I2sDividers>,
+    pub target: Rational,
+    /// Allowed relative deviation from `target`
+    pub tolerance: Rational,
+}
+
+#[derive(Clone, Debug)]
+pub struct McoSetting {
+    pub output: Output,
+    pub source: Source,
+    /// The PLLI2S dividers to program, when that is the source
+    pub plli2s: Option<I2sDividers>,
+    pub prescaler: i64,
+    pub frequency: Rational,
+    /// Relative deviation of `frequency` from the target
+    pub error: Rational,
+}
+
+/// Returns every MCO setting within the tolerance that the pin can output, most accurate first.
+/// Among equally accurate settings, ones that do not need PLLI2S come first, since they leave it
+/// free for I2S.
+pub fn search(search: &McoSearch) -> Vec<McoSetting> {
+    let chip = search.chip;
+    let sysclk = match search.main {
+        Some(main) => search.hse / main.m * main.n / main.p,
+        None => search.hse,
+    };
+
+    let mut result = Vec::new();
+    for &output in [Output::Mco1, Output::Mco2].iter() {
+        for &source in output.sources() {
+            let inputs = match source {
+                Source::Hsi => vec![(None, Rational::from_integer(HSI))],
+                Source::Hse => vec![(None, search.hse)],
+                Source::Pll if search.main.is_some() => vec![(None, sysclk)],
+                Source::Pll => Vec::new(),
+                Source::Sysclk => vec![(None, sysclk)],
+                Source::PllI2s => plli2s_outputs(search, chip)
+                    .into_iter()
+                    .map(|(dividers, clock)| (Some(dividers), clock))
+                    .collect(),
+            };
+
+            for (plli2s, clock) in inputs {
+                for prescaler in PRESCALERS {
+                    let frequency = clock / prescaler;
+                    if frequency > chip.gpio_max {
+                        continue;
+                    }
+                    let error = frequency / search.target - 1;
+                    if error.abs() > search.tolerance {
+                        continue;
+                    }
+                    result.push(McoSetting {
+                        output,
+                        source,
+                        plli2s,
+                        prescaler,
+                        frequency,
+                        error,
+                    });
+                }
+            }
+        }
+    }
+
+    // the sort is stable, so ties stay in search order
+    result.sort_by_key(|setting| (setting.error.abs(), setting.plli2s.is_some()));
+    result
+}
+
+/// Every PLLI2S output that is allowed with the given main PLL, or just the fixed one if PLLI2S is
+/// already spoken for.
+fn plli2s_outputs(search: &McoSearch, chip: &Chip) -> Vec<(I2sDividers, Rational)> {
+    if let Some(dividers) = search.plli2s {
+        let clock = search.hse / dividers.m * dividers.n / dividers.r;
+        return vec![(dividers, clock)];
+    }
+
+    let m_range = match (&chip.plli2s.m, search.main) {
+        (Some(range), _) => range.clone(),
+        (None, Some(main)) => main.m..=main.m,
+        (None, None) => chip.pllm.clone(),
+    };
+    let r_range = chip
+        .plli2s
+        .r
+        .clone()
+        .expect("every chip's PLLI2S has an R output for I2S");
+
+    let mut result = Vec::new();
+    for m in m_range {
+        let vco_input = search.hse / m;
+        if !chip.vco_input.contains(&vco_input) {
+            continue;
+        }
+        for n in chip.plli2s.n.clone() {
+            let vco_output = vco_input * n;
+            if !chip.vco_output.contains(&vco_output) {
+                continue;
+            }
+            for r in r_range.clone() {
+                let clock = vco_output / r;
+                if chip.plli2s_out.contains(&clock) {
+                    result.push((I2sDividers { m, n, r }, clock));
+                }
+            }
+        }
+    }
+    result
+}
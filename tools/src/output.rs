@@ -0,0 +1,227 @@
+//! Renders tables of results as CSV, JSON, Markdown, or a colorized table for the terminal.
+
+use ansi_term::{Colour, Style};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Markdown,
+    Table,
+}
+
+/// Every format, as accepted by `Format::from_str`, for use with structopt's `possible_values`.
+pub const FORMATS: &[&str] = &["csv", "json", "markdown", "table"];
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "markdown" | "md" => Ok(Format::Markdown),
+            "table" => Ok(Format::Table),
+            _ => Err(format!("unknown output format {:?}", input)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Cell {
+    Integer(i64),
+    /// A frequency or other measurement
+    Number(f64),
+    /// An error relative to some desired value, in parts-per-million
+    Ppm(f64),
+    Text(String),
+    Blank,
+}
+
+impl Cell {
+    fn plain(&self) -> String {
+        match self {
+            Cell::Integer(value) => value.to_string(),
+            Cell::Number(value) => value.to_string(),
+            Cell::Ppm(value) => format!("{:.3}", value),
+            Cell::Text(value) => value.clone(),
+            Cell::Blank => String::new(),
+        }
+    }
+
+    fn json(&self) -> serde_json::Value {
+        match self {
+            Cell::Integer(value) => (*value).into(),
+            Cell::Number(value) | Cell::Ppm(value) => serde_json::Number::from_f64(*value)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            Cell::Text(value) => value.clone().into(),
+            Cell::Blank => serde_json::Value::Null,
+        }
+    }
+
+    /// Errors are green when they are negligible for audio, yellow when they are audible to a
+    /// careful listener, and red beyond that.
+    fn style(&self) -> Style {
+        match self {
+            Cell::Ppm(value) if value.abs() <= 100.0 => Colour::Green.normal(),
+            Cell::Ppm(value) if value.abs() <= 1_000.0 => Colour::Yellow.normal(),
+            Cell::Ppm(_) => Colour::Red.normal(),
+            _ => Style::new(),
+        }
+    }
+
+    fn right_aligned(&self) -> bool {
+        !matches!(self, Cell::Text(_))
+    }
+}
+
+pub struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new<S: ToString>(columns: &[S]) -> Self {
+        Self {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Csv => self.csv(),
+            Format::Json => self.json(),
+            Format::Markdown => self.markdown(),
+            Format::Table => self.terminal(),
+        }
+    }
+
+    fn csv(&self) -> String {
+        let mut out = self.columns.join(",");
+        out.push('\n');
+        for row in self.rows.iter() {
+            let cells = row.iter().map(Cell::plain).collect::<Vec<_>>();
+            out.push_str(&cells.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    fn json(&self) -> String {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| {
+                self.columns
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().map(Cell::json))
+                    .collect::<serde_json::Map<_, _>>()
+            })
+            .map(serde_json::Value::Object)
+            .collect::<Vec<_>>();
+        let mut out = serde_json::to_string_pretty(&rows).expect("tables are always valid JSON");
+        out.push('\n');
+        out
+    }
+
+    fn markdown(&self) -> String {
+        let widths = self.widths();
+        let mut out = String::new();
+
+        let header = self
+            .columns
+            .iter()
+            .zip(widths.iter())
+            .map(|(column, &width)| format!("{:<1$}", column, width))
+            .collect::<Vec<_>>();
+        out.push_str(&format!("| {} |\n", header.join(" | ")));
+
+        let separator = widths
+            .iter()
+            .enumerate()
+            .map(|(i, &width)| {
+                let right = self
+                    .rows
+                    .first()
+                    .map_or(false, |row| row[i].right_aligned());
+                if right {
+                    format!("{}:", "-".repeat(width - 1))
+                } else {
+                    "-".repeat(width)
+                }
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&format!("| {} |\n", separator.join(" | ")));
+
+        for row in self.rows.iter() {
+            let cells = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, &width)| pad(&cell.plain(), width, cell.right_aligned()))
+                .collect::<Vec<_>>();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+
+        out
+    }
+
+    fn terminal(&self) -> String {
+        let widths = self.widths();
+        let mut out = String::new();
+
+        let header = self
+            .columns
+            .iter()
+            .zip(widths.iter())
+            .map(|(column, &width)| {
+                Style::new()
+                    .bold()
+                    .paint(pad(column, width, true))
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&format!("{}\n", header.join("  ")));
+
+        for row in self.rows.iter() {
+            let cells = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, &width)| {
+                    let text = pad(&cell.plain(), width, cell.right_aligned());
+                    cell.style().paint(text).to_string()
+                })
+                .collect::<Vec<_>>();
+            out.push_str(&format!("{}\n", cells.join("  ")));
+        }
+
+        out
+    }
+
+    /// The width of each column, wide enough for the header and every cell.
+    fn widths(&self) -> Vec<usize> {
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                self.rows
+                    .iter()
+                    .map(|row| row[i].plain().len())
+                    .chain(std::iter::once(column.len()))
+                    .max()
+                    .unwrap_or(0)
+                    .max(3)
+            })
+            .collect()
+    }
+}
+
+fn pad(text: &str, width: usize, right_aligned: bool) -> String {
+    if right_aligned {
+        format!("{:>1$}", text, width)
+    } else {
+        format!("{:<1$}", text, width)
+    }
+}
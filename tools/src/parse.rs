@@ -0,0 +1,120 @@
+//! Command-line argument parsers, for use with structopt's `parse(try_from_str = ...)`.
+
+use crate::Rational;
+
+/// Parses an exact decimal number, like "11.0592", into a Rational.
+pub fn decimal(input: &str) -> Result<Rational, String> {
+    let (whole, fraction) = match input.find('.') {
+        Some(dot) => (&input[..dot], &input[dot + 1..]),
+        None => (input, ""),
+    };
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(format!("{:?} is not a number", input));
+    }
+    if !whole
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return Err(format!("{:?} is not a number", input));
+    }
+
+    let digits = format!("{}{}", whole, fraction);
+    let numer: i64 = digits
+        .parse()
+        .map_err(|e| format!("{:?} is not a number: {}", input, e))?;
+    let denom = 10i64
+        .checked_pow(fraction.len() as u32)
+        .ok_or_else(|| format!("{:?} has too many decimal places", input))?;
+
+    Ok(Rational::new(numer, denom))
+}
+
+/// Parses a frequency in Hz, optionally with a k/M suffix: "48000", "48k", "12.288MHz".
+pub fn frequency(input: &str) -> Result<Rational, String> {
+    let trimmed = input.trim();
+    let without_hz = trimmed
+        .strip_suffix("Hz")
+        .or_else(|| trimmed.strip_suffix("hz"))
+        .unwrap_or(trimmed);
+
+    let (number, multiplier) = if let Some(number) = without_hz.strip_suffix('k') {
+        (number, 1_000)
+    } else if let Some(number) = without_hz.strip_suffix('M') {
+        (number, 1_000_000)
+    } else {
+        (without_hz, 1)
+    };
+
+    let frequency = decimal(number.trim())? * multiplier;
+    if frequency <= Rational::from_integer(0) {
+        return Err(format!("{:?} is not a positive frequency", input));
+    }
+
+    Ok(frequency)
+}
+
+/// Parses a tolerance as either a percentage ("1%") or parts-per-million ("500ppm"), and returns it
+/// as a fraction.
+pub fn tolerance(input: &str) -> Result<Rational, String> {
+    let trimmed = input.trim();
+
+    let tolerance = if let Some(percent) = trimmed.strip_suffix('%') {
+        decimal(percent.trim())? / 100
+    } else if let Some(ppm) = trimmed.strip_suffix("ppm") {
+        decimal(ppm.trim())? / 1_000_000
+    } else {
+        return Err(format!(
+            "{:?} must be given in percent (\"1%\") or ppm (\"500ppm\")",
+            input
+        ));
+    };
+    // a clock can be off by at most all of itself, and anything more would make it negative
+    if tolerance > Rational::from_integer(1) {
+        return Err(format!("{:?} is more than 100%", input));
+    }
+
+    Ok(tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimals_are_exact() {
+        assert_eq!(decimal("11.0592"), Ok(Rational::new(110_592, 10_000)));
+        assert_eq!(decimal("2.7"), Ok(Rational::new(27, 10)));
+        assert_eq!(decimal(".5"), Ok(Rational::new(1, 2)));
+        assert_eq!(decimal("3"), Ok(Rational::from_integer(3)));
+        assert!(decimal(".").is_err());
+        assert!(decimal("-1").is_err());
+        assert!(decimal("1e6").is_err());
+    }
+
+    #[test]
+    fn frequencies_take_suffixes() {
+        assert_eq!(
+            frequency("12.288MHz"),
+            Ok(Rational::from_integer(12_288_000))
+        );
+        assert_eq!(frequency("44.1k"), Ok(Rational::from_integer(44_100)));
+        assert_eq!(frequency("440hz"), Ok(Rational::from_integer(440)));
+        assert_eq!(
+            frequency(" 11059200 "),
+            Ok(Rational::from_integer(11_059_200))
+        );
+        assert!(frequency("0Hz").is_err());
+        assert!(frequency("fast").is_err());
+    }
+
+    #[test]
+    fn tolerances_need_a_unit() {
+        assert_eq!(tolerance("0.25%"), Ok(Rational::new(1, 400)));
+        assert_eq!(tolerance("500ppm"), Ok(Rational::new(1, 2_000)));
+        assert_eq!(tolerance("0ppm"), Ok(Rational::from_integer(0)));
+        assert!(tolerance("5").is_err());
+        assert_eq!(tolerance("100%"), Ok(Rational::from_integer(1)));
+        assert!(tolerance("1000001ppm").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! What a clock configuration costs beyond its frequencies: the flash wait states, the regulator
+//! setting, and a rough estimate of the supply current, so that clock accuracy can be traded
+//! against battery life.
+
+use crate::chip::{Chip, VoltageScale};
+use crate::Rational;
+
+/// Typical HSE oscillator current with a crystal, in uA.  The datasheets give 450uA to 530uA
+/// depending on the frequency.
+const HSE_UA: i64 = 500;
+
+/// Typical current drawn by one running PLL on VDD and VDDA combined, in uA, at the slowest and
+/// fastest VCO outputs the datasheets list.
+const PLL_UA: (i64, i64) = (450, 1000);
+const PLL_VCO_MHZ: (i64, i64) = (100, 432);
+
+#[derive(Clone, Debug)]
+pub struct Power {
+    /// The LATENCY field of FLASH_ACR
+    pub wait_states: usize,
+    /// The lowest-power voltage scale that still allows SYSCLK
+    pub voltage_scale: u8,
+    /// Whether over-drive has to be enabled on top of `voltage_scale`
+    pub overdrive: bool,
+    /// Estimated supply current in mA
+    pub current: Rational,
+}
+
+/// The number of flash wait states `hclk` needs when the supply can drop to `vdd` volts, or an
+/// error if the chip cannot run that fast at that voltage.
+pub fn flash_wait_states(chip: &Chip, vdd: Rational, hclk: Rational) -> Result<usize, String> {
+    let millivolts = (vdd * 1000).floor().to_integer();
+    let latency = chip
+        .flash_latency
+        .iter()
+        .find(|latency| latency.vdd_mv.contains(&(millivolts as u32)))
+        .ok_or_else(|| format!("{} cannot run from {}V", chip.name, crate::floatify(&vdd)))?;
+
+    latency
+        .hclk_max_mhz
+        .iter()
+        .position(|&max| hclk <= Rational::from_integer(max * 1_000_000))
+        .ok_or_else(|| {
+            format!(
+                "{} cannot run HCLK at {}Hz from {}V",
+                chip.name,
+                crate::floatify(&hclk),
+                crate::floatify(&vdd)
+            )
+        })
+}
+
+/// The highest-numbered (lowest-power) voltage scale that allows `sysclk`, preferring to stay out
+/// of over-drive, which costs more than a faster scale.
+pub fn regulator(chip: &Chip, sysclk: Rational) -> Option<(&VoltageScale, bool)> {
+    let without_overdrive = chip
+        .voltage_scales
+        .iter()
+        .rev()
+        .find(|vos| sysclk <= vos.sysclk_max)
+        .map(|vos| (vos, false));
+    let with_overdrive = || {
+        chip.voltage_scales
+            .iter()
+            .rev()
+            .find(|vos| vos.overdrive_max.map_or(false, |max| sysclk <= max))
+            .map(|vos| (vos, true))
+    };
+    without_overdrive.or_else(with_overdrive)
+}
+
+/// A rough run-mode supply current in mA: the core scaled linearly from the chip's typical uA/MHz,
+/// plus the HSE oscillator and each running PLL.  It leaves out peripherals and assumes the
+/// highest voltage scale, so it is only good for comparing configurations with each other.
+pub fn run_current(chip: &Chip, hclk: Rational, vco_outputs: &[Rational]) -> Rational {
+    let core = hclk * chip.run_ua_per_mhz / 1_000_000;
+    let plls = vco_outputs
+        .iter()
+        .map(|&vco| {
+            let (low, high) = PLL_UA;
+            let (slowest, fastest) = PLL_VCO_MHZ;
+            let mhz = vco / 1_000_000;
+            Rational::from_integer(low) + (mhz - slowest) * (high - low) / (fastest - slowest)
+        })
+        .sum::<Rational>();
+
+    (core + plls + HSE_UA) / 1000
+}
+
+/// Everything above for one configuration, where `hclk` is SYSCLK after the AHB prescaler.
+pub fn estimate(
+    chip: &Chip,
+    vdd: Rational,
+    sysclk: Rational,
+    hclk: Rational,
+    vco_outputs: &[Rational],
+) -> Result<Power, String> {
+    let wait_states = flash_wait_states(chip, vdd, hclk)?;
+    let (voltage_scale, overdrive) = regulator(chip, sysclk).ok_or_else(|| {
+        format!(
+            "no voltage scale of {} allows a {}Hz SYSCLK",
+            chip.name,
+            crate::floatify(&sysclk)
+        )
+    })?;
+
+    Ok(Power {
+        wait_states,
+        voltage_scale: voltage_scale.scale,
+        overdrive,
+        current: run_current(chip, hclk, vco_outputs),
+    })
+}
@@ -0,0 +1,462 @@
+//! Search for a single main PLL and PLLI2S setting that satisfies the system clock, the USB clock,
+//! and several I2S sample rates all at once.
+
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+
+use num_traits::Signed;
+
+use crate::chip::{Chip, I2sSource};
+use crate::Rational;
+
+/// How the I2S peripheral divides its kernel clock down to the sample rate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum I2sFrame {
+    /// MCKOE = 1: the master clock is always 256 * Fs, regardless of the channel length.
+    MasterClock,
+    /// MCKOE = 0, with a 16-bit channel length: 32 bit clocks per sample.
+    Channel16,
+    /// MCKOE = 0, with a 32-bit channel length: 64 bit clocks per sample.
+    Channel32,
+}
+
+impl I2sFrame {
+    pub const ALL: [I2sFrame; 3] = [
+        I2sFrame::MasterClock,
+        I2sFrame::Channel16,
+        I2sFrame::Channel32,
+    ];
+
+    /// A short label for tables; 16-bit data in a 32-bit channel counts as "CHLEN32".
+    pub fn name(self) -> &'static str {
+        match self {
+            I2sFrame::MasterClock => "MCKOE",
+            I2sFrame::Channel16 => "CHLEN16",
+            I2sFrame::Channel32 => "CHLEN32",
+        }
+    }
+
+    /// The ratio between the I2S kernel clock and the sample rate, with an I2SDIV/ODD divider of 1.
+    pub fn clocks_per_sample(self) -> i64 {
+        let (mckoe, channel_bits) = match self {
+            I2sFrame::MasterClock => (true, 32),
+            I2sFrame::Channel16 => (false, 16),
+            I2sFrame::Channel32 => (false, 32),
+        };
+        clock_model::i2s::clocks_per_sample(mckoe, channel_bits) as i64
+    }
+}
+
+pub struct Requirements {
+    pub chip: &'static Chip,
+    pub hse: Rational,
+    pub sysclk: RangeInclusive<Rational>,
+    /// Allowed relative deviation of PLL48CLK from exactly 48MHz.
+    pub usb_tolerance: Rational,
+    pub sample_rates: Vec<Rational>,
+    pub i2s_frame: I2sFrame,
+    /// The frequency on the I2S_CKIN pin, if there is an external clock to consider.
+    pub i2s_ckin: Option<Rational>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MainPll {
+    pub m: i64,
+    pub n: i64,
+    pub p: i64,
+    pub q: i64,
+    pub sysclk: Rational,
+    pub pll48_clk: Rational,
+    /// Relative deviation of `pll48_clk` from 48MHz
+    pub usb_error: Rational,
+}
+
+#[derive(Clone, Debug)]
+pub struct SampleRateSetting {
+    pub desired: Rational,
+    pub i2sdiv: i64,
+    pub odd: i64,
+    pub actual: Rational,
+    /// Relative deviation of `actual` from `desired`
+    pub error: Rational,
+}
+
+/// The M, N and R dividers of whichever PLL produces the I2S kernel clock.
+#[derive(Clone, Copy, Debug)]
+pub struct I2sDividers {
+    pub m: i64,
+    pub n: i64,
+    pub r: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct I2sClock {
+    pub source: I2sSource,
+    /// `None` when the kernel clock does not come from a PLL
+    pub dividers: Option<I2sDividers>,
+    pub i2s_clk: Rational,
+    pub sample_rates: Vec<SampleRateSetting>,
+}
+
+impl I2sClock {
+    /// The magnitude of the error of the least accurate sample rate.
+    pub fn worst_error(&self) -> Rational {
+        self.sample_rates
+            .iter()
+            .map(|s| s.error.abs())
+            .max()
+            .unwrap_or_else(|| Rational::from_integer(0))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Solution {
+    pub main: MainPll,
+    pub i2s: I2sClock,
+}
+
+impl Solution {
+    /// The magnitude of the largest error of any constrained clock, USB or I2S.
+    pub fn worst_error(&self) -> Rational {
+        std::cmp::max(self.main.usb_error.abs(), self.i2s.worst_error())
+    }
+}
+
+/// Returns the `limit` best solutions, ordered by their worst-case error and then by the fastest
+/// system clock.
+pub fn solve(requirements: &Requirements, limit: usize) -> Vec<Solution> {
+    let chip = requirements.chip;
+    let mut solutions = Vec::new();
+
+    // I2S clocks that do not depend on the main PLL's settings at all
+    let mut independent = Vec::new();
+    if chip.i2s_sources.contains(&I2sSource::PllI2s) {
+        if let Some(i2sm_range) = chip.plli2s.m.clone() {
+            for i2sm in i2sm_range {
+                let vco_input = requirements.hse / i2sm;
+                if chip.vco_input.contains(&vco_input) {
+                    independent.extend(i2s_plls(requirements, i2sm, vco_input));
+                }
+            }
+        }
+    }
+    if chip.i2s_sources.contains(&I2sSource::PllSource) {
+        independent.extend(i2s_clock(
+            requirements,
+            I2sSource::PllSource,
+            None,
+            requirements.hse,
+        ));
+    }
+    if let Some(ckin) = requirements.i2s_ckin {
+        if chip.i2s_sources.contains(&I2sSource::Ckin) {
+            independent.extend(i2s_clock(requirements, I2sSource::Ckin, None, ckin));
+        }
+    }
+    independent.sort_by_key(|i2s| i2s.worst_error());
+    independent.truncate(limit);
+
+    for m in chip.pllm.clone() {
+        let vco_input = requirements.hse / m;
+        if !chip.vco_input.contains(&vco_input) {
+            continue;
+        }
+
+        let mut main_plls = main_plls(requirements, m, vco_input);
+        let mut m_solutions = Vec::new();
+
+        // the main PLL's own R output is tied to its M and N, so it has to be paired up with every
+        // main PLL setting rather than just the best ones.
+        if chip.i2s_sources.contains(&I2sSource::PllR) {
+            if let Some(pllr) = chip.pllr.clone() {
+                for main in main_plls.iter() {
+                    for r in pllr.clone() {
+                        let i2s_clk = vco_input * main.n / r;
+                        if !chip.pll_out.contains(&i2s_clk) {
+                            continue;
+                        }
+                        let dividers = I2sDividers { m, n: main.n, r };
+                        if let Some(i2s) =
+                            i2s_clock(requirements, I2sSource::PllR, Some(dividers), i2s_clk)
+                        {
+                            m_solutions.push(Solution {
+                                main: main.clone(),
+                                i2s,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut i2s_clocks = independent.clone();
+        if chip.plli2s_shares_pllm() && chip.i2s_sources.contains(&I2sSource::PllI2s) {
+            i2s_clocks.extend(i2s_plls(requirements, m, vco_input));
+        }
+        i2s_clocks.sort_by_key(|i2s| i2s.worst_error());
+        i2s_clocks.truncate(limit);
+
+        // rank each main PLL as it would be paired with the most accurate I2S clock, which no
+        // other pairing of it can beat, so that a faster SYSCLK wins once its USB error no longer
+        // decides the worst error
+        if let Some(best_i2s) = i2s_clocks.first().map(I2sClock::worst_error) {
+            main_plls.sort_by(|a, b| {
+                let worst = |main: &MainPll| std::cmp::max(main.usb_error.abs(), best_i2s);
+                worst(a)
+                    .cmp(&worst(b))
+                    .then_with(|| b.sysclk.cmp(&a.sysclk))
+                    .then_with(|| a.usb_error.abs().cmp(&b.usb_error.abs()))
+            });
+            main_plls.truncate(limit);
+        }
+
+        for main in main_plls.iter() {
+            for i2s in i2s_clocks.iter() {
+                m_solutions.push(Solution {
+                    main: main.clone(),
+                    i2s: i2s.clone(),
+                });
+            }
+        }
+
+        m_solutions.sort_by(compare_solutions);
+        m_solutions.truncate(limit);
+        solutions.extend(m_solutions);
+    }
+
+    solutions.sort_by(compare_solutions);
+    solutions.truncate(limit);
+    solutions
+}
+
+fn compare_solutions(a: &Solution, b: &Solution) -> Ordering {
+    a.worst_error()
+        .cmp(&b.worst_error())
+        .then_with(|| b.main.sysclk.cmp(&a.main.sysclk))
+        .then_with(|| a.main.usb_error.abs().cmp(&b.main.usb_error.abs()))
+}
+
+/// The closest PLL48CLK can get to 48MHz, as a relative error, with a SYSCLK that meets the
+/// requirements and whatever the USB tolerance.  `None` means that no main PLL setting reaches the
+/// SYSCLK range at all.
+pub fn closest_usb_error(requirements: &Requirements) -> Option<Rational> {
+    let chip = requirements.chip;
+    chip.pllm
+        .clone()
+        .map(|m| (m, requirements.hse / m))
+        .filter(|(_, vco_input)| chip.vco_input.contains(vco_input))
+        .flat_map(|(m, vco_input)| {
+            main_plls_within(requirements, m, vco_input, Rational::from_integer(1))
+        })
+        .map(|main| main.usb_error)
+        .min_by_key(|error| error.abs())
+}
+
+pub(crate) fn main_plls(requirements: &Requirements, m: i64, vco_input: Rational) -> Vec<MainPll> {
+    main_plls_within(requirements, m, vco_input, requirements.usb_tolerance)
+}
+
+/// The main PLL settings from `m` with a SYSCLK in range and PLL48CLK within `usb_tolerance`.
+fn main_plls_within(
+    requirements: &Requirements,
+    m: i64,
+    vco_input: Rational,
+    usb_tolerance: Rational,
+) -> Vec<MainPll> {
+    let chip = requirements.chip;
+    let usb_clock = Rational::from_integer(48_000_000);
+    let mut result = Vec::new();
+
+    for n in chip.plln.clone() {
+        let vco_output = vco_input * n;
+        if !chip.vco_output.contains(&vco_output) {
+            continue;
+        }
+
+        for q in chip.pllq.clone() {
+            let pll48_clk = vco_output / q;
+            if !chip.pll48_out.contains(&pll48_clk) {
+                continue;
+            }
+            let usb_error = pll48_clk / usb_clock - 1;
+            if usb_error.abs() > usb_tolerance {
+                continue;
+            }
+
+            for &p in chip.pllp.iter() {
+                let sysclk = vco_output / p;
+                if !chip.pll_out.contains(&sysclk) || !requirements.sysclk.contains(&sysclk) {
+                    continue;
+                }
+
+                result.push(MainPll {
+                    m,
+                    n,
+                    p,
+                    q,
+                    sysclk,
+                    pll48_clk,
+                    usb_error,
+                });
+            }
+        }
+    }
+
+    result
+}
+
+pub(crate) fn i2s_plls(requirements: &Requirements, m: i64, vco_input: Rational) -> Vec<I2sClock> {
+    let chip = requirements.chip;
+    let r_range = match chip.plli2s.r.clone() {
+        Some(r) => r,
+        None => return Vec::new(),
+    };
+    let mut result = Vec::new();
+
+    for n in chip.plli2s.n.clone() {
+        let vco_output = vco_input * n;
+        if !chip.vco_output.contains(&vco_output) {
+            continue;
+        }
+
+        for r in r_range.clone() {
+            let i2s_clk = vco_output / r;
+            if !chip.plli2s_out.contains(&i2s_clk) {
+                continue;
+            }
+
+            let dividers = I2sDividers { m, n, r };
+            result.extend(i2s_clock(
+                requirements,
+                I2sSource::PllI2s,
+                Some(dividers),
+                i2s_clk,
+            ));
+        }
+    }
+
+    result
+}
+
+/// Picks the dividers for every requested sample rate, or returns `None` if `i2s_clk` cannot reach
+/// one of them at all.
+fn i2s_clock(
+    requirements: &Requirements,
+    source: I2sSource,
+    dividers: Option<I2sDividers>,
+    i2s_clk: Rational,
+) -> Option<I2sClock> {
+    let sample_rates = requirements
+        .sample_rates
+        .iter()
+        .map(|&desired| best_divider(requirements.chip, i2s_clk, desired, requirements.i2s_frame))
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(I2sClock {
+        source,
+        dividers,
+        i2s_clk,
+        sample_rates,
+    })
+}
+
+/// Finds the I2SDIV and ODD setting that gets closest to the desired sample rate, if any of them
+/// are in range.
+pub fn best_divider(
+    chip: &Chip,
+    i2s_clk: Rational,
+    desired: Rational,
+    frame: I2sFrame,
+) -> Option<SampleRateSetting> {
+    let min_divider = 2 * chip.i2sdiv.start();
+    let max_divider = 2 * chip.i2sdiv.end() + 1;
+
+    let ideal = i2s_clk / (desired * frame.clocks_per_sample());
+    let candidates = [ideal.floor().to_integer(), ideal.ceil().to_integer()];
+
+    candidates
+        .iter()
+        .filter(|&&divider| (min_divider..=max_divider).contains(&divider))
+        .map(|&divider| {
+            let actual = i2s_clk / (divider * frame.clocks_per_sample());
+            SampleRateSetting {
+                desired,
+                i2sdiv: divider / 2,
+                odd: divider % 2,
+                actual,
+                error: actual / desired - 1,
+            }
+        })
+        .min_by_key(|setting| setting.error.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chip, ppm};
+
+    fn requirements(hse: i64, usb_tolerance: Rational) -> Requirements {
+        let chip = chip::by_name("f407").unwrap();
+        Requirements {
+            chip,
+            hse: Rational::from_integer(hse),
+            sysclk: Rational::from_integer(24_000_000)..=*chip.pll_out.end(),
+            usb_tolerance,
+            sample_rates: [32_000, 44_100, 48_000, 96_000]
+                .iter()
+                .map(|&rate| Rational::from_integer(rate))
+                .collect(),
+            i2s_frame: I2sFrame::MasterClock,
+            i2s_ckin: None,
+        }
+    }
+
+    #[test]
+    fn both_audio_families_from_eight_mhz() {
+        let solutions = solve(&requirements(8_000_000, Rational::from_integer(0)), 1);
+        let best = &solutions[0];
+        assert_eq!(
+            (best.main.m, best.main.n, best.main.p, best.main.q),
+            (5, 210, 2, 7)
+        );
+        assert_eq!(best.main.sysclk, Rational::from_integer(168_000_000));
+        assert_eq!(best.i2s.source, I2sSource::PllI2s);
+        let dividers = best.i2s.dividers.unwrap();
+        assert_eq!((dividers.m, dividers.n, dividers.r), (5, 184, 2));
+        // 44.1kHz is the worst, from 147.2MHz / (256 * 13)
+        assert_eq!(
+            best.worst_error(),
+            Rational::new(147_200_000, 256 * 13 * 44_100) - 1
+        );
+        assert!((ppm(&best.worst_error()) - 2965.289).abs() < 0.001);
+    }
+
+    #[test]
+    fn usb_tolerance_filters_main_plls() {
+        // 11.0592MHz has no exact way to 48MHz
+        let exact = requirements(11_059_200, Rational::from_integer(0));
+        assert!(solve(&exact, 1).is_empty());
+        let closest = closest_usb_error(&exact).unwrap();
+        assert!(closest != Rational::from_integer(0) && closest.abs() < Rational::new(1, 400));
+        let solutions = solve(&requirements(11_059_200, Rational::new(1, 400)), 10);
+        assert!(!solutions.is_empty());
+        assert!(solutions
+            .iter()
+            .all(|solution| solution.main.usb_error.abs() <= Rational::new(1, 400)));
+    }
+
+    #[test]
+    fn best_divider_matches_rm0090() {
+        // PLLI2SN = 258 and PLLI2SR = 3 from 1MHz, RM0090's 48kHz row with MCLK enabled
+        let chip = chip::by_name("f407").unwrap();
+        let setting = best_divider(
+            chip,
+            Rational::from_integer(86_000_000),
+            Rational::from_integer(48_000),
+            I2sFrame::MasterClock,
+        )
+        .unwrap();
+        assert_eq!((setting.i2sdiv, setting.odd), (3, 1));
+        assert_eq!(setting.actual, Rational::new(86_000_000, 256 * 7));
+    }
+}
@@ -0,0 +1,235 @@
+//! The host side of the signal generator's arbitrary waveform upload: reads a WAV or CSV capture,
+//! scales it to 12-bit DAC codes, and frames it the way the firmware's `upload` module expects,
+//! after `k` and `u` commands giving its sample rate and length.
+
+use std::convert::TryInto;
+
+/// The most samples the firmware can hold, its `dac::ARBITRARY_CAPACITY`.
+pub const CAPACITY: usize = 42_000;
+
+/// The slowest and fastest rates the firmware's timer can play samples at.
+pub const MIN_SAMPLE_RATE: u32 = 84_000_000 / 65536 + 1;
+pub const MAX_SAMPLE_RATE: u32 = 10_500_000;
+
+/// The DAC's reference voltage on the STM32F4DISCOVERY board.
+const DAC_VOLTAGE: f64 = 3.0;
+const MIDSCALE: f64 = 2048.0;
+const FULL_SCALE: f64 = 4095.0;
+
+pub struct Capture {
+    pub samples: Vec<f64>,
+    /// The rate the capture was made at, if the file says
+    pub sample_rate: Option<u32>,
+}
+
+/// Reads a WAV file's first channel, which may be 8-, 16-, 24- or 32-bit PCM or 32- or 64-bit
+/// float.
+pub fn read_wav(bytes: &[u8]) -> Result<Capture, String> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+
+    // (format, channels, sample rate, bits per sample)
+    let mut format = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let id = &rest[..4];
+        let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        let body = rest
+            .get(8..8 + size)
+            .ok_or_else(|| format!("the {:?} chunk is cut short", String::from_utf8_lossy(id)))?;
+
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err("the fmt chunk is too short".to_string());
+                }
+                let mut tag = u16_at(body, 0);
+                // WAVE_FORMAT_EXTENSIBLE keeps the real format at the start of its subformat GUID
+                if tag == 0xfffe && body.len() >= 26 {
+                    tag = u16_at(body, 24);
+                }
+                format = Some((
+                    tag,
+                    u16_at(body, 2) as usize,
+                    u32::from_le_bytes(body[4..8].try_into().unwrap()),
+                    u16_at(body, 14) as usize,
+                ));
+            }
+            b"data" => {
+                let (tag, channels, sample_rate, bits) =
+                    format.ok_or("the data chunk comes before the fmt chunk")?;
+                if channels == 0 {
+                    return Err("the WAV file has no channels".to_string());
+                }
+                let width = bits / 8;
+                let decode = decoder(tag, bits)?;
+                let samples = body
+                    .chunks_exact(width * channels)
+                    .map(|frame| decode(&frame[..width]))
+                    .collect();
+                return Ok(Capture {
+                    samples,
+                    sample_rate: Some(sample_rate),
+                });
+            }
+            _ => (),
+        }
+
+        // chunks are padded to an even length
+        let padded = size + size % 2;
+        rest = rest.get(8 + padded..).unwrap_or(&[]);
+    }
+
+    Err("the WAV file has no data chunk".to_string())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// The function that turns one little-endian sample into a number from -1 to 1.
+fn decoder(tag: u16, bits: usize) -> Result<fn(&[u8]) -> f64, String> {
+    const PCM: u16 = 1;
+    const FLOAT: u16 = 3;
+
+    let decode: fn(&[u8]) -> f64 = match (tag, bits) {
+        // 8-bit WAV is the one unsigned format
+        (PCM, 8) => |b| (b[0] as f64 - 128.0) / 128.0,
+        (PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32768.0,
+        (PCM, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 8_388_608.0,
+        (PCM, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2_147_483_648.0,
+        (FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        (FLOAT, 64) => |b| f64::from_le_bytes(b.try_into().unwrap()),
+        _ => {
+            return Err(format!(
+                "WAV format {} with {}-bit samples is not supported",
+                tag, bits
+            ))
+        }
+    };
+    Ok(decode)
+}
+
+/// Reads a CSV file with one sample per line, taking the last column if there are several, like
+/// the time and voltage columns of an oscilloscope export.  A header line and lines starting with
+/// '#' are skipped.
+pub fn read_csv(text: &str) -> Result<Capture, String> {
+    let mut samples = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let field = line.rsplit(',').next().unwrap_or("").trim();
+        match field.parse::<f64>() {
+            Ok(value) if value.is_finite() => samples.push(value),
+            _ if samples.is_empty() => continue,
+            _ => return Err(format!("line {}: {:?} is not a number", index + 1, field)),
+        }
+    }
+
+    Ok(Capture {
+        samples,
+        sample_rate: None,
+    })
+}
+
+/// Scales `samples` so that their peaks span `mvpp` millivolts centered on the middle of the DAC's
+/// range, whatever units they were in.
+pub fn dac_codes(samples: &[f64], mvpp: u32) -> Vec<u16> {
+    let low = samples.iter().cloned().fold(f64::INFINITY, f64::min);
+    let high = samples.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let span = high - low;
+    let middle = (high + low) / 2.0;
+    let codes_pp = mvpp as f64 / 1000.0 / DAC_VOLTAGE * 4096.0;
+
+    samples
+        .iter()
+        .map(|&sample| {
+            let offset = if span > 0.0 {
+                (sample - middle) / span * codes_pp
+            } else {
+                0.0
+            };
+            (MIDSCALE + offset).round().clamp(0.0, FULL_SCALE) as u16
+        })
+        .collect()
+}
+
+/// The Fletcher-16 checksum the firmware checks an upload against.
+pub fn fletcher16(bytes: &[u8]) -> u16 {
+    let (mut sum1, mut sum2) = (0u16, 0u16);
+    for &byte in bytes {
+        sum1 = (sum1 + byte as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+    sum2 << 8 | sum1
+}
+
+/// Everything to send over the serial port to play `codes` at `sample_rate`.
+pub fn frame(codes: &[u16], sample_rate: u32) -> Result<Vec<u8>, String> {
+    if codes.is_empty() {
+        return Err("there are no samples to send".to_string());
+    }
+    if codes.len() > CAPACITY {
+        return Err(format!(
+            "{} samples will not fit in the signal generator's {}",
+            codes.len(),
+            CAPACITY
+        ));
+    }
+    if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+        return Err(format!(
+            "the signal generator can only play from {}Hz to {}Hz",
+            MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+        ));
+    }
+
+    let mut samples = Vec::with_capacity(2 * codes.len());
+    for code in codes {
+        samples.extend_from_slice(&code.to_le_bytes());
+    }
+    let mut frame = format!("k{}\nu{}\n", sample_rate, codes.len()).into_bytes();
+    frame.extend_from_slice(&samples);
+    frame.extend_from_slice(&fletcher16(&samples).to_le_bytes());
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_matches_the_firmware() {
+        // the firmware's `upload::tests` check the same values
+        assert_eq!(fletcher16(b"abcde"), 0xc8f0);
+        assert_eq!(fletcher16(&[0x00, 0x00, 0x23, 0x01, 0xff, 0x0f]), 0x9e33);
+    }
+
+    #[test]
+    fn frame_has_the_commands_samples_and_checksum() {
+        let bytes = frame(&[0x000, 0x123, 0xfff], 48_000).unwrap();
+        let mut expected = b"k48000\nu3\n".to_vec();
+        expected.extend_from_slice(&[0x00, 0x00, 0x23, 0x01, 0xff, 0x0f, 0x33, 0x9e]);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn frame_rejects_what_the_firmware_cannot_play() {
+        assert!(frame(&[], 48_000).is_err());
+        assert!(frame(&[0x800; CAPACITY + 1], 48_000).is_err());
+        assert!(frame(&[0x800], MIN_SAMPLE_RATE - 1).is_err());
+        assert!(frame(&[0x800], MAX_SAMPLE_RATE + 1).is_err());
+        assert!(frame(&[0x800], MAX_SAMPLE_RATE).is_ok());
+    }
+
+    #[test]
+    fn dac_codes_span_mvpp_around_midscale() {
+        // 3V is the whole 4096-code range
+        let codes = dac_codes(&[-1.0, 0.0, 1.0], 1_500);
+        assert_eq!(codes, [1024, 2048, 3072]);
+        assert_eq!(dac_codes(&[5.0, 5.0], 1_500), [2048, 2048]);
+        assert_eq!(dac_codes(&[-1.0, 1.0], 3_000), [0, 4095]);
+    }
+}